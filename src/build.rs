@@ -0,0 +1,140 @@
+//! Compiles the sources of a file or of a project and writes their
+//! datapack, for `tag compile`.
+
+use crate::{Compiler, CompileOptions, Datapack, Diagnostic, Source};
+use crate::cache::{Cache, CACHE_DIR};
+use crate::datapack::{DirectoryWriter, ZipWriter};
+use crate::project::{Project, ProjectError, MANIFEST_FILE};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+pub const ZIP_EXTENSION: &str = ".zip";
+
+/// Appended to the output path to get the path of the source map.
+pub const SOURCE_MAP_EXTENSION: &str = ".map.json";
+
+/// Changes coming this close to each other are built once by [watch], as an
+/// editor may save a file in several steps.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Everything needed to compile and write a datapack.
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub sources: Vec<Source>,
+    pub options: CompileOptions,
+    pub output: PathBuf,
+    /// Writes the datapack to a zip archive rather than a directory.
+    pub zip: bool,
+    pub icon: Option<Vec<u8>>,
+    /// The files the build is read from, watched by [watch].
+    pub inputs: Vec<PathBuf>,
+    /// The directory of the incremental compilation cache, if any.
+    pub cache: Option<PathBuf>
+}
+
+impl Build {
+    /// The build of `project`, to its output and with its cache.
+    pub fn project(project: &Project) -> Result<Build, ProjectError> {
+        let output = project.output();
+        let mut inputs = vec![project.root.join(MANIFEST_FILE)];
+        inputs.extend(project.entries());
+        inputs.extend(project.manifest.pack.icon.as_ref().map(|icon| project.root.join(icon)));
+
+        Ok(Build {
+            sources: project.read_sources()?,
+            options: project.options()?,
+            zip: output.to_string_lossy().ends_with(ZIP_EXTENSION),
+            output,
+            icon: project.read_icon()?,
+            inputs,
+            cache: Some(project.root.join(CACHE_DIR))
+        })
+    }
+
+    /// Compiles the sources, reusing what the cache kept from the previous
+    /// builds.
+    pub fn compile(&self, compiler: &Compiler) -> Result<Datapack, Vec<Diagnostic>> {
+        let mut cache = match &self.cache {
+            Some(dir) => Cache::new(dir),
+            None => return compiler.compile_sources(&self.sources, self.options.clone())
+        };
+        let datapack = compiler.compile_cached(&self.sources, self.options.clone(), &mut cache)?;
+
+        // Only a successful build drops the entries it didn't use, as a broken
+        // one may not have gotten to them. Entries that can't be removed are
+        // only wasted space.
+        let _ = cache.prune();
+        Ok(datapack)
+    }
+
+    /// Writes `datapack` to the output, with the icon of the build, and its
+    /// source map next to it.
+    pub fn write(&self, mut datapack: Datapack) -> Result<(), String> {
+        datapack.icon = self.icon.clone();
+        let result = if self.zip {
+            ZipWriter::create(&self.output).and_then(|mut writer| datapack.write(&mut writer))
+        } else {
+            datapack.write(&mut DirectoryWriter::new(&self.output))
+        };
+        result.map_err(|err| err.to_string())?;
+
+        let mut source_map_path = self.output.clone().into_os_string();
+        source_map_path.push(SOURCE_MAP_EXTENSION);
+        let source_map = datapack.source_map.to_json(&datapack.namespace, &self.sources);
+        let source_map = serde_json::to_vec_pretty(&source_map).expect("a JSON value can always be serialized");
+        std::fs::write(&source_map_path, source_map)
+            .map_err(|err| format!("can't write `{}`: {}", PathBuf::from(source_map_path).display(), err))
+    }
+}
+
+/// Runs `build` once, then again whenever one of `inputs` changes. It
+/// returns the files to watch after each run: those of a project change
+/// with the entries of its manifest. It only returns if the files can't be
+/// watched anymore.
+pub fn watch<F: FnMut() -> Vec<PathBuf>>(inputs: Vec<PathBuf>, mut build: F) -> notify::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // The directories are watched rather than the files, which editors may
+    // replace when saving them.
+    let mut dirs = BTreeSet::new();
+    watch_inputs(&mut watcher, &mut dirs, &inputs)?;
+
+    // Changes made while building are waiting in `receiver` for the next round.
+    let mut inputs = watch_inputs(&mut watcher, &mut dirs, &build())?;
+    loop {
+        loop {
+            let event = match receiver.recv() {
+                Ok(event) => event?,
+                Err(_) => return Ok(())
+            };
+            if !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|path| inputs.contains(path)) {
+                break;
+            }
+        }
+        while receiver.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+
+        inputs = watch_inputs(&mut watcher, &mut dirs, &build())?;
+    }
+}
+
+/// Makes `watcher` watch the directories of `inputs` instead of `dirs`,
+/// returning the absolute paths of `inputs`, as the events name them.
+fn watch_inputs<W: Watcher>(watcher: &mut W, dirs: &mut BTreeSet<PathBuf>, inputs: &[PathBuf])
+    -> notify::Result<BTreeSet<PathBuf>>
+{
+    let inputs: BTreeSet<_> = inputs.iter().map(std::path::absolute).collect::<Result<_, _>>()?;
+    let watched: BTreeSet<_> = inputs.iter().filter_map(|path| path.parent().map(Path::to_path_buf)).collect();
+
+    for dir in dirs.difference(&watched) {
+        watcher.unwatch(dir)?;
+    }
+    for dir in watched.difference(dirs) {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    *dirs = watched;
+
+    Ok(inputs)
+}
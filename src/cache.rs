@@ -0,0 +1,222 @@
+//! The cache of incremental builds, kept in a [CACHE_DIR] directory.
+//!
+//! It holds the AST of each source, and what each step of
+//! [crate::generator::generate_incremental] changed in the generator, as JSON
+//! files named after the hash of what they were computed from. A build
+//! reuses the entries whose inputs didn't change, and its result is the one
+//! of a clean build: the generator is deterministic, and a step only reads
+//! what its key is computed from.
+//!
+//! The cache is only an optimization. An entry that can't be read is
+//! computed again, and one that can't be written is skipped.
+
+use crate::{parse_source, FileId, Source};
+use crate::errors::Diagnostic;
+use crate::generator::{Checkpoints, Step};
+use crate::parser::AST;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::PathBuf;
+
+pub const CACHE_DIR: &str = ".tag-cache";
+
+/// Entries are only valid for the version of the compiler writing them.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const AST_DIR: &str = "ast";
+const STEP_DIR: &str = "step";
+
+pub struct Cache {
+    dir: PathBuf,
+    /// The entries read or written since the cache was opened.
+    used: HashSet<PathBuf>,
+    hits: usize
+}
+
+impl Cache {
+    /// Opens the cache in `dir`, which is created when needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache { dir: dir.into(), used: HashSet::new(), hits: 0 }
+    }
+
+    /// How many entries the builds found since the cache was opened.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Parses `source`, numbered `file`, unless its AST is cached.
+    pub fn parse(&mut self, source: &Source, file: FileId) -> Result<AST, Diagnostic> {
+        let key = stable_hash(format!("{}\0{}\0{}", VERSION, file, source.text).as_bytes());
+        if let Some(ast) = self.load(AST_DIR, key) {
+            return Ok(ast);
+        }
+
+        let ast = parse_source(&source.text, file)?;
+        self.store(AST_DIR, key, &ast);
+        Ok(ast)
+    }
+
+    /// Removes the entries the builds didn't use since the cache was
+    /// opened, so that it doesn't keep growing.
+    pub fn prune(&self) -> io::Result<()> {
+        for kind in &[AST_DIR, STEP_DIR] {
+            let dir = self.dir.join(kind);
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if !self.used.contains(&path) {
+                    std::fs::remove_file(path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, kind: &str, key: u64) -> PathBuf {
+        self.dir.join(kind).join(format!("{:016x}.json", key))
+    }
+
+    fn load<T: DeserializeOwned>(&mut self, kind: &str, key: u64) -> Option<T> {
+        let path = self.path(kind, key);
+        let value = std::fs::read(&path).ok().and_then(|json| serde_json::from_slice(&json).ok())?;
+        self.used.insert(path);
+        self.hits += 1;
+        Some(value)
+    }
+
+    fn store<T: Serialize>(&mut self, kind: &str, key: u64, value: &T) {
+        let path = self.path(kind, key);
+        let written = serde_json::to_vec(value).ok().is_some_and(|json| {
+            std::fs::create_dir_all(self.dir.join(kind)).is_ok() && std::fs::write(&path, json).is_ok()
+        });
+        if written {
+            self.used.insert(path);
+        }
+    }
+}
+
+impl Checkpoints for Cache {
+    fn load(&mut self, key: u64) -> Option<Step> {
+        Cache::load(self, STEP_DIR, key)
+    }
+
+    fn save(&mut self, key: u64, step: &Step) {
+        self.store(STEP_DIR, key, step)
+    }
+}
+
+/// The key of what `value` is computed from, along with `previous`, the
+/// key of what it depends on.
+pub fn key<T: Serialize>(previous: u64, value: &T) -> u64 {
+    let json = serde_json::to_vec(value).expect("the AST can always be serialized");
+    stable_hash(&[&previous.to_le_bytes()[..], VERSION.as_bytes(), &json].concat())
+}
+
+/// The hash of `value`, like [stable_hash], for the types deriving [Hash].
+pub fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// FNV-1a, which unlike the std hashers is stable across builds.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3));
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Serializes a map as a list of pairs, as JSON objects only have string
+/// keys, e.g. with `#[serde(with = "crate::cache::pairs")]`.
+pub(crate) mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+        where K: Serialize, V: Serialize, S: Serializer
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+        where K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>, D: Deserializer<'de>
+    {
+        Vec::<(K, V)>::deserialize(deserializer).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_sources, CompileOptions, Compiler};
+
+    fn source(name: &str, text: &str) -> Source {
+        Source { name: name.into(), text: text.into() }
+    }
+
+    #[test]
+    fn test_incremental() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = CompileOptions { namespace: "cached".into(), debug: true, ..Default::default() };
+        let compiler = Compiler::new().unwrap();
+        let mut sources = vec![
+            source("a.tag", "def $hello() {\n\t/say hello\n}\ndef $greet(name: string) {\n\t/say #{name}\n}\n$count := 1;\n"),
+            source("b.tag", "$hello();\n$greet(\"b\");\n$total := $count + 1;\n"),
+            source("c.tag", "$greet(\"c\");\n$double := $total * 2;\n")
+        ];
+        // Builds the sources, expecting the result of a clean build and
+        // `hits` entries of the cache to be reused.
+        let build = |sources: &[Source], hits: usize| {
+            let mut cache = Cache::new(dir.path());
+            let datapack = compiler.compile_cached(sources, options.clone(), &mut cache).unwrap();
+            assert_eq!(datapack, compile_sources(sources, options.clone()).unwrap());
+            assert_eq!(cache.hits(), hits);
+            cache.prune().unwrap();
+        };
+
+        build(&sources, 0);
+
+        // The other sources and the function are reused, with their ASTs.
+        sources[2].text = "$greet(\"c\");\n$double := $total * 3;\n".into();
+        build(&sources, 5);
+
+        // Calls only refer to a dynamic function by name: its body is
+        // generated again with its source, but not with the callers.
+        sources[0].text = sources[0].text.replace("/say hello", "/say howdy");
+        build(&sources, 4);
+
+        // A new variable is in scope of the sources after it.
+        sources[0].text += "$other := 2;\n";
+        build(&sources, 3);
+
+        // The cache keeps one entry per source and per step.
+        assert_eq!(std::fs::read_dir(dir.path().join(AST_DIR)).unwrap().count(), 3);
+        assert_eq!(std::fs::read_dir(dir.path().join(STEP_DIR)).unwrap().count(), 4);
+        build(&sources, 7);
+    }
+}
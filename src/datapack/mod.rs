@@ -0,0 +1,244 @@
+pub mod writer;
+pub mod source_map;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use serde_json::json;
+use crate::target::Target;
+use crate::testing::TestSuite;
+use crate::{source_name, source_text, Source};
+
+pub use self::writer::{DatapackWriter, DirectoryWriter, ZipWriter, WriteError};
+pub use self::source_map::SourceMap;
+
+/// A compiled datapack, kept in memory until it is handed to a
+/// [DatapackWriter].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datapack {
+    pub namespace: String,
+    pub meta: PackMeta,
+    /// The Minecraft version the datapack is laid out for.
+    pub target: Target,
+    /// The content of `pack.png`.
+    pub icon: Option<Vec<u8>>,
+    /// The content of each function of the namespace, by name.
+    pub functions: BTreeMap<String, Vec<String>>,
+    /// Function tags (e.g. `minecraft:load`), with the functions they contain.
+    pub function_tags: BTreeMap<String, Vec<String>>,
+    /// Any other JSON file of the datapack, by path relative to `data/`.
+    pub resources: BTreeMap<PathBuf, serde_json::Value>,
+    /// Where the lines of [Datapack::functions] come from. It isn't part of
+    /// the pack itself.
+    pub source_map: SourceMap,
+    /// The tests of the sources, when compiled with [crate::CompileOptions::tests].
+    pub tests: Option<TestSuite>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackMeta {
+    pub pack_format: i32,
+    pub description: String
+}
+
+impl Datapack {
+    pub fn new(namespace: String, meta: PackMeta, target: Target) -> Self {
+        Datapack {
+            namespace,
+            meta,
+            target,
+            icon: None,
+            functions: BTreeMap::new(),
+            function_tags: BTreeMap::new(),
+            resources: BTreeMap::new(),
+            source_map: SourceMap::default(),
+            tests: None
+        }
+    }
+
+    /// Adds `function` (without its namespace) to the function tag `tag`.
+    pub fn tag_function(&mut self, tag: &str, function: &str) {
+        self.function_tags.entry(tag.to_string())
+            .or_default()
+            .push(format!("{}:{}", self.namespace, function));
+    }
+
+    /// Every file of the datapack, with its path relative to the root of
+    /// the pack, in a stable order.
+    pub fn files(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = vec![];
+
+        let pack_mcmeta = json!({
+            "pack": {
+                "pack_format": self.meta.pack_format,
+                "description": self.meta.description
+            }
+        });
+        files.push((PathBuf::from("pack.mcmeta"), to_json(&pack_mcmeta)));
+        if let Some(icon) = &self.icon {
+            files.push((PathBuf::from("pack.png"), icon.clone()));
+        }
+
+        let functions_dir = PathBuf::from("data").join(&self.namespace).join(self.target.functions_dir());
+        for (name, content) in &self.functions {
+            files.push((functions_dir.join(format!("{}.mcfunction", name)), content.join("\n").into_bytes()));
+        }
+
+        for (tag, values) in &self.function_tags {
+            let (namespace, name) = tag.split_once(':').unwrap_or(("minecraft", tag));
+            let path = PathBuf::from("data").join(namespace).join("tags").join(self.target.functions_dir())
+                .join(format!("{}.json", name));
+            files.push((path, to_json(&json!({ "values": values }))));
+        }
+
+        for (path, value) in &self.resources {
+            files.push((PathBuf::from("data").join(path), to_json(value)));
+        }
+
+        files
+    }
+
+    /// Adds a `# <file>:<line>: <statement>` comment before the lines
+    /// generated for each statement, keeping [Datapack::source_map] in sync.
+    pub fn annotate(&mut self, sources: &[Source]) {
+        for (name, lines) in self.functions.iter_mut() {
+            let positions = match self.source_map.functions.get_mut(name) {
+                Some(positions) => positions,
+                None => continue
+            };
+
+            let mut annotated = vec![];
+            let mut annotated_positions = vec![];
+            let mut last = None;
+            for (line, position) in lines.drain(..).zip(positions.drain(..)) {
+                if let Some(pos) = position.as_ref().filter(|_| position != last) {
+                    let statement = source_text(sources, pos.file).lines().nth(pos.line - 1).unwrap_or("");
+                    annotated.push(format!("# {}:{}: {}", source_name(sources, pos.file), pos.line, statement.trim()));
+                    annotated_positions.push(None);
+                }
+                last = position.clone();
+                annotated.push(line);
+                annotated_positions.push(position);
+            }
+
+            *lines = annotated;
+            *positions = annotated_positions;
+        }
+    }
+
+    pub fn write<W: DatapackWriter>(&self, writer: &mut W) -> Result<(), WriteError> {
+        for (path, content) in self.files() {
+            writer.write_file(&path, &content)?;
+        }
+        writer.finish()
+    }
+
+    /// Writes the lines of every function to `out`, like `tag compile --stdout`.
+    pub fn print<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        for (name, lines) in &self.functions {
+            print_function(out, &self.namespace, name, lines)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the lines of the function `name` to `out`, after a header naming it.
+pub fn print_function<W: io::Write>(out: &mut W, namespace: &str, name: &str, lines: &[String]) -> io::Result<()> {
+    writeln!(out, "# function {}:{}", namespace, name)?;
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn to_json(value: &serde_json::Value) -> Vec<u8> {
+    serde_json::to_vec_pretty(value).expect("a JSON value can always be serialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_str, CompileOptions};
+
+    #[test]
+    fn test_files() {
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let datapack = compile_str("/say hello world\n", options).unwrap();
+        assert_eq!(datapack.meta.pack_format, 6);
+        assert!(datapack.functions["0"].contains(&"say hello world".to_string()));
+        assert!(datapack.functions["0"].contains(&"scoreboard objectives add __test__ dummy".to_string()));
+
+        let files: BTreeMap<PathBuf, Vec<u8>> = datapack.files().into_iter().collect();
+        let load = &files[&PathBuf::from("data/minecraft/tags/functions/load.json")];
+        let load: serde_json::Value = serde_json::from_slice(load).unwrap();
+        assert_eq!(load, json!({ "values": ["test:0"] }));
+        assert!(files.contains_key(&PathBuf::from("pack.mcmeta")));
+        assert!(files.contains_key(&PathBuf::from("data/test/functions/0.mcfunction")));
+    }
+
+    #[test]
+    fn test_zip_is_deterministic() {
+        let zip = || {
+            let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+            let datapack = compile_str("$a := 1;\n/say hello world\n", options).unwrap();
+            let mut bytes = vec![];
+            datapack.write(&mut ZipWriter::new(std::io::Cursor::new(&mut bytes))).unwrap();
+            bytes
+        };
+
+        assert_eq!(zip(), zip());
+    }
+
+    #[test]
+    fn test_target() {
+        let src = "$x := 3;\n/say #{$x}\n";
+        let options = |target: &str| CompileOptions {
+            namespace: "test".into(),
+            target: target.parse().unwrap(),
+            ..Default::default()
+        };
+
+        let errors = compile_str(src, options("1.20.1")).unwrap_err();
+        assert_eq!(errors[0].error, "interpolating a dynamic value in a command requires Minecraft 1.20.2 or later");
+
+        let datapack = compile_str(src, options("1.21")).unwrap();
+        assert_eq!(datapack.meta.pack_format, 48);
+        assert_eq!(datapack.functions["1"], vec!["$say $(a0)".to_string()]);
+        let files: BTreeMap<PathBuf, Vec<u8>> = datapack.files().into_iter().collect();
+        assert!(files.contains_key(&PathBuf::from("data/test/function/0.mcfunction")));
+        assert!(files.contains_key(&PathBuf::from("data/minecraft/tags/function/load.json")));
+    }
+
+    #[test]
+    fn test_uninstall() {
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let datapack = compile_str("/scoreboard objectives add kills playerKillCount\n", options).unwrap();
+        assert_eq!(datapack.functions["uninstall"], vec![
+            "scoreboard objectives remove __test__",
+            "scoreboard objectives remove kills",
+            "data remove storage test:runtime stack",
+            "data remove storage test:runtime vars",
+            "data remove storage test:runtime callers",
+            "data remove storage test:runtime condition",
+            "data remove storage test:runtime macro",
+            "data remove storage test:runtime eq",
+            "data remove storage test:runtime probe"
+        ]);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let options = |debug| CompileOptions { namespace: "test".into(), debug, ..Default::default() };
+        let src = "def $f() {\n  /say hi\n}\n$f();\n";
+
+        let release = compile_str(src, options(false)).unwrap();
+        assert!(release.functions.values().flatten().all(|line| !line.starts_with('#') && !line.contains("tellraw")));
+
+        let debug = compile_str(src, options(true)).unwrap();
+        let function = &debug.functions["1"];
+        assert_eq!(function[0], "# <input>:1: def $f() {");
+        assert!(function[1].starts_with("execute if data storage test:runtime {debug:1b} run tellraw @a "));
+        assert!(function.contains(&"# <input>:2: /say hi".to_string()));
+        assert_eq!(debug.source_map.functions["1"].len(), function.len());
+    }
+}
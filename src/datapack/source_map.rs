@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+use serde_json::json;
+use crate::parser::Position;
+use crate::{source_name, Source};
+
+/// Where each line of the generated functions comes from, written as a JSON
+/// sidecar next to the datapack:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "functions": {
+///     "hello:0": [null, { "source": "hello.tag", "line": 1, "column": 1 }]
+///   }
+/// }
+/// ```
+///
+/// The lines of a function are listed in order. Lines that don't come
+/// from any statement (e.g. the ones opening a scope) are `null`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// The position of each line, by function name (without its namespace).
+    pub functions: BTreeMap<String, Vec<Option<Position>>>
+}
+
+impl SourceMap {
+    pub const VERSION: u32 = 1;
+
+    /// The position of the line `line` (starting at 1) of `function`.
+    pub fn get(&self, function: &str, line: usize) -> Option<&Position> {
+        self.functions.get(function)?.get(line.checked_sub(1)?)?.as_ref()
+    }
+
+    /// The JSON sidecar, with the file names taken from `sources`.
+    pub fn to_json(&self, namespace: &str, sources: &[Source]) -> serde_json::Value {
+        let functions: serde_json::Map<String, serde_json::Value> = self.functions.iter()
+            .map(|(name, lines)| {
+                let lines: Vec<serde_json::Value> = lines.iter().map(|position| match position {
+                    Some(position) => json!({
+                        "source": source_name(sources, position.file),
+                        "line": position.line,
+                        "column": position.column
+                    }),
+                    None => serde_json::Value::Null
+                }).collect();
+                (format!("{}:{}", namespace, name), lines.into())
+            })
+            .collect();
+
+        json!({ "version": Self::VERSION, "functions": functions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, CompileOptions};
+
+    #[test]
+    fn test_source_map() {
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let datapack = compile_str("$a := 1;\n/say hello\n", options).unwrap();
+        let source_map = &datapack.source_map;
+
+        let line = |content: &str| datapack.functions["0"].iter().position(|line| line == content).unwrap() + 1;
+        assert_eq!(source_map.get("0", line("say hello")).map(|pos| (pos.line, pos.column)), Some((2, 1)));
+        assert_eq!(source_map.get("0", line("data remove storage test:runtime stack[-1]")).unwrap().line, 1);
+        assert_eq!(source_map.get("0", 1), None);
+        assert_eq!(source_map.functions["0"].len(), datapack.functions["0"].len());
+    }
+}
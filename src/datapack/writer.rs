@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf, Component};
+use std::io::{Write, Seek};
+use std::{fmt, fs, io};
+use zip::write::FileOptions;
+use zip::DateTime;
+
+/// Serializes the files of a [Datapack](super::Datapack) somewhere.
+pub trait DatapackWriter {
+    /// Writes a single file, `path` being relative to the root of the pack.
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> Result<(), WriteError>;
+
+    /// Called once every file has been written.
+    fn finish(&mut self) -> Result<(), WriteError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct WriteError {
+    pub path: PathBuf,
+    pub error: io::Error
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.as_os_str().is_empty() {
+            write!(f, "can't write the datapack: {}", self.error)
+        } else {
+            write!(f, "can't write `{}`: {}", self.path.display(), self.error)
+        }
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The file listing what the compiler wrote in an output directory.
+pub const MANIFEST: &str = ".tag-manifest";
+
+/// Writes the datapack as a folder, as found in `.minecraft/saves/<world>/datapacks`.
+///
+/// The files it writes are listed in a [MANIFEST], so that the files written
+/// by a previous compilation and not generated anymore get removed, while
+/// the files added by hand are left alone.
+pub struct DirectoryWriter {
+    root: PathBuf,
+    written: Vec<PathBuf>
+}
+
+impl DirectoryWriter {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DirectoryWriter { root: root.into(), written: vec![] }
+    }
+
+    fn read_manifest(&self) -> Result<Vec<PathBuf>, WriteError> {
+        let path = self.root.join(MANIFEST);
+        match fs::read_to_string(&path) {
+            // Only relative paths staying inside the output directory are
+            // trusted, in case the manifest has been tampered with.
+            Ok(manifest) => Ok(manifest.lines()
+                .map(PathBuf::from)
+                .filter(|path| path.components().all(|c| matches!(c, Component::Normal(_))))
+                .collect()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(WriteError { path, error })
+        }
+    }
+
+    fn remove_stale_file(&self, path: &Path) -> Result<(), WriteError> {
+        let full_path = self.root.join(path);
+        match fs::remove_file(&full_path) {
+            Ok(()) => {},
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+            Err(error) => return Err(WriteError { path: full_path, error })
+        }
+
+        // Also remove the directories left empty, e.g. `data/<old namespace>`.
+        for dir in path.ancestors().skip(1).take_while(|dir| !dir.as_os_str().is_empty()) {
+            if fs::remove_dir(self.root.join(dir)).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DatapackWriter for DirectoryWriter {
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> Result<(), WriteError> {
+        self.written.push(path.to_path_buf());
+
+        let path = self.root.join(path);
+        let error = |error| WriteError { path: path.clone(), error };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(error)?;
+        }
+        fs::write(&path, content).map_err(error)
+    }
+
+    fn finish(&mut self) -> Result<(), WriteError> {
+        for path in self.read_manifest()? {
+            if !self.written.contains(&path) {
+                self.remove_stale_file(&path)?;
+            }
+        }
+
+        let manifest: Vec<String> = self.written.iter()
+            .map(|path| path.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/"))
+            .collect();
+        let path = self.root.join(MANIFEST);
+        fs::create_dir_all(&self.root)
+            .and_then(|_| fs::write(&path, manifest.join("\n") + "\n"))
+            .map_err(|error| WriteError { path, error })
+    }
+}
+
+/// Writes the datapack as a `.zip` archive, with `pack.mcmeta` at its root.
+/// Every entry gets the same timestamp and permissions, so that the same
+/// datapack always gives a byte-identical archive.
+pub struct ZipWriter<W: Write + Seek> {
+    zip: zip::ZipWriter<W>
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ZipWriter { zip: zip::ZipWriter::new(inner) }
+    }
+
+}
+
+impl ZipWriter<fs::File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, WriteError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| WriteError { path: parent.to_path_buf(), error })?;
+        }
+        let file = fs::File::create(path).map_err(|error| WriteError { path: path.to_path_buf(), error })?;
+        Ok(ZipWriter::new(file))
+    }
+}
+
+impl<W: Write + Seek> DatapackWriter for ZipWriter<W> {
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> Result<(), WriteError> {
+        let error = |error: io::Error| WriteError { path: path.to_path_buf(), error };
+        let options = FileOptions::default()
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644);
+
+        // Zip entries always use `/`, whatever the platform is.
+        let name: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+        self.zip.start_file(name.join("/"), options).map_err(|err| error(err.into()))?;
+        self.zip.write_all(content).map_err(error)
+    }
+
+    fn finish(&mut self) -> Result<(), WriteError> {
+        self.zip.finish()
+            .map(|_| ())
+            .map_err(|err| WriteError { path: PathBuf::new(), error: err.into() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        let mut writer = DirectoryWriter::new(root);
+        writer.write_file("data/a/functions/0.mcfunction".as_ref(), b"say 0").unwrap();
+        writer.write_file("data/a/functions/1.mcfunction".as_ref(), b"say 1").unwrap();
+        writer.finish().unwrap();
+        fs::write(root.join("data/a/functions/mine.mcfunction"), "say mine").unwrap();
+
+        let mut writer = DirectoryWriter::new(root);
+        writer.write_file("data/b/functions/0.mcfunction".as_ref(), b"say 0").unwrap();
+        writer.finish().unwrap();
+
+        assert!(root.join("data/b/functions/0.mcfunction").exists());
+        assert!(!root.join("data/a/functions/0.mcfunction").exists());
+        assert!(!root.join("data/a/functions/1.mcfunction").exists());
+        assert!(root.join("data/a/functions/mine.mcfunction").exists());
+        assert_eq!(fs::read_to_string(root.join(MANIFEST)).unwrap(), "data/b/functions/0.mcfunction\n");
+    }
+}
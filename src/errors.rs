@@ -1,14 +1,17 @@
-use nom_greedyerror::GreedyErrorKind;
 use nom::error::{ParseError, ErrorKind};
 use crate::parser::{Position, Span};
-use nom::Parser;
+use crate::{source_location, source_text, Source};
 use std::fmt;
-use nom_locate::position;
+use std::io::{self, Write};
+
+/// The name under which errors are exposed by the library.
+pub type Diagnostic = CompilerError;
 
 #[derive(Debug)]
 pub struct CompilerError {
     pub error: String,
-    pub position: Position
+    pub position: Position,
+    pub notes: Vec<String>
 }
 
 impl<'a, S: ToString> From<(Span<'a>, S)> for CompilerError {
@@ -17,12 +20,12 @@ impl<'a, S: ToString> From<(Span<'a>, S)> for CompilerError {
     }
 }
 
-impl<'a, S: ToString> From<(Position, S)> for CompilerError {
+impl<S: ToString> From<(Position, S)> for CompilerError {
     fn from(from: (Position, S)) -> Self {
         let position = from.0;
         let error: String = from.1.to_string();
 
-        CompilerError { error, position }
+        CompilerError { error, position, notes: vec![] }
     }
 }
 
@@ -35,16 +38,30 @@ impl CompilerError {
         CompilerError::fail(pos, "syntax error")
     }
 
-    pub fn format(&self, src: &str) {
+    /// Attaches an extra line of explanation, printed below the error
+    /// (e.g. `help: did you mean `foo`?`).
+    pub fn with_note<S: ToString>(mut self, note: S) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Shows the error under the lines of `src` around it, highlighted with
+    /// ANSI colours if `color` is set.
+    pub fn format(&self, src: &str, color: bool) -> String {
         use termion::{color, color::Fg, style};
 
         const OFFSET: i32 = 2;
 
+        let paint = |code: String| if color { code } else { String::new() };
+        let (red, light_red) = (paint(Fg(color::Red).to_string()), paint(Fg(color::LightRed).to_string()));
+        let (bold, reset) = (paint(style::Bold.to_string()), paint(style::Reset.to_string()));
+        let reset_fg = paint(Fg(color::Reset).to_string());
+
         let lines: Vec<_> = src.split("\n").collect();
         let line = self.position.line as i32;
         let lines_nb: Vec<_> = (OFFSET-3..OFFSET+2)
             .map(|off| off+line-1)
-            .filter_map(|i| if i <= 0 || lines.len() <= i as usize { None } else { Some(i) })
+            .filter(|i| *i > 0 && (*i as usize) < lines.len())
             .collect();
         let margin = lines_nb.iter()
             .map(|i| i.to_string().len())
@@ -54,32 +71,55 @@ impl CompilerError {
         for i in lines_nb {
             out.push(format!(
                 "{}{: >margin$} |{} {}",
-                Fg(color::Red), i, Fg(color::Reset),
+                red, i, reset_fg,
                 lines[(i - 1) as usize],
                 margin = margin));
 
             if i == self.position.line as i32 {
                 out.push(format!(
                     "{}{: >margin$} ){}{: >column$}{}{}",
-                    Fg(color::Red), " ", Fg(color::LightRed), "^", "here", style::Reset,
+                    red, " ", light_red, "^", "here", reset,
                     margin = margin, column = self.position.column + 1))
             }
         }
         out.push(format!("{}{}error{}: {}",
-                         style::Bold, Fg(color::Red), style::Reset,
+                         bold, red, reset,
                          self.error));
+        for note in &self.notes {
+            out.push(format!("{: >margin$} = {}", " ", note, margin = margin));
+        }
+
+        out.join("\n")
+    }
+}
+
+/// Writes `errors` to `out`, each after the location it points to in `sources`.
+/// See [CompilerError::format] for `color`.
+pub fn report<W: Write>(out: &mut W, errors: &[Diagnostic], sources: &[Source], color: bool) -> io::Result<()> {
+    for err in errors {
+        let text = source_text(sources, err.position.file);
+        writeln!(out, "--> {}", source_location(sources, &err.position))?;
+        writeln!(out, "{}", err.format(text, color))?;
+    }
+    Ok(())
+}
 
-        println!("{}", out.join("\n"));
+impl fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.position.line, self.position.column, self.error)?;
+        for note in &self.notes {
+            write!(f, "\n  = {}", note)?;
+        }
+        Ok(())
     }
 }
 
 impl ParseError<Span<'_>> for CompilerError {
-    fn from_error_kind(input: Span, kind: ErrorKind) -> Self {
+    fn from_error_kind(input: Span, _kind: ErrorKind) -> Self {
         CompilerError {
-            error: match kind {
-                _ => "invalid syntax"
-            }.into(),
-            position: input.into()
+            error: "invalid syntax".into(),
+            position: input.into(),
+            notes: vec![]
         }
     }
 
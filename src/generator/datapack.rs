@@ -1,35 +1,60 @@
-use crate::generator::Generator;
+use crate::generator::{Generator, CALLERS, DEBUG_FLAG, FAILURES};
 use crate::parser::expression::VariableName;
 use crate::generator::simplify::Simplify;
 use crate::errors::CompilerError;
-use crate::CompileOptions;
+use crate::datapack::{Datapack, PackMeta};
+
+/// The function tag ran by Minecraft when the datapack gets loaded.
+const LOAD_TAG: &str = "minecraft:load";
+
+/// The function removing the objectives and storage of the datapack.
+const UNINSTALL_FUNCTION: &str = "uninstall";
+
+/// The paths of the runtime storage written by the generated code.
+const RUNTIME_PATHS: &[&str] = &["stack", "vars", CALLERS, "condition", "macro", "eq", "probe"];
 
 impl Generator {
-    pub fn write_datapack(&mut self, options: CompileOptions) -> Result<(), CompilerError> {
-        let pack_mcmeta = self.generate_pack_mcmeta()?;
-        let functions_dir = options.outdir
-            .join("data")
-            .join(options.namespace)
-            .join("functions");
+    pub fn into_datapack(mut self) -> Result<Datapack, CompilerError> {
+        let meta = self.generate_pack_meta()?;
         self.pop_scope();
-        std::fs::create_dir_all(&functions_dir);
-        std::fs::write(options.outdir.join("pack.mcmeta"), pack_mcmeta);
-        for (name, content) in &self.files {
-            std::fs::write(functions_dir.join(format!("{}.mcfunction", name)), content.join("\n"));
+
+        let uninstall = self.generate_uninstall();
+        let mut datapack = Datapack::new(self.namespace, meta, self.target);
+        for (name, lines) in self.files {
+            let (lines, positions) = lines.into_iter().unzip();
+            datapack.functions.insert(name.clone(), lines);
+            datapack.source_map.functions.insert(name, positions);
         }
+        datapack.source_map.functions.insert(UNINSTALL_FUNCTION.to_string(), vec![None; uninstall.len()]);
+        datapack.functions.insert(UNINSTALL_FUNCTION.to_string(), uninstall);
+        datapack.tag_function(LOAD_TAG, "0");
+        datapack.tests = self.tests;
 
-        Ok(())
+        Ok(datapack)
     }
 
-    fn generate_pack_mcmeta(&self) -> Result<String, CompilerError> {
-        let pack_format: String
-            = self.get_static_variable_value(&VariableName::Static("pack_format".into()))
-            .expect("variable `pack_format` isn't set to any value")
-            .to_string(self)?;
-        let pack_description: String
-            = self.get_static_variable_value(&VariableName::Static("pack_description".into()))
-            .expect("variable `pack_description` isn't set to any value")
-            .to_string(self)?;
-        Ok(format!(include_str!("../data/pack.mcmeta"), pack_format, pack_description))
+    fn generate_uninstall(&self) -> Vec<String> {
+        let objectives = self.objectives.iter()
+            .map(|objective| format!("scoreboard objectives remove {}", objective));
+        let debug_paths = if self.debug { &[DEBUG_FLAG][..] } else { &[] };
+        let test_paths = if self.tests.is_some() { &[FAILURES][..] } else { &[] };
+        let paths = RUNTIME_PATHS.iter().chain(debug_paths).chain(test_paths)
+            .map(|path| format!("data remove storage {} {}", self.storage, path));
+
+        objectives.chain(paths).collect()
+    }
+
+    fn generate_pack_meta(&self) -> Result<PackMeta, CompilerError> {
+        let pack_format = self.get_static_variable_value(&VariableName::Static("pack_format".into()))
+            .expect("variable `pack_format` isn't set to any value");
+        let pack_description = self.get_static_variable_value(&VariableName::Static("pack_description".into()))
+            .expect("variable `pack_description` isn't set to any value");
+
+        Ok(PackMeta {
+            pack_format: pack_format.simplify(self)
+                .map_err(|err| CompilerError::from((pack_format.pos().clone(), err)))?,
+            description: pack_description.simplify(self)
+                .map_err(|err| CompilerError::from((pack_description.pos().clone(), err)))?
+        })
     }
 }
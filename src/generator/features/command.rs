@@ -1,19 +1,155 @@
 use crate::generator::Generator;
 use crate::parser::statement::Command;
-use crate::generator::simplify::Simplify;
+use crate::generator::staticness::IsStatic;
 use crate::errors::CompilerError;
+use crate::parser::typing::Typing;
+use crate::target::Feature;
 
 impl Generator {
     pub fn generate_command(&mut self, cmd: Command) -> Result<(), CompilerError> {
+        if cmd.is_dynamic() {
+            return self.generate_macro_command(cmd);
+        }
+
         let mut start: Vec<String> = vec![];
         for (string, expr) in cmd.start.clone() {
-            let to_string: String = expr.to_string(&self)?;
+            let to_string: String = expr.to_string(self)?;
             start.push(string + to_string.as_str());
         }
 
         let interpolated = start.join("") + cmd.end.as_str();
+        self.record_objective(&interpolated);
         self.write(interpolated);
 
         Ok(())
     }
+
+    /// Dynamic values are interpolated with a function macro: each of them
+    /// is copied to the `macro` compound, which the function running the
+    /// command is then called with.
+    ///
+    /// They are written like static values are by [Expression::to_string]:
+    /// strings are quoted, and booleans, stored as bytes, are `true` or
+    /// `false`.
+    fn generate_macro_command(&mut self, cmd: Command) -> Result<(), CompilerError> {
+        self.require(Feature::Macros, &cmd.position)?;
+
+        self.write(format!("data modify storage {} macro set value {{}}", self.storage));
+        let mut line = String::from("$");
+        for (i, (string, expr)) in cmd.start.into_iter().enumerate() {
+            line += string.as_str();
+            if expr.is_static() {
+                line += expr.to_string(self)?.as_str();
+                continue;
+            }
+
+            let typing = self.get_typing(&expr);
+            self.generate_expression(expr)?;
+            line += self.generate_macro_argument(&format!("a{}", i), typing).as_str();
+            self.generate_pop_expression();
+        }
+        line += cmd.end.as_str();
+        self.record_objective(&line);
+
+        let name = self.push_file();
+        self.write(line);
+        self.pop_file();
+        self.write(format!("function {}:{} with storage {} macro", self.namespace, name, self.storage));
+
+        Ok(())
+    }
+
+    /// Copies the value on top of the stack to the argument `arg` of the
+    /// macro, giving how the macro line reads it.
+    fn generate_macro_argument(&mut self, arg: &str, typing: Typing) -> String {
+        let set = |ctx: &Self, value: &str| format!("data modify storage {} macro.{} set value {}", ctx.storage, arg, value);
+        let if_score = |ctx: &Self| format!("execute if score %a {} matches 1", ctx.objective);
+
+        match typing {
+            Typing::Integer => {
+                self.write(format!("data modify storage {0} macro.{1} set from storage {0} stack[-1].@", self.storage, arg));
+                format!("$({})", arg)
+            },
+            Typing::String => {
+                self.write(format!("data modify storage {0} macro.{1} set from storage {0} stack[-1].@", self.storage, arg));
+                format!("\"$({})\"", arg)
+            },
+            Typing::Boolean => {
+                self.write(set(self, "\"false\""));
+                self.write(format!("execute store result score %a {} run data get storage {} stack[-1].@", self.objective, self.storage));
+                self.write(format!("{} run {}", if_score(self), set(self, "\"true\"")));
+                format!("$({})", arg)
+            },
+            Typing::Unknown => {
+                // The type is found at runtime, by appending the value to
+                // lists of strings and of bytes: lists only hold values of a
+                // single type (until 1.21.5, past the latest target), so at
+                // most one of them succeeds. The quotes of strings are then
+                // given by another argument.
+                let probe = |ctx: &mut Self, list: &str| {
+                    ctx.write(format!("data modify storage {} probe set value {}", ctx.storage, list));
+                    ctx.write(format!("execute store success score %a {1} run data modify storage {0} probe append from storage {0} stack[-1].@",
+                                      ctx.storage, ctx.objective));
+                };
+                self.write(format!("data modify storage {0} macro.{1} set from storage {0} stack[-1].@", self.storage, arg));
+                self.write(format!("data modify storage {} macro.{}q set value \"\"", self.storage, arg));
+
+                probe(self, "[\"\"]");
+                self.write(format!("{} run data modify storage {} macro.{}q set value \"\\\"\"", if_score(self), self.storage, arg));
+
+                probe(self, "[0b]");
+                self.write(format!("{} run {}", if_score(self), set(self, "\"false\"")));
+                self.write(format!("{} store result score %a {} run data get storage {} stack[-1].@",
+                                   if_score(self), self.objective, self.storage));
+                self.write(format!("{} run {}", if_score(self), set(self, "\"true\"")));
+                format!("$({0}q)$({0})$({0}q)", arg)
+            }
+        }
+    }
+
+    /// Remembers the objective added by `command`, if any, so that the
+    /// uninstall function removes it. Names given by a macro argument
+    /// can't be known.
+    fn record_objective(&mut self, command: &str) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let objective = words.windows(4)
+            .find(|words| words[..3] == ["scoreboard", "objectives", "add"])
+            .map(|words| words[3]);
+
+        if let Some(objective) = objective.filter(|objective| !objective.contains("$(")) {
+            self.objectives.insert(objective.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, CompileOptions};
+    use crate::simulator::Simulator;
+
+    fn chat(src: &str) -> Vec<String> {
+        let options = CompileOptions { namespace: "test".into(), target: "1.20.4".parse().unwrap(), ..Default::default() };
+        let datapack = compile_str(src, options).unwrap();
+        let mut simulator = Simulator::new(&datapack);
+        simulator.load().unwrap();
+        simulator.chat
+    }
+
+    #[test]
+    fn test_interpolation() {
+        // Dynamic values are written like static ones, whether their type
+        // is known when compiling or only at runtime.
+        let src = "$b := true;\n$s := \"hi\";\n$n := 3;\n\
+                   def $show($v) {\n  /say #{$v}\n}\n\
+                   /say #{false} #{\"hi\"} #{3}\n\
+                   /say #{$b == false} #{$s} #{$n}\n\
+                   $show(false);\n$show($s);\n$show($n * 2);\n";
+        assert_eq!(chat(src), [
+            "false \"hi\" 3",
+            "false \"hi\" 3",
+            "false",
+            "\"hi\"",
+            "6"
+        ]);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::generator::Generator;
+use crate::generator::{Generator, nbt_bool};
 use crate::parser::statement::IfStatement;
 use crate::errors::CompilerError;
 
@@ -6,24 +6,30 @@ impl Generator {
     pub fn generate_if_statement(&mut self, if_stmt: IfStatement) -> Result<(), CompilerError> {
         self.generate_expression(if_stmt.expr)?;
 
-        self.write("data modify storage tag:runtime condition set from storage tag:runtime stack[-1].@");
+        self.write(format!("data modify storage {0} condition set from storage {0} stack[-1].@", self.storage));
 
         let fn_name = self.push_file();
         self.generate_scoped_statements(if_stmt.block)?;
         self.pop_file();
 
-        self.write(format!("execute if data storage tag:runtime {{condition:true}} run function {}:{}", self.namespace, fn_name));
+        self.write(format!("execute if data storage {} {{condition:{}}} run function {}:{}", self.storage, nbt_bool(true), self.namespace, fn_name));
+
+        // The block may have run other conditions, so the condition is read
+        // again from the stack, where it is kept until the end.
+        if if_stmt.else_if.is_some() || if_stmt.else_block.is_some() {
+            self.write(format!("data modify storage {0} condition set from storage {0} stack[-1].@", self.storage));
+        }
 
         if let Some(else_if) = *if_stmt.else_if {
             let name = self.push_file();
             self.generate_if_statement(else_if)?;
             self.pop_file();
-            self.write(format!("execute unless data storage tag:runtime {{condition:true}} run function {}:{}", self.namespace, name));
+            self.write(format!("execute unless data storage {} {{condition:{}}} run function {}:{}", self.storage, nbt_bool(true), self.namespace, name));
         } else if let Some(else_block) = if_stmt.else_block {
             let name = self.push_file();
             self.generate_scoped_statements(else_block)?;
             self.pop_file();
-            self.write(format!("execute unless data storage tag:runtime {{condition:true}} run function {}:{}", self.namespace, name));
+            self.write(format!("execute unless data storage {} {{condition:{}}} run function {}:{}", self.storage, nbt_bool(true), self.namespace, name));
         }
 
         self.generate_pop_expression();
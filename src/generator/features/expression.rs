@@ -1,51 +1,95 @@
 use crate::parser::expression::Term;
 use crate::parser::expression::Summand;
-use crate::generator::Generator;
+use crate::generator::{Generator, nbt_bool};
 use crate::parser::expression::Expression;
 use crate::generator::staticness::IsStatic;
-use crate::generator::simplify::Simplify;
 use crate::errors::CompilerError;
+use crate::parser::typing::Typing;
+use crate::parser::Position;
 
 impl Generator {
     pub fn generate_expression(&mut self, expr: Expression) -> Result<(), CompilerError> {
         if expr.is_static() {
-            let value: String = expr.to_string(self)?;
+            let value: String = expr.to_nbt(self)?;
             self.generate_push_to_stack(value);
             return Ok(());
         }
 
+        let typing = self.get_typing(&expr);
         match expr {
             Expression::Boolean(bl, _) => {
-                self.generate_push_to_stack(bl);
+                self.generate_push_to_stack(nbt_bool(bl));
             },
-            Expression::Sum(summand, expr, _) => {
-                self.generate_summand(summand)?;
-                self.generate_expression(*expr)?;
-                self.write("execute store result score %a __tag__ run data get storage tag:runtime stack[-1].@");
-                self.write("execute store result score %b __tag__ run data get storage tag:runtime stack[-2].@");
+            Expression::Sum(mut operands, pos) => {
+                // Integers are added on the scoreboard, but there is no way
+                // to concatenate strings stored in NBT at runtime.
+                if typing == Typing::String {
+                    return Err((pos, "can't concatenate dynamic strings").into());
+                }
 
-                self.generate_pop_expression();
+                // The static operands ending the sum are added at compile
+                // time, and pushed as one.
+                let dynamic = operands.iter().rposition(Expression::is_dynamic).expect("the sum is dynamic");
+                if operands.len() - dynamic > 2 {
+                    let rest = operands.split_off(dynamic + 1);
+                    let pos = rest[0].pos().clone();
+                    operands.push(Expression::Sum(rest, pos));
+                }
 
-                self.write("execute store result storage tag:runtime stack[-1].@ int 1 run scoreboard players operation %a __tag__ *= %b __tag__");
+                let count = operands.len();
+                for operand in operands {
+                    self.generate_expression(operand)?;
+                }
+                for _ in 1..count {
+                    self.generate_operation("+=");
+                }
             },
-            Expression::Summand(summand, _) => self.generate_summand(summand)?,
-            _ => todo!()
+            Expression::Summand(summand, pos) => self.generate_summand(summand, &pos)?,
+            Expression::Equality(left, right, _) => {
+                self.generate_expression(*left)?;
+                self.generate_expression(*right)?;
+
+                // Setting a value to the one it already has fails, so this
+                // succeeds only if both sides differ.
+                self.write(format!("data modify storage {0} eq set from storage {0} stack[-2].@", self.storage));
+                self.write(format!("execute store success score %a {1} run data modify storage {0} eq set from storage {0} stack[-1].@", self.storage, self.objective));
+
+                self.generate_pop_expression();
+
+                self.write(format!("execute store result storage {} stack[-1].@ byte 1 if score %a {} matches 0", self.storage, self.objective));
+            }
         };
 
         Ok(())
     }
 
-    pub fn generate_summand(&mut self, summand: Summand) -> Result<(), CompilerError> {
-        match summand {
-            Summand::Multiplication(term, summand) => {
-                self.generate_term(term)?;
-                self.generate_summand(*summand)?;
-                self.write("execute result store score %a __tag__ run data get storage tag:runtime stack[-1].@");
-                self.write("execute result store score %b __tag__ run data get storage tag:runtime stack[-2].@");
+    /// Summands don't have a position: `pos` is the one of the expression
+    /// they are part of.
+    pub fn generate_summand(&mut self, summand: Summand, pos: &Position) -> Result<(), CompilerError> {
+        // The static operands of a dynamic expression are computed at
+        // compile time, as they have no runtime variable to be read from.
+        if summand.is_static() {
+            let value = Expression::Summand(summand, pos.clone()).to_nbt(self)?;
+            self.generate_push_to_stack(value);
+            return Ok(());
+        }
 
-                self.generate_pop_expression();
+        match summand {
+            Summand::Multiplication(mut terms) => {
+                // Like sums, the static terms ending the product are pushed as one.
+                let dynamic = terms.iter().rposition(Term::is_dynamic).expect("the product is dynamic");
+                if terms.len() - dynamic > 2 {
+                    let rest = terms.split_off(dynamic + 1);
+                    terms.push(Term::Expression(Box::new(Expression::Summand(Summand::Multiplication(rest), pos.clone()))));
+                }
 
-                self.write("execute store result storage tag:runtime stack[-1].@ int 1 run scoreboard players operation %a __tag__ *= %b __tag__");
+                let count = terms.len();
+                for term in terms {
+                    self.generate_summand(Summand::Term(term), pos)?;
+                }
+                for _ in 1..count {
+                    self.generate_operation("*=");
+                }
 
                 Ok(())
             },
@@ -57,8 +101,8 @@ impl Generator {
         match term {
             Term::Number(n) => self.generate_push_to_stack(n),
             Term::Expression(expr) => self.generate_expression(*expr)?,
-            Term::FunctionCall(_call) => unimplemented!(),
-            Term::Variable(var) => {
+            Term::FunctionCall(call) => self.generate_function_result(call)?,
+            Term::Variable(var, position) => {
                 // if var.is_static() {
                 //     let value = Expression::Summand(
                 //         Summand::Term(
@@ -68,9 +112,9 @@ impl Generator {
                 //     self.write(format!("data modify storage tag:runtime stack append value {}",
                 //         <Expression as Simplify<String>>::simplify(&value, self).unwrap()));
                 // } else {
-                    let path = self.get_variable_nbt_path(&var);
-                    self.write("data modify storage tag:runtime stack append value {}");
-                    self.write(format!("data modify storage tag:runtime stack[-1].@ set from storage tag:runtime {}", path))
+                    let path = self.get_variable_nbt_path(&var, &position)?;
+                    self.write(format!("data modify storage {} stack append value {{}}", self.storage));
+                    self.write(format!("data modify storage {0} stack[-1].@ set from storage {0} {1}", self.storage, path))
                 // }
             },
             Term::String(str) => self.generate_push_to_stack(str)
@@ -79,7 +123,61 @@ impl Generator {
         Ok(())
     }
 
+    /// Replaces the two values on top of the stack by the result of
+    /// `operation` on them, the top one being its left operand.
+    fn generate_operation(&mut self, operation: &str) {
+        self.write(format!("execute store result score %a {} run data get storage {} stack[-1].@", self.objective, self.storage));
+        self.write(format!("execute store result score %b {} run data get storage {} stack[-2].@", self.objective, self.storage));
+
+        self.generate_pop_expression();
+
+        self.write(format!("execute store result storage {1} stack[-1].@ int 1 run scoreboard players operation %a {0} {2} %b {0}", self.objective, self.storage, operation));
+    }
+
     pub fn generate_pop_expression(&mut self) {
-        self.write(format!("data remove storage tag:runtime stack[-1]"));
+        self.write(format!("data remove storage {} stack[-1]", self.storage));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, CompileOptions};
+    use crate::simulator::Simulator;
+
+    #[test]
+    fn test_booleans() {
+        // Booleans are stored as bytes, whether they are assigned, compared
+        // or passed to a function.
+        let src = "$b := true;\n$c := $b == false;\n\
+                   def $check($x: bool) {\n  if $x {\n    /say yes\n  } else {\n    /say no\n  }\n}\n\
+                   $check($b);\n$check($c);\n$check(false);\n";
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let datapack = compile_str(src, options).unwrap();
+
+        let lines: Vec<&String> = datapack.functions.values().flatten().collect();
+        assert!(lines.iter().any(|line| line.ends_with("stack[-1].@ set value 1b")), "{:?}", lines);
+        assert!(lines.iter().any(|line| line.ends_with("stack[-1].@ set value 0b")), "{:?}", lines);
+        assert!(lines.iter().any(|line| line.ends_with("stack[-1].@ byte 1 if score %a __test__ matches 0")), "{:?}", lines);
+        assert!(!lines.iter().any(|line| line.contains("true") || line.contains("false")), "{:?}", lines);
+
+        let mut simulator = Simulator::new(&datapack);
+        simulator.load().unwrap();
+        assert_eq!(simulator.chat, ["yes", "no", "no"]);
+    }
+
+    #[test]
+    fn test_overflow() {
+        // Integers wrap around on overflow, whether they are computed at
+        // compile time or on the scoreboard, rather than failing the build.
+        let src = "max := 2147483647;\n$max := 2147483647;\n$sum := max + 1;\n$product := max * 2;\n\
+                   $dynamic_sum := $max + 1;\n$dynamic_product := $max * 2;\n\
+                   /say #{max + 1} #{max * 2}\n\
+                   if $sum == $dynamic_sum {\n  /say sums\n}\nif $product == $dynamic_product {\n  /say products\n}\n";
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let datapack = compile_str(src, options).unwrap();
+
+        let mut simulator = Simulator::new(&datapack);
+        simulator.load().unwrap();
+        assert_eq!(simulator.chat, ["-2147483648 -2", "sums", "products"]);
     }
 }
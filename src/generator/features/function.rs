@@ -1,11 +1,11 @@
 use crate::parser::statement::VariableSignature;
-use crate::parser::statement::VariableAssignment;
-use crate::parser::function::FunctionSignature;
 use crate::parser::function::FunctionCall;
 use crate::parser::function::Function;
-use crate::generator::Generator;
+use crate::generator::{Generator, nbt_bool, CALLERS, DEBUG_FLAG, MAX_DEPTH};
+use serde_json::json;
 use crate::generator::staticness::IsStatic;
 use crate::errors::CompilerError;
+use crate::parser::typing::Typing;
 
 impl Generator {
     pub fn generate_function(&mut self, function: Function) -> Result<(), CompilerError> {
@@ -13,18 +13,20 @@ impl Generator {
 
         let requires_scope = Self::requires_scope(&function.block)
             || !function.signature.get_dynamic_args().is_empty();
+        self.generate_trace(&function);
         if requires_scope { self.push_scope(); }
 
-        for sign in &function.signature.get_dynamic_args() {
+        // The arguments are pushed in order, so the last one is on top.
+        for sign in function.signature.get_dynamic_args().into_iter().rev() {
             self.register_runtime_variable(sign);
-            self.write(format!(
-                "data modify storage tag:runtime vars[-1].\"{}\" set from storage tag:runtime stack[-1].@",
-                sign.name.get_name()
-            ));
-            self.generate_pop_expression();
+            self.generate_pop_to_variable(&sign.name);
         }
 
         self.generate_statements(function.block)?;
+        if let Some(result) = function.result {
+            let position = result.position.clone();
+            self.at(&position, |ctx| ctx.generate_expression(result.expr))?;
+        }
         if requires_scope { self.pop_scope(); }
         self.pop_file();
 
@@ -32,65 +34,194 @@ impl Generator {
     }
 
     pub fn generate_function_call(&mut self, function_call: FunctionCall) -> Result<(), CompilerError> {
-        if let Some(info) = self.resolve_function_call(&function_call) {
-            let (func, file_name) = info.clone();
-            let args: Vec<_> = func.signature.args.iter().zip(function_call.args).collect();
-            let static_args: Vec<_> = args.iter().filter(|(sign, _)| sign.is_static()).collect();
-            let dyn_args: Vec<_> = args.iter().filter(|(sign, _)| sign.is_dynamic()).collect();
-            
-            for (sign, expr) in &static_args {
-                self.assign_static_variable(VariableAssignment {
-                    signature: sign.clone().clone(),
-                    value: expr.clone(),
-                    position: Default::default()
-                })?;
-            }
+        // The value a function returns is left on the stack, unless no one
+        // uses it.
+        if self.call_function(function_call)? {
+            self.generate_pop_expression();
+        }
 
-            if func.is_static() {
-                // Macros
-                self.push_static_scope();
-                self.generate_statements(func.block.clone())?;
-                self.pop_static_scope();
-            } else if func.is_dynamic() && !func.signature.get_static_args().is_empty() {
-                // Dynamic macros
-                for (_, expr) in &dyn_args {
-                    self.generate_expression(expr.clone())?;
-                }
+        Ok(())
+    }
 
-                let statements = func.block.clone();
-                let requires_scope = Self::requires_scope(&statements);
+    /// Calls a function, pushing the value it returns to the stack.
+    pub fn generate_function_result(&mut self, function_call: FunctionCall) -> Result<(), CompilerError> {
+        let (name, position) = (function_call.name.clone(), function_call.position.clone());
+        if !self.call_function(function_call)? {
+            return Err((position, format!("`{}` doesn't return a value", name)).into());
+        }
 
-                if requires_scope { self.push_scope(); }
+        Ok(())
+    }
 
-                for (sign, _) in dyn_args.iter().rev() {
-                    self.register_runtime_variable(sign);
-                    self.write(format!(
-                        "data modify storage tag:runtime vars[-1].\"{}\" set from storage tag:runtime stack[-1].@",
-                        sign.name.get_name()
-                    ));
-                    self.generate_pop_expression();
-                }
+    /// Calls a function, returning whether it pushed a value to the stack.
+    fn call_function(&mut self, function_call: FunctionCall) -> Result<bool, CompilerError> {
+        let (depth, (func, file_name)) = self.resolve_function_call(&function_call)
+            .map(|(depth, info)| (depth, info.clone()))?;
 
-                let name = self.push_file();
+        // Macros are expanded where they are called, so a recursive one
+        // would be expanded forever.
+        let expanded = func.is_static() || !func.signature.get_static_args().is_empty();
+        if expanded && self.depth >= MAX_DEPTH {
+            return Err(CompilerError::from((function_call.position, "too many nested macro expansions"))
+                .with_note(format!("note: expanded macros can't nest blocks more than {} deep", MAX_DEPTH)));
+        }
+        let args: Vec<_> = func.signature.args.iter().zip(function_call.args).collect();
+        let dyn_args: Vec<_> = args.iter().filter(|(sign, _)| sign.is_dynamic()).collect();
 
-                self.generate_statements(statements)?;
-                if requires_scope { self.pop_scope(); }
-                self.pop_file();
+        // The arguments are evaluated by the caller, the body sees the
+        // scopes around the function's declaration instead (see
+        // [Generator::in_declaring_scope]).
+        let mut static_values = vec![];
+        for (sign, expr) in args.iter().filter(|(sign, _)| sign.is_static()) {
+            static_values.push((sign.name.clone(), expr.evaluate(self)?));
+        }
 
-                self.write(format!("function {}:{}", self.namespace, name))
-            } else if func.is_dynamic() && func.signature.get_static_args().is_empty() {
-                // Functions
-                for (_, expr) in dyn_args.iter().rev() {
-                    self.generate_expression(expr.clone())?;
-                }
-                self.write(format!("function {}:{}", self.namespace, file_name.unwrap()))
-            } else {
-                return panic!("can't resolve function call");
+        if func.is_static() {
+            // Macros
+            self.in_declaring_scope(depth, |ctx| {
+                ctx.push_static_scope();
+                ctx.peek_scope().comptime_variables.extend(static_values);
+                ctx.generate_statements(func.block.clone())?;
+                ctx.pop_static_scope();
+                Ok(())
+            })?;
+        } else if func.is_dynamic() && !func.signature.get_static_args().is_empty() {
+            // Dynamic macros
+            for (_, expr) in &dyn_args {
+                self.generate_expression(expr.clone())?;
             }
 
-            Ok(())
+            // Macros are expanded for each call, so their arguments can
+            // have the type of the values they are called with.
+            let typings: Vec<_> = dyn_args.iter().map(|(sign, expr)| match sign.typing {
+                Typing::Unknown => self.get_typing(expr),
+                ref typing => typing.clone()
+            }).collect();
+
+            let name = self.push_file();
+            self.in_declaring_scope(depth, |ctx| {
+                ctx.generate_trace(&func);
+                ctx.push_scope();
+                ctx.peek_scope().comptime_variables.extend(static_values);
+                for ((sign, _), typing) in dyn_args.iter().zip(typings).rev() {
+                    ctx.register_runtime_variable(&VariableSignature { name: sign.name.clone(), typing });
+                    ctx.generate_pop_to_variable(&sign.name);
+                }
+
+                ctx.generate_statements(func.block.clone())?;
+                if let Some(result) = &func.result {
+                    ctx.at(&result.position, |ctx| ctx.generate_expression(result.expr.clone()))?;
+                }
+                ctx.pop_scope();
+                Ok(())
+            })?;
+            self.pop_file();
+
+            self.generate_call(&name, depth);
+        } else if func.is_dynamic() && func.signature.get_static_args().is_empty() {
+            // Functions
+            for (_, expr) in &dyn_args {
+                self.generate_expression(expr.clone())?;
+            }
+            self.generate_call(&file_name.unwrap(), depth);
         } else {
-            Err((function_call.position, "can't resolve function call").into())
+            unreachable!("can't resolve function call");
+        }
+
+        Ok(func.result.is_some())
+    }
+
+    /// Runs `generate` with the scopes seen by a function declared in the
+    /// scope at `depth`: functions are scoped lexically, so the scopes of
+    /// the caller above it are put aside meanwhile.
+    fn in_declaring_scope<F>(&mut self, depth: usize, generate: F) -> Result<(), CompilerError>
+        where F: FnOnce(&mut Self) -> Result<(), CompilerError>
+    {
+        let caller_scopes = self.scopes.split_off(depth + 1);
+        let result = generate(self);
+        self.scopes.extend(caller_scopes);
+        result
+    }
+
+    /// Calls the function `name`, declared in the scope at `depth`. Its body
+    /// reads the runtime variables of the scopes around its declaration
+    /// from the top of `vars`, so the ones of the caller above them are
+    /// moved to [CALLERS] while it runs.
+    fn generate_call(&mut self, name: &str, depth: usize) {
+        let caller_scopes = self.scopes.len() - 1 - depth;
+        for _ in 0..caller_scopes {
+            self.write(format!("data modify storage {0} {1} append from storage {0} vars[-1]", self.storage, CALLERS));
+            self.write(format!("data remove storage {} vars[-1]", self.storage));
+        }
+        self.write(format!("function {}:{}", self.namespace, name));
+        for _ in 0..caller_scopes {
+            self.write(format!("data modify storage {0} vars append from storage {0} {1}[-1]", self.storage, CALLERS));
+            self.write(format!("data remove storage {} {}[-1]", self.storage, CALLERS));
+        }
+    }
+
+    /// In debug builds, dynamic functions announce when they are entered,
+    /// if the `debug` flag of the runtime storage is set.
+    fn generate_trace(&mut self, function: &Function) {
+        if !self.debug {
+            return;
+        }
+
+        let position = &function.position;
+        let trace = json!({
+            "text": format!("[{}] {} ({}:{})", self.namespace, function.signature, position.line, position.column),
+            "color": "gray"
+        });
+        self.write(format!("execute if data storage {} {{{}:{}}} run tellraw @a {}",
+                           self.storage, DEBUG_FLAG, nbt_bool(true), trace));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, CompileOptions};
+    use crate::generator::MAX_DEPTH;
+    use crate::simulator::Simulator;
+    use crate::simulator::nbt::Nbt;
+
+    fn options() -> CompileOptions {
+        CompileOptions { namespace: "test".into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_recursive_macros() {
+        // Macros are expanded where they are called, so a recursive one is
+        // reported rather than expanded until the stack overflows.
+        let src = "def $f(n) {\n  $g(n);\n}\ndef $g(n) {\n  $f(n);\n}\n$f(1);\n";
+        let errors = compile_str(src, options()).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].error, "too many nested macro expansions");
+        assert_eq!(errors[0].notes, [format!("note: expanded macros can't nest blocks more than {} deep", MAX_DEPTH)]);
+
+        // Macros expanded one after the other don't nest.
+        let src = format!("def $f(n) {{\n  /say #{{n}}\n}}\n{}", "$f(1);\n".repeat(MAX_DEPTH + 1));
+        assert!(compile_str(&src, options()).is_ok());
+    }
+    #[test]
+    fn test_returns() {
+        // The value of a call whose result is unused is popped.
+        let src = "def $f($n) {\n  return $n + 1;\n}\n$f(1);\n/say #{$f(2)}\n";
+        let target = "1.20.4".parse().unwrap();
+        let datapack = compile_str(src, CompileOptions { target, ..options() }).unwrap();
+        let mut simulator = Simulator::new(&datapack);
+        simulator.load().unwrap();
+        assert_eq!(simulator.chat, ["3"]);
+        assert_eq!(simulator.get("test:runtime", "stack"), Some(&Nbt::List(vec![])));
+
+        let errors = [
+            ("def f(n) {\n  return n;\n}\n", "can't return a value from a static function"),
+            ("return 1;\n", "`return` can only end the block of a function"),
+            ("def $f() {\n  if true {\n    return 1;\n  }\n}\n", "`return` can only end the block of a function"),
+            ("def $f() {\n  return 1;\n  /say hi\n}\n", "`return` must be the last statement of a function")
+        ];
+        for (src, error) in errors {
+            let errors = compile_str(src, options()).unwrap_err();
+            assert_eq!(errors[0].error, error, "{}", src);
         }
     }
 }
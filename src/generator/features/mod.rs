@@ -2,4 +2,5 @@ pub mod condition;
 pub mod expression;
 pub mod statement;
 pub mod command;
-pub mod function;
\ No newline at end of file
+pub mod function;
+pub mod test;
\ No newline at end of file
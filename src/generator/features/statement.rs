@@ -1,7 +1,7 @@
 use crate::generator::Generator;
 use crate::parser::statement::{Statement, VariableAssignment};
+use crate::parser::function::Function;
 use crate::generator::staticness::IsStatic;
-use crate::generator::scopes;
 use crate::errors::CompilerError;
 
 impl Generator {
@@ -18,58 +18,78 @@ impl Generator {
     }
 
     pub fn generate_statements(&mut self, statements: Vec<Statement>) -> Result<(), CompilerError> {
-        // first we analyze the statements
-        // (e.g. we register the functions)
+        self.declare_functions(&statements)?;
 
-        let functions: Vec<_> = statements.iter().filter_map(|statement| {
-            match statement {
-                Statement::FunctionDeclaration(func) => Some(func.clone()),
-                _ => None
-            }
-        }).collect();
-
-        for func in &functions {
-            if func.is_dynamic() && func.signature.get_static_args().is_empty() {
-                let name = self.push_file();
-                self.register_function(func.clone(), Some(name));
-            } else {
-                self.register_function(func.clone(), None);
-            }
+        self.depth += 1;
+        for statement in statements {
+            self.generate_statement(statement)?;
         }
+        self.depth -= 1;
 
-        for func in functions.iter().rev() {
-            if func.is_dynamic() && func.signature.get_static_args().is_empty() {
-                self.generate_function(func.clone());
-            }
+        Ok(())
+    }
+
+    /// Registers the functions declared in a block, before its statements,
+    /// generating the dynamic functions that aren't macros.
+    pub fn declare_functions(&mut self, statements: &[Statement]) -> Result<(), CompilerError> {
+        for func in self.register_functions(statements).into_iter().rev() {
+            self.generate_declared_function(func)?;
         }
 
+        Ok(())
+    }
+
+    /// Registers the functions declared in a block, returning the dynamic
+    /// ones that aren't macros, which are to be generated in the reverse
+    /// order with [Generator::generate_declared_function].
+    pub fn register_functions(&mut self, statements: &[Statement]) -> Vec<Function> {
+        let mut generated = vec![];
         for statement in statements {
-            self.generate_statement(statement)?;
+            if let Statement::FunctionDeclaration(func) = statement {
+                if func.is_dynamic() && func.signature.get_static_args().is_empty() {
+                    let name = self.push_file();
+                    self.register_function(func.clone(), Some(name));
+                    generated.push(func.clone());
+                } else {
+                    self.register_function(func.clone(), None);
+                }
+            }
         }
 
-        Ok(())
+        generated
+    }
+
+    pub fn generate_declared_function(&mut self, func: Function) -> Result<(), CompilerError> {
+        let position = func.position.clone();
+        self.at(&position, |ctx| ctx.generate_function(func))
     }
 
     pub fn generate_statement(&mut self, statement: Statement) -> Result<(), CompilerError> {
+        let position = statement.pos().clone();
+        self.at(&position, |ctx| ctx.generate_statement_at(statement))
+    }
+
+    fn generate_statement_at(&mut self, statement: Statement) -> Result<(), CompilerError> {
         use Statement::*;
 
         match statement {
             Command(cmd) => self.generate_command(cmd),
             IfStatement(if_stmt) => self.generate_if_statement(if_stmt),
             VariableAssignment(assignment) => self.generate_variable_assignment(assignment),
-            FunctionDeclaration(func) => Ok(()),
+            FunctionDeclaration(_) => Ok(()),
             FunctionCall(call) => self.generate_function_call(call),
-            _ => todo!()
+            Test(test) => self.generate_test(test),
+            Assert(assert) => self.generate_assert(assert)
         }
     }
 
     pub fn generate_variable_assignment(&mut self, assignment: VariableAssignment) -> Result<(), CompilerError> {
         if assignment.is_dynamic() {
-            self.register_runtime_variable(&assignment.signature);
+            // The value is generated first, so that `$a := $a + 1` reads
+            // the previous `$a`, even if the new one shadows it.
             self.generate_expression(assignment.value)?;
-            self.write(format!("data modify storage tag:runtime vars[-1].\"{}\" set from storage tag:runtime stack[-1].@",
-                               assignment.signature.name.get_name()));
-            self.generate_pop_expression();
+            self.register_runtime_variable(&assignment.signature);
+            self.generate_pop_to_variable(&assignment.signature.name);
         } else {
             self.assign_static_variable(assignment)?;
         }
@@ -0,0 +1,42 @@
+use crate::generator::{Generator, nbt_bool, FAILURES};
+use crate::parser::statement::{Test, Assert};
+use crate::errors::CompilerError;
+use crate::testing::TestCase;
+
+impl Generator {
+    /// Generates the function of `test`, when compiling for `tag test`.
+    /// Nothing calls it: the runner does, once the datapack is loaded.
+    pub fn generate_test(&mut self, test: Test) -> Result<(), CompilerError> {
+        let suite = match &self.tests {
+            Some(suite) => suite,
+            None => return Ok(())
+        };
+        if suite.tests.iter().any(|case| case.name == test.name) {
+            return Err((test.position, format!("the test `{}` is declared twice", test.name)).into());
+        }
+
+        let function = self.push_file();
+        self.generate_scoped_statements(test.block)?;
+        self.pop_file();
+
+        let case = TestCase { name: test.name, function, position: test.position };
+        self.tests.as_mut().unwrap().tests.push(case);
+        Ok(())
+    }
+
+    /// Appends the id of the assertion to the failures of the storage when
+    /// its expression is false. See [crate::testing::TestSuite::assertions].
+    pub fn generate_assert(&mut self, assert: Assert) -> Result<(), CompilerError> {
+        let suite = self.tests.as_mut().expect("assertions are only generated in tests");
+        let id = suite.assertions.len();
+        suite.assertions.push(assert.position);
+
+        self.generate_expression(assert.expr)?;
+        self.write(format!("data modify storage {0} condition set from storage {0} stack[-1].@", self.storage));
+        self.write(format!("execute unless data storage {} {{condition:{}}} run data modify storage {} {} append value {}",
+                           self.storage, nbt_bool(true), self.storage, FAILURES, id));
+        self.generate_pop_expression();
+
+        Ok(())
+    }
+}
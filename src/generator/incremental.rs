@@ -0,0 +1,158 @@
+//! Incremental generation, for [crate::cache::Cache]: each function declared
+//! at the top level and each source is generated as a [Step], kept by the
+//! key of what it reads from the generator, so that a build only generates
+//! again the steps whose inputs changed.
+
+use crate::parser::AST;
+use crate::parser::Position;
+use crate::generator::{Generator, Lines, is_prelude};
+use crate::generator::scopes::Scope;
+use crate::testing::TestCase;
+use crate::errors::CompilerError;
+use crate::datapack::Datapack;
+use crate::cache::{hash, key};
+use crate::{CompileOptions, Source};
+use std::collections::{BTreeSet, HashMap};
+use serde::{Serialize, Deserialize};
+
+/// What a step changed in the generator, to be applied again by a build
+/// whose step reads the same inputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Step {
+    /// The lines appended to each function, the functions created by the
+    /// step included.
+    lines: Vec<(String, Lines)>,
+    file_counter: i32,
+    /// How many functions are still being generated after the step.
+    open_files: usize,
+    /// The entries the step set in the top level scope.
+    scope: Scope,
+    objectives: BTreeSet<String>,
+    tests: Vec<TestCase>,
+    assertions: Vec<Position>
+}
+
+/// Where [generate_incremental] keeps the steps it generated, by the key
+/// of what they read.
+pub trait Checkpoints {
+    fn load(&mut self, key: u64) -> Option<Step>;
+    fn save(&mut self, key: u64, step: &Step);
+}
+
+/// Generates `ast` like [crate::generator::generate], reusing the steps of
+/// `checkpoints` whose inputs didn't change: the functions declared at the
+/// top level, in the order they are generated, then each source.
+///
+/// A step is keyed by its statements and by what it reads from the steps
+/// before it: the variables and functions in scope, with the bodies of the
+/// dynamic functions left out as calls only refer to them by name, the
+/// functions created so far, and the tests. Editing a command only
+/// generates its source again, while declaring a variable also generates
+/// the sources after it.
+pub fn generate_incremental(ast: AST, options: CompileOptions, sources: &[Source], checkpoints: &mut dyn Checkpoints)
+    -> Result<Datapack, CompilerError>
+{
+    let (prelude, statements): (Vec<_>, Vec<_>) = ast.statements.into_iter().partition(is_prelude);
+    let options_key = hash(&options);
+    let mut ctx = Generator::with_prelude(prelude, &options)?;
+    ctx.set_sources(sources);
+
+    for func in ctx.register_functions(&statements).into_iter().rev() {
+        let key = key(options_key, &(ctx.inputs(), &func));
+        ctx.step(checkpoints, key, |ctx| ctx.generate_declared_function(func))?;
+    }
+
+    for source in statements.chunk_by(|a, b| a.pos().file == b.pos().file) {
+        let key = key(options_key, &(ctx.inputs(), source));
+        ctx.step(checkpoints, key, |ctx| {
+            ctx.depth += 1;
+            for statement in source {
+                ctx.generate_statement(statement.clone())?;
+            }
+            ctx.depth -= 1;
+            Ok(())
+        })?;
+    }
+
+    ctx.into_datapack()
+}
+
+/// What a step reads from the generator, besides its statements, in a
+/// stable order.
+#[derive(Serialize)]
+struct Inputs<'a> {
+    scopes: Vec<Vec<String>>,
+    file_name_stack: &'a [String],
+    file_counter: i32,
+    depth: usize,
+    /// The names of the tests, which must be unique, and the number of
+    /// assertions, which are numbered.
+    tests: Option<(Vec<&'a str>, usize)>
+}
+
+impl Generator {
+    fn inputs(&self) -> Inputs<'_> {
+        Inputs {
+            scopes: self.scopes.iter().map(Scope::inputs).collect(),
+            file_name_stack: &self.file_name_stack,
+            file_counter: self.file_counter,
+            depth: self.depth,
+            tests: self.tests.as_ref().map(|suite| {
+                (suite.tests.iter().map(|case| case.name.as_str()).collect(), suite.assertions.len())
+            })
+        }
+    }
+
+    /// Applies the step saved under `key`, or runs `generate` and saves
+    /// what it changed.
+    fn step<F>(&mut self, checkpoints: &mut dyn Checkpoints, key: u64, generate: F) -> Result<(), CompilerError>
+        where F: FnOnce(&mut Self) -> Result<(), CompilerError>
+    {
+        if let Some(step) = checkpoints.load(key) {
+            self.apply(step);
+            return Ok(());
+        }
+
+        let lines: HashMap<_, _> = self.files.iter().map(|(name, lines)| (name.clone(), lines.len())).collect();
+        let scope = self.scopes[0].clone();
+        let objectives = self.objectives.clone();
+        let tests = self.tests.as_ref().map_or((0, 0), |suite| (suite.tests.len(), suite.assertions.len()));
+
+        generate(self)?;
+
+        let mut written: Vec<_> = self.files.iter()
+            .filter(|(name, after)| lines.get(*name).is_none_or(|before| after.len() > *before))
+            .map(|(name, after)| (name.clone(), after[lines.get(name).map_or(0, |before| *before)..].to_vec()))
+            .collect();
+        written.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let (new_tests, new_assertions) = match &self.tests {
+            Some(suite) => (suite.tests[tests.0..].to_vec(), suite.assertions[tests.1..].to_vec()),
+            None => (vec![], vec![])
+        };
+
+        checkpoints.save(key, &Step {
+            lines: written,
+            file_counter: self.file_counter,
+            open_files: self.file_name_stack.len(),
+            scope: self.scopes[0].changes_since(&scope),
+            objectives: self.objectives.difference(&objectives).cloned().collect(),
+            tests: new_tests,
+            assertions: new_assertions
+        });
+        Ok(())
+    }
+
+    fn apply(&mut self, step: Step) {
+        for (name, lines) in step.lines {
+            self.files.entry(name).or_default().extend(lines);
+        }
+        self.file_counter = step.file_counter;
+        self.file_name_stack.truncate(step.open_files);
+        self.scopes[0].extend(step.scope);
+        self.objectives.extend(step.objectives);
+        if let Some(suite) = &mut self.tests {
+            suite.tests.extend(step.tests);
+            suite.assertions.extend(step.assertions);
+        }
+    }
+}
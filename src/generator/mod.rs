@@ -1,25 +1,53 @@
 mod features;
 mod scopes;
-mod typing;
+pub mod typing;
 mod simplify;
 pub mod staticness;
 pub mod datapack;
+pub mod resolve;
+mod incremental;
+
+pub use self::incremental::{generate_incremental, Checkpoints, Step};
 
 use super::parser::{AST};
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeSet};
 use crate::errors::CompilerError;
-use crate::parser::statement::{VariableAssignment, VariableSignature};
+use crate::parser::statement::Statement;
 use crate::parser::expression::VariableName;
-use crate::parser::typing::Typing;
-use crate::CompileOptions;
+use crate::{CompileOptions, Source};
+use crate::datapack::Datapack;
+use crate::target::{Feature, Target};
+use crate::parser::Position;
+use crate::testing::TestSuite;
+
+/// The lines of a function, with the position of the statement they were
+/// generated for.
+type Lines = Vec<(String, Option<Position>)>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Generator {
     file_name_stack: Vec<String>,
-    files: HashMap<String, Vec<String>>,
+    files: HashMap<String, Lines>,
     file_counter: i32,
     scopes: Vec<self::scopes::Scope>,
-    namespace: String
+    namespace: String,
+    target: Target,
+    /// The storage holding the stack and the runtime variables.
+    storage: String,
+    /// The scoreboard objective used for arithmetic.
+    objective: String,
+    /// Every objective the datapack creates, removed when uninstalling it.
+    objectives: BTreeSet<String>,
+    /// The statement being generated, if any.
+    position: Option<Position>,
+    debug: bool,
+    /// The tests generated so far, if they are compiled.
+    tests: Option<TestSuite>,
+    /// How deeply the blocks being generated are nested, counting the
+    /// bodies of the macros expanded in them.
+    depth: usize,
+    /// The sources being generated, which the notes of the errors point into.
+    sources: Vec<Source>
 }
 
 impl Generator {
@@ -29,17 +57,92 @@ impl Generator {
             files: HashMap::new(),
             file_counter: -1,
             scopes: vec![],
-            namespace: options.namespace.clone()
+            namespace: options.namespace.clone(),
+            target: options.target,
+            storage: options.runtime_storage(),
+            objective: options.runtime_objective(),
+            objectives: BTreeSet::new(),
+            position: None,
+            debug: options.debug,
+            tests: if options.tests { Some(TestSuite::new(options.runtime_storage())) } else { None },
+            depth: 0,
+            sources: vec![]
         };
         ctx.push_file();
         ctx.push_scope();
+        ctx.write(format!("scoreboard objectives add {} dummy", ctx.objective));
+        ctx.objectives.insert(ctx.objective.clone());
         ctx
     }
 
+    /// A generator having generated `prelude`, the statements of the
+    /// bootstrap and the defines, ready for the statements of the sources.
+    pub fn with_prelude(prelude: Vec<Statement>, options: &CompileOptions) -> Result<Self, CompilerError> {
+        let mut ctx = Generator::new(options);
+        if ctx.objective.len() > crate::MAX_OBJECTIVE_LENGTH {
+            let position = Position { file: crate::DEFINES_FILE, ..Default::default() };
+            ctx.require(Feature::LongObjectiveNames, &position)
+                .map_err(|err| err.with_note("help: choose a shorter `objective` for the pack"))?;
+        }
+
+        ctx.generate_statements(prelude)?;
+        Ok(ctx)
+    }
+
+    /// Sets the sources the statements to generate are parsed from, when
+    /// they are given after the generator is created.
+    pub fn set_sources(&mut self, sources: &[Source]) {
+        self.sources = sources.to_vec();
+    }
+
+    /// Runs `generate`, giving the lines it wrote to each function, the
+    /// top level coming first. If it fails, the generator is left as it
+    /// was before.
+    pub fn preview<F>(&mut self, generate: F) -> Result<Vec<(String, Vec<String>)>, CompilerError>
+        where F: FnOnce(&mut Self) -> Result<(), CompilerError>
+    {
+        let saved = self.clone();
+        if let Err(err) = generate(self) {
+            *self = saved;
+            return Err(err);
+        }
+
+        let mut written: Vec<(String, Vec<String>)> = self.files.iter()
+            .map(|(name, lines)| {
+                let before = saved.files.get(name).map_or(0, Vec::len);
+                (name.clone(), lines[before..].iter().map(|(line, _)| line.clone()).collect())
+            })
+            .filter(|(_, lines): &(String, Vec<String>)| !lines.is_empty())
+            .collect();
+        // Functions are numbered in the order they are created, from the
+        // top level, `0`.
+        written.sort_by_key(|(name, _)| (name.len(), name.clone()));
+        Ok(written)
+    }
+
     fn write<S: ToString>(&mut self, content: S) {
         let name = self.file_name_stack.last().expect("file name stack is empty");
         let file = self.files.get_mut(name).unwrap();
-        file.push(content.to_string());
+        file.push((content.to_string(), self.position.clone()));
+    }
+
+    /// Runs `generate`, mapping the lines it writes to `position`.
+    fn at<T, F: FnOnce(&mut Self) -> T>(&mut self, position: &Position, generate: F) -> T {
+        let outer = self.position.replace(position.clone());
+        let result = generate(self);
+        self.position = outer;
+        result
+    }
+
+    /// Fails at `pos` if the target doesn't support `feature`.
+    fn require(&self, feature: Feature, pos: &Position) -> Result<(), CompilerError> {
+        if self.target.supports(feature) {
+            return Ok(());
+        }
+
+        let error = format!("{} requires Minecraft {} or later", feature.description(), feature.since());
+        Err(CompilerError::from((pos.clone(), error))
+            .with_note(format!("note: the target is Minecraft {}", self.target)))
     }
 
     fn push_file(&mut self) -> String {
@@ -56,16 +159,53 @@ impl Generator {
     }
 
     fn generate_push_to_stack<S: std::fmt::Display>(&mut self, value: S) {
-        self.write("data modify storage tag:runtime stack append value {}");
-        self.write(format!("data modify storage tag:runtime stack[-1].@ set value {}", value));
+        self.write(format!("data modify storage {} stack append value {{}}", self.storage));
+        self.write(format!("data modify storage {} stack[-1].@ set value {}", self.storage, value));
     }
+
+    /// Moves the top of the stack to the runtime variable `name` of the
+    /// innermost scope.
+    fn generate_pop_to_variable(&mut self, name: &VariableName) {
+        self.write(format!("data modify storage {0} vars[-1].\"{1}\" set from storage {0} stack[-1].@",
+                           self.storage, name.get_name()));
+        self.generate_pop_expression();
+    }
+}
+
+/// The path of the runtime storage enabling the traces of debug builds.
+pub const DEBUG_FLAG: &str = "debug";
+
+/// How deeply blocks can be nested once macros are expanded. Sources are
+/// already limited to [crate::parser::MAX_NESTING] levels, but a macro
+/// expanded within another one nests its blocks in the other's.
+pub const MAX_DEPTH: usize = 128;
+
+/// The path of the runtime storage keeping the runtime variables of the
+/// callers of a function aside while it runs.
+pub const CALLERS: &str = "callers";
+
+/// The path of the runtime storage listing the assertions that failed.
+pub const FAILURES: &str = "failures";
+
+/// Booleans are stored at runtime as NBT bytes, so they can both be matched
+/// with `execute if data` and read as a 0/1 score.
+pub fn nbt_bool(value: bool) -> &'static str {
+    if value { "1b" } else { "0b" }
 }
 
-pub fn generate(ast: AST, options: CompileOptions) -> Result<(), CompilerError> {
-    let mut ctx = Generator::new(&options);
+/// Whether `statement` comes from the bootstrap or the defines, which are
+/// generated before the sources.
+pub fn is_prelude(statement: &Statement) -> bool {
+    matches!(statement.pos().file, crate::BOOTSTRAP_FILE | crate::DEFINES_FILE)
+}
 
-    ctx.generate_statements(ast.statements)?;
-    dbg!(&ctx);
-    ctx.write_datapack(options)?;
-    Ok(())
+pub fn generate(ast: AST, options: CompileOptions, sources: &[Source]) -> Result<Datapack, CompilerError> {
+    // The bootstrap and the defines are generated on their own, so that the
+    // functions of the sources, which are generated ahead of the statements
+    // around them, can use the static variables they declare.
+    let (prelude, statements): (Vec<_>, Vec<_>) = ast.statements.into_iter().partition(is_prelude);
+    let mut ctx = Generator::with_prelude(prelude, &options)?;
+    ctx.set_sources(sources);
+    ctx.generate_statements(statements)?;
+    ctx.into_datapack()
 }
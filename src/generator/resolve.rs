@@ -0,0 +1,637 @@
+use crate::parser::AST;
+use crate::parser::{Position, FileId};
+use crate::parser::statement::{Statement, IfStatement, Command, VariableAssignment, Test};
+use crate::parser::function::{Function, FunctionCall, FunctionSignature};
+use crate::parser::expression::{Expression, Summand, Term, VariableName};
+use crate::generator::is_prelude;
+use crate::generator::staticness::IsStatic;
+use crate::errors::CompilerError;
+use crate::{source_location, Source};
+use std::collections::{HashMap, HashSet};
+
+/// The place where a variable or a function has been declared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: VariableName,
+    pub position: Position
+}
+
+/// The result of the resolution pass: every variable use and every function
+/// call of the AST, keyed by its position in the source, bound to the
+/// declaration(s) it refers to.
+#[derive(Debug, Default)]
+pub struct Resolutions {
+    pub variables: HashMap<Position, Declaration>,
+    /// A call is bound to every overload visible from it; picking the right
+    /// one is up to [crate::generator::Generator::resolve_function_call].
+    pub functions: HashMap<Position, Vec<(FunctionSignature, Position)>>,
+    /// An assignment of a variable its scope already declares, keyed by
+    /// its position, bound to the previous declaration.
+    pub reassignments: HashMap<Position, Declaration>
+}
+
+/// The names visible from a point of the source, see [scope_at].
+#[derive(Debug, Default, Clone)]
+pub struct Visible {
+    pub variables: Vec<Declaration>,
+    pub functions: Vec<(FunctionSignature, Position)>
+}
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<VariableName, Position>,
+    functions: HashMap<VariableName, Vec<(FunctionSignature, Position)>>,
+    /// The variables assigned in the block, including the ones that
+    /// aren't yet, to explain why the functions of the block can't use them.
+    assigned: HashSet<VariableName>,
+    /// The macros declared in the block, whose bodies are resolved where
+    /// they are expanded.
+    macros: Vec<Function>
+}
+
+/// Walks the AST the same way the generator does, mirroring its
+/// [Scope](crate::generator::scopes) chain, so that undefined names are
+/// reported before any code gets generated.
+struct Resolver<'a> {
+    /// The sources of the AST, which the notes of the errors point into.
+    sources: &'a [Source],
+    scopes: Vec<Scope>,
+    resolutions: Resolutions,
+    errors: Vec<CompilerError>,
+    /// The index of the scope of the `test` being resolved, if any.
+    test_scope: Option<usize>,
+    /// The point of the source [scope_at] looks from, and the names
+    /// visible from the statement before it closest to it, along with the
+    /// offset of that statement.
+    cursor: Option<(FileId, usize)>,
+    visible: Option<(usize, Visible)>,
+    /// The offset of the statement whose block is about to be resolved,
+    /// if it starts before the cursor.
+    entering: Option<usize>,
+    /// The macros being expanded, the innermost last, and the ones that
+    /// have been.
+    expanding: Vec<Position>,
+    expanded: HashSet<Position>,
+    /// The indices of the scopes whose functions are being generated
+    /// first, before the statements of their block.
+    generated_first: Vec<usize>
+}
+
+pub fn resolve(ast: &AST, sources: &[Source]) -> Result<Resolutions, Vec<CompilerError>> {
+    let (resolutions, errors) = resolve_all(ast, sources);
+
+    if errors.is_empty() {
+        Ok(resolutions)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Like [resolve], but keeps the names that could be resolved when some
+/// couldn't, for tools working on sources being edited.
+pub fn resolve_all(ast: &AST, sources: &[Source]) -> (Resolutions, Vec<CompilerError>) {
+    let mut resolver = Resolver::new(sources, None);
+    resolver.resolve_top_level(ast);
+    (resolver.resolutions, resolver.errors)
+}
+
+/// The variables and functions visible from `offset` in the source `file`,
+/// for completions.
+pub fn scope_at(ast: &AST, file: FileId, offset: usize) -> Visible {
+    let mut resolver = Resolver::new(&[], Some((file, offset)));
+    resolver.resolve_top_level(ast);
+    resolver.visible.map(|(_, visible)| visible).unwrap_or_default()
+}
+
+impl<'a> Resolver<'a> {
+    fn new(sources: &'a [Source], cursor: Option<(FileId, usize)>) -> Self {
+        Resolver {
+            sources,
+            scopes: vec![],
+            resolutions: Resolutions::default(),
+            errors: vec![],
+            test_scope: None,
+            cursor,
+            visible: None,
+            entering: None,
+            expanding: vec![],
+            expanded: HashSet::new(),
+            generated_first: vec![]
+        }
+    }
+
+    /// Resolves the prelude, then the statements of the sources in the same
+    /// scope, like [crate::generator::generate].
+    fn resolve_top_level(&mut self, ast: &AST) {
+        let (prelude, statements): (Vec<_>, Vec<_>) = ast.statements.iter().cloned().partition(is_prelude);
+        self.scopes.push(Scope::default());
+        self.resolve_statements(&prelude);
+        self.resolve_statements(&statements);
+        self.scopes.pop();
+    }
+
+    fn resolve_block(&mut self, statements: &[Statement]) {
+        self.scopes.push(Scope::default());
+        self.resolve_statements(statements);
+        self.scopes.pop();
+    }
+
+    fn resolve_statements(&mut self, statements: &[Statement]) {
+        // Functions are registered before anything else in their block
+        // (see `Generator::declare_functions`), so they can be called
+        // before being declared.
+        for statement in statements {
+            match statement {
+                Statement::FunctionDeclaration(func) => {
+                    self.declare_function(func);
+                    if !is_generated_first(func) {
+                        self.peek_scope().macros.push(func.clone());
+                    }
+                },
+                Statement::VariableAssignment(assignment) => {
+                    self.peek_scope().assigned.insert(assignment.signature.name.clone());
+                },
+                _ => {}
+            }
+        }
+
+        if let Some(offset) = self.entering.take() {
+            self.snapshot(offset);
+        }
+
+        // The generator generates the dynamic functions that aren't macros
+        // right away, before the other statements of the block, so they
+        // can't use the variables assigned in it. Macros are expanded
+        // where they are called, see [Resolver::expand].
+        self.generated_first.push(self.scopes.len() - 1);
+        for statement in statements.iter().rev() {
+            if let Statement::FunctionDeclaration(func) = statement {
+                if is_generated_first(func) {
+                    self.resolve_statement_at(statement);
+                }
+            }
+        }
+        self.generated_first.pop();
+
+        for statement in statements {
+            if !matches!(statement, Statement::FunctionDeclaration(_)) {
+                self.resolve_statement_at(statement);
+            }
+        }
+
+        // The macros that are never expanded are still resolved, as if
+        // they were at the end of their block, so that their mistakes are
+        // reported.
+        let macros = self.peek_scope().macros.clone();
+        for func in macros {
+            if !self.expanded.contains(&func.position) {
+                self.resolve_statement_at(&Statement::FunctionDeclaration(func));
+            }
+        }
+    }
+
+    /// Resolves `statement`, taking a snapshot of the names visible from
+    /// it if the cursor is past it.
+    fn resolve_statement_at(&mut self, statement: &Statement) {
+        let position = statement.pos();
+        let before_cursor = self.cursor.is_some_and(|(file, offset)|
+            position.file == file && position.offset < offset);
+        let has_block = matches!(statement,
+            Statement::IfStatement(_) | Statement::FunctionDeclaration(_) | Statement::Test(_));
+
+        // The cursor may be in the block of the statement, or after the
+        // statement, in which case it sees its declarations.
+        self.entering = Some(position.offset).filter(|_| before_cursor && has_block);
+        self.resolve_statement(statement);
+        self.entering = None;
+        if before_cursor && !has_block {
+            self.snapshot(position.offset);
+        }
+    }
+
+    /// Records the names visible from the statement at `offset`, unless
+    /// the cursor is closer to the one already recorded: statements aren't
+    /// resolved in the order of the source.
+    fn snapshot(&mut self, offset: usize) {
+        if self.visible.as_ref().is_some_and(|(recorded, _)| *recorded > offset) {
+            return;
+        }
+
+        let mut visible = Visible::default();
+        let boundary = self.test_scope.unwrap_or(0);
+
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            for (name, position) in &scope.variables {
+                // Runtime variables of the surroundings of a test can't be
+                // read from it.
+                let hidden = name.is_dynamic() && i < boundary;
+                if !hidden && visible.variables.iter().all(|declaration| declaration.name != *name) {
+                    visible.variables.push(Declaration { name: name.clone(), position: position.clone() });
+                }
+            }
+            for overloads in scope.functions.values() {
+                visible.functions.extend(overloads.iter().cloned());
+            }
+        }
+
+        self.visible = Some((offset, visible));
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Command(cmd) => self.resolve_command(cmd),
+            Statement::IfStatement(if_stmt) => self.resolve_if_statement(if_stmt),
+            Statement::VariableAssignment(assignment) => self.resolve_variable_assignment(assignment),
+            Statement::FunctionDeclaration(func) => self.resolve_function(func),
+            Statement::FunctionCall(call) => self.resolve_function_call(call),
+            Statement::Test(test) => self.resolve_test(test),
+            Statement::Assert(assert) => {
+                if self.test_scope.is_none() {
+                    self.errors.push((assert.position.clone(), "`assert` can only be used in a test").into());
+                }
+                self.resolve_expression(&assert.expr)
+            }
+        }
+    }
+
+    fn resolve_command(&mut self, cmd: &Command) {
+        for (_, expr) in &cmd.start {
+            self.resolve_expression(expr);
+        }
+    }
+
+    fn resolve_if_statement(&mut self, if_stmt: &IfStatement) {
+        self.resolve_expression(&if_stmt.expr);
+        self.resolve_block(&if_stmt.block);
+
+        if let Some(else_if) = if_stmt.else_if.as_ref() {
+            self.resolve_if_statement(else_if);
+        } else if let Some(else_block) = &if_stmt.else_block {
+            self.resolve_block(else_block);
+        }
+    }
+
+    fn resolve_variable_assignment(&mut self, assignment: &VariableAssignment) {
+        // The value is resolved first, so that `$a := $a + 1` refers
+        // to the previous `$a`.
+        self.resolve_expression(&assignment.value);
+        let name = &assignment.signature.name;
+        if let Some(previous) = self.peek_scope().variables.insert(name.clone(), assignment.position.clone()) {
+            let declaration = Declaration { name: name.clone(), position: previous };
+            self.resolutions.reassignments.insert(assignment.position.clone(), declaration);
+        }
+    }
+
+    fn resolve_test(&mut self, test: &Test) {
+        if self.scopes.len() > 1 {
+            self.errors.push((test.position.clone(), "tests must be declared at the top level").into());
+        }
+
+        // Tests are run on their own, once the top level has been, so they
+        // can't read its runtime variables.
+        let outer = self.test_scope.replace(self.scopes.len());
+        self.resolve_block(&test.block);
+        self.test_scope = outer;
+    }
+
+    fn resolve_function(&mut self, func: &Function) {
+        self.scopes.push(Scope::default());
+        for arg in &func.signature.args {
+            self.peek_scope().variables.insert(arg.name.clone(), func.position.clone());
+        }
+        self.resolve_statements(&func.block);
+        if let Some(result) = &func.result {
+            self.resolve_expression(&result.expr);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_function_call(&mut self, call: &FunctionCall) {
+        let overloads: Vec<_> = self.scopes.iter()
+            .rev()
+            .filter_map(|scope| scope.functions.get(&call.name))
+            .flatten()
+            .cloned()
+            .collect();
+
+        if overloads.is_empty() {
+            let candidates = self.scopes.iter()
+                .flat_map(|scope| scope.functions.keys())
+                .cloned()
+                .collect();
+            let error = undefined_error("function", &call.name, call.position.clone(), candidates);
+            self.errors.push(error);
+        } else {
+            self.resolutions.functions.insert(call.position.clone(), overloads);
+        }
+
+        for arg in &call.args {
+            self.resolve_expression(arg);
+        }
+
+        // Like in the generator, inner scopes shadow the functions of outer
+        // scopes with the exact same signature.
+        let mut expansions: Vec<(usize, Function)> = vec![];
+        for (depth, scope) in self.scopes.iter().enumerate().rev() {
+            for func in &scope.macros {
+                let signature = &func.signature;
+                let shadowed = expansions.iter().any(|(_, other)| other.signature == *signature);
+                if signature.name == call.name && signature.args.len() == call.args.len() && !shadowed {
+                    expansions.push((depth, func.clone()));
+                }
+            }
+        }
+        for (depth, func) in expansions {
+            self.expand(depth, &func, call);
+        }
+    }
+
+    /// Resolves the body of the macro `func`, declared in the scope at
+    /// `depth`, where `call` expands it. Like functions, macros are scoped
+    /// lexically: they see the scopes around their declaration, as they
+    /// are when they are expanded, but not the ones of the caller.
+    fn expand(&mut self, depth: usize, func: &Function, call: &FunctionCall) {
+        // A recursive macro would be expanded forever, which the generator
+        // reports.
+        if self.expanding.contains(&func.position) {
+            return;
+        }
+        self.expanded.insert(func.position.clone());
+
+        let errors = self.errors.len();
+        let caller_scopes = self.scopes.split_off(depth + 1);
+        let generated_first = self.generated_first.clone();
+        self.generated_first.retain(|scope| *scope <= depth);
+        self.expanding.push(func.position.clone());
+        self.resolve_statement_at(&Statement::FunctionDeclaration(func.clone()));
+        self.expanding.pop();
+        self.generated_first = generated_first;
+        self.scopes.extend(caller_scopes);
+
+        // A body expanded several times is reported once.
+        for error in self.errors.split_off(errors) {
+            let reported = self.errors.iter().any(|other| other.position == error.position && other.error == error.error);
+            if !reported {
+                let note = format!("note: in the expansion of `{}` at {}",
+                                   func.signature, source_location(self.sources, &call.position));
+                self.errors.push(error.with_note(note));
+            }
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Sum(operands, _) => {
+                for operand in operands {
+                    self.resolve_expression(operand);
+                }
+            },
+            Expression::Summand(summand, _) => self.resolve_summand(summand),
+            Expression::Boolean(_, _) => {},
+            Expression::Equality(left, right, _) => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+        }
+    }
+
+    fn resolve_summand(&mut self, summand: &Summand) {
+        match summand {
+            Summand::Multiplication(terms) => {
+                for term in terms {
+                    self.resolve_term(term);
+                }
+            },
+            Summand::Term(term) => self.resolve_term(term)
+        }
+    }
+
+    fn resolve_term(&mut self, term: &Term) {
+        match term {
+            Term::Variable(var, position) => self.resolve_variable(var, position),
+            Term::FunctionCall(call) => self.resolve_function_call(call),
+            Term::Expression(expr) => self.resolve_expression(expr),
+            Term::Number(_) | Term::String(_) => {}
+        }
+    }
+
+    fn resolve_variable(&mut self, var: &VariableName, position: &Position) {
+        let declaration = self.scopes.iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, scope)| scope.variables.get(var).map(|declaration| (i, declaration)));
+
+        if let (Some((scope, _)), Some(test_scope)) = (declaration, self.test_scope) {
+            if var.is_dynamic() && scope < test_scope {
+                let error = CompilerError::from((position.clone(), format!("can't use `{}` in a test", var)))
+                    .with_note("note: runtime variables declared outside of a test don't exist while it runs");
+                self.errors.push(error);
+                return;
+            }
+        }
+
+        // A function generated first would read the variable declared
+        // before its block, while the block declares its own later on.
+        let shadowed_later = declaration.is_some_and(|(scope, _)| self.generated_first.iter()
+            .any(|block| scope < *block && self.scopes[*block].assigned.contains(var)));
+
+        if shadowed_later {
+            let (_, declaration) = declaration.unwrap();
+            let error = CompilerError::from((position.clone(), format!("ambiguous variable `{}`", var)))
+                .with_note(format!("note: `{}` is assigned after the functions of its block are generated", var))
+                .with_note(format!("note: they would use the `{}` declared at {}", var, source_location(self.sources, declaration)));
+            self.errors.push(error);
+        } else if let Some((_, declaration)) = declaration {
+            let declaration = Declaration { name: var.clone(), position: declaration.clone() };
+            self.resolutions.variables.insert(position.clone(), declaration);
+        } else {
+            let candidates = self.scopes.iter()
+                .flat_map(|scope| scope.variables.keys())
+                .cloned()
+                .collect();
+            let mut error = undefined_error("variable", var, position.clone(), candidates);
+            if self.scopes.iter().any(|scope| scope.assigned.contains(var)) {
+                error = error.with_note(format!(
+                    "note: `{}` is assigned after the functions of its block are generated", var
+                ));
+            }
+            self.errors.push(error);
+        }
+    }
+
+    fn declare_function(&mut self, func: &Function) {
+        self.peek_scope().functions
+            .entry(func.signature.name.clone())
+            .or_default()
+            .push((func.signature.clone(), func.position.clone()));
+    }
+
+    fn peek_scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("no scope")
+    }
+}
+
+/// Whether `func` is generated where it is declared, before the other
+/// statements of its block, rather than expanded where it is called.
+fn is_generated_first(func: &Function) -> bool {
+    func.is_dynamic() && func.signature.get_static_args().is_empty()
+}
+
+fn undefined_error(kind: &str, name: &VariableName, position: Position, candidates: Vec<VariableName>) -> CompilerError {
+    let mut error = CompilerError::from((position, format!("unknown {} `{}`", kind, name)));
+
+    let counterpart = match name {
+        VariableName::Dynamic(name) => VariableName::Static(name.clone()),
+        VariableName::Static(name) => VariableName::Dynamic(name.clone())
+    };
+    if candidates.contains(&counterpart) {
+        let staticness = if counterpart.is_static() { "static" } else { "dynamic" };
+        return error.with_note(format!("note: there is a {} {} named `{}`", staticness, kind, counterpart));
+    }
+
+    if let Some(suggestion) = suggest(name, &candidates) {
+        error = error.with_note(format!("help: did you mean `{}`?", suggestion));
+    }
+
+    error
+}
+
+/// Finds the candidate with the same staticness closest to `name`, if any is
+/// close enough to be a plausible typo.
+fn suggest<'a>(name: &VariableName, candidates: &'a [VariableName]) -> Option<&'a VariableName> {
+    let name_str = name.get_name();
+    let max_distance = std::cmp::max(1, name_str.chars().count() / 3);
+
+    candidates.iter()
+        .filter(|candidate| candidate.is_static() == name.is_static())
+        .map(|candidate| (edit_distance(name_str, candidate.get_name()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by(|(d1, c1), (d2, c2)| d1.cmp(d2).then_with(|| c1.get_name().cmp(c2.get_name())))
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use nom::Finish;
+
+    fn errors(src: &str) -> Vec<CompilerError> {
+        let (_, ast) = parse(src).finish().unwrap();
+        let sources = [Source { name: "test.tag".into(), text: src.into() }];
+        resolve(&ast, &sources).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("msg", "msg"), 0);
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let errors = errors("message := \"hi\";\n/say #{mesage}\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error, "unknown variable `mesage`");
+        assert_eq!(errors[0].position.line, 2);
+        assert_eq!(errors[0].notes, vec!["help: did you mean `message`?"]);
+    }
+
+    #[test]
+    fn test_other_staticness() {
+        let errors = errors("def $log($content) {\n/say hi\n}\nlog(1);\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error, "unknown function `log`");
+        assert_eq!(errors[0].notes, vec!["note: there is a dynamic function named `$log`"]);
+    }
+
+    #[test]
+    fn test_scoping() {
+        assert!(errors("$a := 1;\nif true {\n$b := $a;\n}\n$c := $b;\n").len() == 1);
+        assert!(errors("$f();\ndef $f() {\n/say hi\n}\n").is_empty());
+    }
+
+    #[test]
+    fn test_generation_order() {
+        // `$f` is generated before `greeting` is assigned.
+        let src = "greeting := \"hi\";\ndef $f() {\n/say #{greeting}\n}\n$f();\n";
+        let early = errors(src);
+        assert_eq!(early.len(), 1);
+        assert_eq!(early[0].error, "unknown variable `greeting`");
+        assert_eq!(early[0].position.line, 3);
+        assert_eq!(early[0].notes, vec!["note: `greeting` is assigned after the functions of its block are generated"]);
+        assert!(crate::compile_str(src, Default::default()).is_err());
+
+        // Macros are expanded where they are called, and the functions of
+        // a nested block are generated once the statements before it are.
+        assert!(errors("greeting := \"hi\";\ndef $f(n) {\n/say #{greeting}\n}\n$f(1);\n").is_empty());
+        let nested = "greeting := \"hi\";\nif true {\ndef $f() {\n/say #{greeting}\n}\n$f();\n}\n";
+        assert!(errors(nested).is_empty());
+        assert!(crate::compile_str(nested, Default::default()).is_ok());
+
+        // A function generated first would read `greeting` from the top
+        // level, while it is called once its block has shadowed it.
+        let shadowed = "greeting := \"hi\";\nif true {\ndef $f() {\n/say #{greeting}\n}\ngreeting := \"yo\";\n$f();\n}\n";
+        let shadowed = errors(shadowed);
+        assert_eq!(shadowed.len(), 1);
+        assert_eq!(shadowed[0].error, "ambiguous variable `greeting`");
+        assert_eq!(shadowed[0].notes, vec![
+            "note: `greeting` is assigned after the functions of its block are generated",
+            "note: they would use the `greeting` declared at test.tag:1:1"
+        ]);
+    }
+
+    #[test]
+    fn test_expansion_scoping() {
+        // Macros are resolved where they are expanded, which is before `$a`
+        // is assigned when it is in a function generated first.
+        let src = "$a := 1;\ndef $m(n) {\n/say #{$a}\n}\ndef $g() {\n$m(1);\n}\n$g();\n";
+        let early = errors(src);
+        assert_eq!(early.len(), 1);
+        assert_eq!(early[0].error, "unknown variable `$a`");
+        assert_eq!(early[0].position.line, 3);
+        assert_eq!(early[0].notes, vec![
+            "note: `$a` is assigned after the functions of its block are generated",
+            "note: in the expansion of `$m(n)` at test.tag:6:1"
+        ]);
+        assert!(crate::compile_str(src, Default::default()).is_err());
+
+        let src = "a := 1;\ndef m() {\n/say #{a}\n}\ndef $g() {\nm();\n}\n$g();\n";
+        let early = errors(src);
+        assert_eq!(early.len(), 1);
+        assert_eq!(early[0].error, "unknown variable `a`");
+        assert_eq!(early[0].notes, vec![
+            "note: `a` is assigned after the functions of its block are generated",
+            "note: in the expansion of `m()` at test.tag:6:1"
+        ]);
+        assert!(crate::compile_str(src, Default::default()).is_err());
+
+        // Macros see the variables around their declaration rather than
+        // the ones of their caller, even declared after them.
+        assert!(errors("def m() {\n/say #{a}\n}\na := 1;\nm();\n").is_empty());
+        assert_eq!(errors("def m() {\n/say #{b}\n}\nif true {\nb := 1;\nm();\n}\n").len(), 1);
+    }
+}
@@ -1,24 +1,73 @@
 use crate::parser::function::FunctionCall;
 use crate::parser::statement::{Statement, IfStatement};
 use crate::parser::statement::VariableSignature;
-use std::collections::HashSet;
 use crate::parser::function::FunctionSignature;
 use crate::parser::function::Function;
 use crate::parser::expression::{VariableName, Expression};
 use crate::generator::Generator;
 use crate::parser::statement::VariableAssignment;
 use std::collections::HashMap;
+use std::hash::Hash;
+use serde_json::json;
 use crate::generator::staticness::IsStatic;
 use crate::parser::typing::Typing;
-use crate::generator::simplify::Simplify;
-use std::cmp::Ordering;
 use crate::errors::CompilerError;
+use crate::{source_location, Source};
+use crate::parser::Position;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(in super) struct Scope {
-    runtime_variables: HashMap<VariableName, Typing>,
-    comptime_variables: HashMap<VariableName, Expression>,
-    functions: HashMap<FunctionSignature, (Function, Option<String>)>
+    #[serde(with = "crate::cache::pairs")]
+    pub(in super) runtime_variables: HashMap<VariableName, Typing>,
+    #[serde(with = "crate::cache::pairs")]
+    pub(in super) comptime_variables: HashMap<VariableName, Expression>,
+    #[serde(with = "crate::cache::pairs")]
+    pub(in super) functions: HashMap<FunctionSignature, (Function, Option<String>)>
+}
+
+impl Scope {
+    /// What the code generated in the scope can read from it, in a stable
+    /// order, for [crate::generator::generate_incremental]. Calls to a
+    /// dynamic function only refer to it by name, while the body of a macro
+    /// is expanded where it is called.
+    pub(in super) fn inputs(&self) -> Vec<String> {
+        let runtime = self.runtime_variables.iter()
+            .map(|(name, typing)| json!(["runtime", name, typing]));
+        let comptime = self.comptime_variables.iter()
+            .map(|(name, value)| json!(["static", name, value]));
+        let functions = self.functions.iter().map(|(signature, (function, name))| match name {
+            Some(name) => json!(["function", signature, name]),
+            None => json!(["macro", function])
+        });
+
+        let mut inputs: Vec<_> = runtime.chain(comptime).chain(functions).map(|input| input.to_string()).collect();
+        inputs.sort();
+        inputs
+    }
+
+    /// The entries set in the scope since it was `before`.
+    pub(in super) fn changes_since(&self, before: &Scope) -> Scope {
+        fn changes<K: Clone + Eq + Hash, V: Clone + PartialEq>(after: &HashMap<K, V>, before: &HashMap<K, V>) -> HashMap<K, V> {
+            after.iter()
+                .filter(|(key, value)| before.get(key) != Some(value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        }
+
+        Scope {
+            runtime_variables: changes(&self.runtime_variables, &before.runtime_variables),
+            comptime_variables: changes(&self.comptime_variables, &before.comptime_variables),
+            functions: changes(&self.functions, &before.functions)
+        }
+    }
+
+    /// Sets the entries of `changes`, see [Scope::changes_since].
+    pub(in super) fn extend(&mut self, changes: Scope) {
+        self.runtime_variables.extend(changes.runtime_variables);
+        self.comptime_variables.extend(changes.comptime_variables);
+        self.functions.extend(changes.functions);
+    }
 }
 
 impl Generator {
@@ -36,12 +85,12 @@ impl Generator {
 
     pub fn push_scope(&mut self) {
         self.push_static_scope();
-        self.write("data modify storage tag:runtime vars append value {}");
+        self.write(format!("data modify storage {} vars append value {{}}", self.storage));
     }
 
     pub fn pop_scope(&mut self) {
         self.pop_static_scope();
-        self.write("data remove storage tag:runtime vars[-1]");
+        self.write(format!("data remove storage {} vars[-1]", self.storage));
     }
 
     pub(in super) fn peek_scope(&mut self) -> &mut Scope {
@@ -59,23 +108,19 @@ impl Generator {
             return Err(CompilerError::from((assignment.position, "can't assign a dynamic value to a static variable")));
         }
 
-        // we'll have to resolve static variables here, but we need to do
-        // type inference and other things first.
-        // it'll just crash if we try to do something like `a := a + 1` for now.
-        // let expr = assignment.value.simplify(self).expect("can't resolve static variable");
-
+        let value = assignment.value.evaluate(self)?;
         let scope = self.peek_scope();
-        scope.comptime_variables.insert(assignment.signature.name, assignment.value);
+        scope.comptime_variables.insert(assignment.signature.name, value);
 
         Ok(())
     }
 
-    pub fn get_variable_nbt_path(&self, var: &VariableName) -> String {
-        let index = -1 - self.scopes.iter()
+    pub fn get_variable_nbt_path(&self, var: &VariableName, position: &Position) -> Result<String, CompilerError> {
+        let index = self.scopes.iter()
             .rev()
             .position(|scope| scope.runtime_variables.contains_key(var))
-            .unwrap() as i32;
-        format!("vars[{}].\"{}\"", index, var.get_name())
+            .ok_or_else(|| CompilerError::from((position.clone(), format!("unknown variable `{}`", var))))?;
+        Ok(format!("vars[{}].\"{}\"", -1 - index as i32, var.get_name()))
     }
 
     pub fn get_static_variable_value(&self, var: &VariableName) -> Option<Expression> {
@@ -90,7 +135,11 @@ impl Generator {
         scope.functions.insert(function.signature.clone(), (function, file_name));
     }
 
-    pub fn resolve_function_call(&self, call: &FunctionCall) -> Option<&(Function, Option<String>)> {
+    /// Picks the overload `call` refers to, with the index of the scope
+    /// declaring it. Ambiguities are reported for the calls being generated
+    /// only: declaring overloads that no call can tell apart isn't an error
+    /// until one of them is called.
+    pub fn resolve_function_call(&self, call: &FunctionCall) -> Result<(usize, &(Function, Option<String>)), CompilerError> {
         // To resolve a function, we check if the call signature is the same
         // as the function signature.
         // However, compile-time (=static) variables can also be used as dynamic
@@ -98,44 +147,83 @@ impl Generator {
         // we associate a "score". A compile-time value matching with a dynamic
         // argument won't add to the score, while a compile-time value matching
         // with a static argument will.
-        // TODO: other factors will be type matching (i.e. the stricter the type is,
-        // the more important it is).
+        // Typed arguments work the same way: a value matching the declared type
+        // adds to the score, so the stricter the type is, the more important it is.
 
         type Info = (Function, Option<String>);
 
-        let candidates: Vec<(&Info, i32)> = self.scopes.iter().rev().map(|scope| {
-            scope.functions.iter().filter_map(|(sign, info)| {
-                let mut score = 0;
+        // Inner scopes shadow the functions of outer scopes with the exact same signature.
+        let mut overloads: Vec<(usize, &Info)> = vec![];
+        for (depth, scope) in self.scopes.iter().enumerate().rev() {
+            for (sign, info) in &scope.functions {
+                if sign.name == call.name && !overloads.iter().any(|(_, other)| other.0.signature == *sign) {
+                    overloads.push((depth, info));
+                }
+            }
+        }
+        // Sorted by declaration, so that the notes listing them are in a
+        // stable order.
+        overloads.sort_by_key(|(_, info)| (info.0.position.file, info.0.position.offset));
+
+        let candidates: Vec<((usize, &Info), i32)> = overloads.iter().filter_map(|&(depth, info)| {
+            let sign = &info.0.signature;
+            let mut score = 0;
+
+            if sign.args.len() != call.args.len() {
+                return None;
+            }
 
-                if info.0.signature.name != call.name {
+            for (sign_arg, call_arg) in sign.args.iter().zip(&call.args) {
+                if sign_arg.is_static() && call_arg.is_dynamic() {
                     return None;
                 }
 
-                for (sign_arg, call_arg) in sign.args.iter().zip(&call.args) {
-                    if sign_arg.is_static() && call_arg.is_dynamic() {
-                        return None;
-                    }
+                if !self.expr_matches_typing(call_arg, &sign_arg.typing) {
+                    return None;
+                }
 
-                    // TODO: check type
+                if sign_arg.is_static() && call_arg.is_static() {
+                    score += 1;
+                }
 
-                    if sign_arg.is_static() && call_arg.is_static() {
-                        score += 1;
-                    }
+                if sign_arg.typing != Typing::Unknown {
+                    score += 1;
                 }
+            }
 
-                Some((info, score))
-            }).collect::<Vec<(&Info, i32)>>()
-        }).flatten().collect::<Vec<(&Info, i32)>>();
+            Some(((depth, info), score))
+        }).collect();
 
-        candidates.iter().max_by(|(_, score1), (_, score2)| score1.cmp(score2))
-            .map(|candidate| candidate.0)
+        let best_score = candidates.iter().map(|(_, score)| *score).max();
+        let best: Vec<(usize, &Info)> = candidates.iter()
+            .filter(|(_, score)| Some(*score) == best_score)
+            .map(|(overload, _)| *overload)
+            .collect();
+
+        match best.as_slice() {
+            [overload] => Ok(*overload),
+            [] => {
+                let error = format!("no matching overload for `{}` with {} argument(s)", call.name, call.args.len());
+                Err(overloads.iter().fold(
+                    CompilerError::from((call.position.clone(), error)),
+                    |error, (_, info)| error.with_note(describe_candidate("candidate", &info.0, &self.sources))
+                ))
+            },
+            _ => {
+                let error = format!("ambiguous call to `{}`", call.name);
+                Err(best.iter().fold(
+                    CompilerError::from((call.position.clone(), error)),
+                    |error, (_, info)| error.with_note(describe_candidate("could be", &info.0, &self.sources))
+                ))
+            }
+        }
     }
 
-    pub fn requires_scope(statements: &Vec<Statement>) -> bool {
+    pub fn requires_scope(statements: &[Statement]) -> bool {
         fn if_statement_requires_scope(r#if: &IfStatement) -> bool {
             Generator::requires_scope(&r#if.block)
                 || Generator::requires_scope(r#if.else_block.as_ref().unwrap_or(&vec![]))
-                || (*r#if.else_if).as_ref().map_or(false, |r#if| if_statement_requires_scope(r#if))
+                || (*r#if.else_if).as_ref().is_some_and(if_statement_requires_scope)
         }
 
         statements.iter().any(|statement| match statement {
@@ -146,3 +234,77 @@ impl Generator {
         })
     }
 }
+
+fn describe_candidate(label: &str, function: &Function, sources: &[Source]) -> String {
+    format!("note: {} `{}`, declared at {}", label, function.signature, source_location(sources, &function.position))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_sources, compile_str, CompileOptions, Diagnostic, Source};
+
+    fn options() -> CompileOptions {
+        CompileOptions { namespace: "test".into(), ..Default::default() }
+    }
+
+    fn error(src: &str) -> Diagnostic {
+        let mut errors = compile_str(src, options()).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        errors.remove(0)
+    }
+
+    #[test]
+    fn test_arity() {
+        let src = "def $f($a) {\n/say one\n}\ndef $f($a, $b) {\n/say two\n}\n$f(1, 2);\n$f(1, 2, 3);\n";
+        let error = error(src);
+        assert_eq!(error.error, "no matching overload for `$f` with 3 argument(s)");
+        assert_eq!((error.position.line, error.position.column), (8, 1));
+        assert_eq!(error.notes, vec![
+            "note: candidate `$f($a)`, declared at <input>:1:1",
+            "note: candidate `$f($a, $b)`, declared at <input>:4:1"
+        ]);
+    }
+
+    #[test]
+    fn test_scores() {
+        // A static argument wins over a dynamic one, and a typed one over
+        // an untyped one.
+        let src = "def $f($a) {\n/say dynamic\n}\ndef $f(a) {\n/say static\n}\n\
+                   def $g($a) {\n/say any\n}\ndef $g($a: string) {\n/say string\n}\n\
+                   $f(1);\n$g(\"x\");\n";
+        let datapack = compile_str(src, options()).unwrap();
+        let called: Vec<_> = datapack.functions["0"].iter()
+            .filter_map(|line| line.strip_prefix("function test:"))
+            .filter_map(|name| datapack.functions[name].iter().find(|line| line.starts_with("say ")))
+            .collect();
+        assert_eq!(called, ["say static", "say string"]);
+    }
+
+    #[test]
+    fn test_ambiguous_call() {
+        let error = error("def $f($a: int) {\n/say int\n}\ndef $f(b) {\n/say static\n}\n$f(1);\n");
+        assert_eq!(error.error, "ambiguous call to `$f`");
+        assert_eq!(error.notes, vec![
+            "note: could be `$f($a: int)`, declared at <input>:1:1",
+            "note: could be `$f(b)`, declared at <input>:4:1"
+        ]);
+
+        // Declarations are only checked against the calls generated.
+        assert!(compile_str("def $f($a: int) {\n/say int\n}\ndef $f(b) {\n/say static\n}\n", options()).is_ok());
+    }
+
+    #[test]
+    fn test_candidates_order() {
+        // Candidates are listed in the order of the sources, then of their
+        // declarations.
+        let sources = [
+            Source { name: "a.tag".into(), text: "\n\n\n\ndef $f($a) {\n/say one\n}\n".into() },
+            Source { name: "b.tag".into(), text: "def $f($a, $b) {\n/say two\n}\n$f();\n".into() }
+        ];
+        let errors = compile_sources(&sources, options()).unwrap_err();
+        assert_eq!(errors[0].notes, vec![
+            "note: candidate `$f($a)`, declared at a.tag:5:1",
+            "note: candidate `$f($a, $b)`, declared at b.tag:1:1"
+        ]);
+    }
+}
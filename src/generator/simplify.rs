@@ -1,7 +1,7 @@
 use crate::parser::expression::Summand;
 use crate::parser::expression::Term;
 use crate::parser::expression::Expression;
-use crate::generator::Generator;
+use crate::generator::{Generator, nbt_bool};
 use crate::errors::CompilerError;
 
 pub trait Simplify<T> {
@@ -19,23 +19,52 @@ impl Expression {
         let as_bool = self.simplify(ctx).map(|bl: bool| bl.to_string());
 
         as_string.or(as_i32).or(as_bool)
-            .map_err(|err| CompilerError {
-                error: err.to_string(),
-                position: self.pos().clone()
-            })
+            .map_err(|err| (self.pos().clone(), err).into())
     }
+
+    /// Simplifies the value down to a literal, so that a static variable
+    /// doesn't depend on the variables it was computed from.
+    pub fn evaluate(&self, ctx: &Generator) -> Result<Expression, CompilerError> {
+        let pos = self.pos().clone();
+        let as_string = self.simplify(ctx)
+            .map(|str: String| Expression::Summand(Summand::Term(Term::String(str)), pos.clone()));
+        let as_i32 = self.simplify(ctx)
+            .map(|i: i32| Expression::Summand(Summand::Term(Term::Number(i)), pos.clone()));
+        let as_bool = self.simplify(ctx)
+            .map(|bl: bool| Expression::Boolean(bl, pos.clone()));
+
+        as_string.or(as_i32).or(as_bool)
+            .map_err(|err| (pos.clone(), err).into())
+    }
+
+    /// Converts the simplified value to the NBT it is stored as at runtime.
+    /// It only differs from [Expression::to_string] for booleans,
+    /// see [crate::generator::nbt_bool].
+    pub fn to_nbt(&self, ctx: &Generator) -> Result<String, CompilerError> {
+        match self.simplify(ctx) {
+            Ok(bl) => Ok(nbt_bool(bl).to_string()),
+            Err(_) => self.to_string(ctx)
+        }
+    }
+}
+
+/// Whether both sides simplify to the same `T`.
+fn equals<'a, T: PartialEq>(left: &Expression, right: &Expression, ctx: &'a Generator) -> Result<bool, &'a str>
+    where Expression: Simplify<T>
+{
+    let left: T = left.simplify(ctx)?;
+    let right: T = right.simplify(ctx)?;
+    Ok(left == right)
 }
 
 impl Simplify<String> for Expression {
     fn simplify<'a>(&self, ctx: &'a Generator) -> Result<String, &'a str> {
         match self {
-            Expression::Sum(summand, expr, _) => {
-                let str1: String = summand.simplify(ctx)?;
-                let str2: String = expr.simplify(ctx)?;
-                Ok(str1 + str2.as_str())
-            }
+            Expression::Sum(operands, _) => operands.iter()
+                .try_fold(String::new(), |str, operand| Ok(str + <Expression as Simplify<String>>::simplify(operand, ctx)?.as_str())),
             Expression::Summand(summand, _) => summand.simplify(ctx),
-            Expression::Boolean(_, _) => Err("can't convert a boolean to a string")
+            Expression::Boolean(_, _) => Err("can't convert a boolean to a string"),
+            Expression::Equality(_, _, _) => Err("can't convert a comparison to a string")
         }
     }
 }
@@ -43,7 +72,7 @@ impl Simplify<String> for Expression {
 impl Simplify<String> for Summand {
     fn simplify<'a>(&self, ctx: &'a Generator) -> Result<String, &'a str> {
         match self {
-            Summand::Multiplication(_, _) => Err("can't multiply a string"),
+            Summand::Multiplication(_) => Err("can't multiply a string"),
             Summand::Term(term) => term.simplify(ctx)
         }
     }
@@ -54,7 +83,7 @@ impl Simplify<String> for Term {
         match self {
             Term::String(str) => Ok(str.clone()),
             Term::FunctionCall(_) => Err("todo: function call -> string"),
-            Term::Variable(var) => ctx.get_static_variable_value(var)
+            Term::Variable(var, _) => ctx.get_static_variable_value(var)
                 .ok_or("unknown variable")?
                 .simplify(ctx),
             Term::Expression(expr) => expr.simplify(ctx),
@@ -68,11 +97,11 @@ impl Simplify<String> for Term {
 impl Simplify<i32> for Expression {
     fn simplify<'a>(&self, ctx: &'a Generator) -> Result<i32, &'a str> {
         match self {
-            Expression::Sum(summand, expr, _) =>
-                Ok(<Summand as Simplify<i32>>::simplify(summand, ctx)? +
-                    <Expression as Simplify<i32>>::simplify(expr, ctx)?),
+            Expression::Sum(operands, _) => operands.iter()
+                .try_fold(0, |sum: i32, operand| Ok(sum.wrapping_add(operand.simplify(ctx)?))),
             Expression::Summand(summand, _) => summand.simplify(ctx),
-            Expression::Boolean(_, _) => Err("can't resolve a boolean into an i32")
+            Expression::Boolean(_, _) => Err("can't resolve a boolean into an i32"),
+            Expression::Equality(_, _, _) => Err("can't resolve a comparison into an i32")
         }
     }
 }
@@ -80,9 +109,8 @@ impl Simplify<i32> for Expression {
 impl Simplify<i32> for Summand {
     fn simplify<'a>(&self, ctx: &'a Generator) -> Result<i32, &'a str> { 
         match self {
-            Summand::Multiplication(term, summand) => 
-                Ok(<Term as Simplify<i32>>::simplify(term, ctx)? *
-                    <Summand as Simplify<i32>>::simplify(summand, ctx)?),
+            Summand::Multiplication(terms) => terms.iter()
+                .try_fold(1, |product: i32, term| Ok(product.wrapping_mul(term.simplify(ctx)?))),
             Summand::Term(term) => term.simplify(ctx)
         }
     }
@@ -93,10 +121,10 @@ impl Simplify<i32> for Term {
         match self {
             Term::Number(n) => Ok(*n),
             Term::Expression(expr) => expr.simplify(ctx),
-            Term::Variable(var) => ctx.get_static_variable_value(var)
+            Term::Variable(var, _) => ctx.get_static_variable_value(var)
                 .ok_or("unknown variable")?
                 .simplify(ctx),
-            Term::FunctionCall(call) => Err("todo"),
+            Term::FunctionCall(_call) => Err("todo"),
             Term::String(_) => Err("can't convert a string to an i32")
         }
     }
@@ -109,6 +137,12 @@ impl Simplify<bool> for Expression {
         match self {
             Expression::Boolean(bl, _) => Ok(*bl),
             Expression::Summand(summand, _) => summand.simplify(ctx),
+            Expression::Equality(left, right, _) => {
+                equals::<i32>(left, right, ctx)
+                    .or_else(|_| equals::<String>(left, right, ctx))
+                    .or_else(|_| equals::<bool>(left, right, ctx))
+                    .map_err(|_| "can't compare values of different types")
+            },
             _ => Err("can't resolve to a boolean")
         }
     }
@@ -127,7 +161,7 @@ impl Simplify<bool> for Term {
     fn simplify<'a>(&self, ctx: &'a Generator) -> Result<bool, &'a str> { 
         match self {
             Term::Expression(expr) => expr.simplify(ctx),
-            Term::Variable(var) => {
+            Term::Variable(var, _) => {
                 let expr = ctx.get_static_variable_value(var).ok_or("unknown variable")?;
                 expr.simplify(ctx)
             },
@@ -135,3 +169,22 @@ impl Simplify<bool> for Term {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile_str, CompileOptions};
+
+    fn options() -> CompileOptions {
+        CompileOptions { namespace: "test".into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_static_values() {
+        // Static variables hold the value they were assigned, rather than
+        // the expression it was computed from, which may refer to the
+        // variable itself or to one reassigned since.
+        let src = "a := 1;\na := a + 1;\nb := a * 10;\na := 5;\n/say #{a} #{b}\n";
+        let datapack = compile_str(src, options()).unwrap();
+        assert!(datapack.functions["0"].contains(&"say 5 20".to_string()), "{:?}", datapack.functions["0"]);
+    }
+}
@@ -3,8 +3,7 @@ use crate::parser::statement::VariableSignature;
 use crate::parser::function::FunctionSignature;
 use crate::parser::function::Function;
 use crate::parser::statement::IfStatement;
-use crate::parser::statement::Statement;
-use crate::generator::Generator;
+use crate::parser::statement::{Statement, Command};
 use crate::parser::expression::{Expression, Term, Summand, VariableName};
 use crate::parser::statement::VariableAssignment;
 
@@ -16,9 +15,10 @@ pub trait IsStatic {
 impl IsStatic for Expression {
     fn is_static(&self) -> bool {
         match self {
-            Expression::Sum(summand, expr, _) => summand.is_static() && expr.is_static(),
+            Expression::Sum(operands, _) => operands.iter().all(Expression::is_static),
             Expression::Summand(summand, _) => summand.is_static(),
-            Expression::Boolean(_, _) => true
+            Expression::Boolean(_, _) => true,
+            Expression::Equality(left, right, _) => left.is_static() && right.is_static()
         }
     }
 }
@@ -26,7 +26,7 @@ impl IsStatic for Expression {
 impl IsStatic for Summand {
     fn is_static(&self) -> bool {
         match self {
-            Summand::Multiplication(term, summand) => term.is_static() && summand.is_static(),
+            Summand::Multiplication(terms) => terms.iter().all(Term::is_static),
             Summand::Term(term) => term.is_static()
         }
     }
@@ -38,7 +38,7 @@ impl IsStatic for Term {
             Term::Number(_) => true,
             Term::Expression(expr) => expr.is_static(),
             Term::FunctionCall(call) => call.is_static(),
-            Term::Variable(var) => var.is_static(),
+            Term::Variable(var, _) => var.is_static(),
             Term::String(_) => true
         }
     }
@@ -69,7 +69,7 @@ impl IsStatic for Statement {
     fn is_static(&self) -> bool { 
         match self {
             Statement::IfStatement(if_stmt) => if_stmt.is_static(),
-            Statement::Command(_) => true,
+            Statement::Command(cmd) => cmd.is_static(),
             Statement::VariableAssignment(var) => var.is_static(),
             Statement::FunctionDeclaration(function) => function.is_static(),
             _ => false
@@ -77,6 +77,12 @@ impl IsStatic for Statement {
     }
 }
 
+impl IsStatic for Command {
+    fn is_static(&self) -> bool {
+        self.start.iter().all(|(_, expr)| expr.is_static())
+    }
+}
+
 impl IsStatic for Vec<Statement> {
     fn is_static(&self) -> bool { 
         self.iter().all(Statement::is_static)
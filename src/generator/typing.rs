@@ -1,26 +1,402 @@
-use crate::parser::expression::Expression;
+use crate::parser::{AST, Position};
+use crate::parser::expression::{Expression, Summand, Term, VariableName};
+use crate::parser::statement::{Statement, IfStatement, VariableAssignment};
+use crate::parser::function::FunctionCall;
 use crate::parser::typing::Typing;
 use crate::generator::Generator;
+use crate::generator::staticness::IsStatic;
+use crate::generator::resolve::Resolutions;
+use crate::errors::CompilerError;
+use std::collections::HashMap;
 
 impl Generator {
-    pub fn expr_matches_typing(&self, typing: Typing) -> bool {
-        // match typing {
-        //     Typing::Integer if let Expression:: => true ,
-        //     Typing::String => true
-        //     Typing::Unknown => true
-        // }
+    /// Whether `expr` can be used where a value of type `typing` is expected.
+    /// An unknown type on either side always matches.
+    pub fn expr_matches_typing(&self, expr: &Expression, typing: &Typing) -> bool {
+        match (typing, self.get_typing(expr)) {
+            (Typing::Unknown, _) | (_, Typing::Unknown) => true,
+            (expected, actual) => *expected == actual
+        }
+    }
+
+    pub fn get_typing(&self, expr: &Expression) -> Typing {
+        match expr {
+            Expression::Boolean(_, _) | Expression::Equality(_, _, _) => Typing::Boolean,
+            Expression::Summand(summand, _) => self.get_summand_typing(summand),
+            // Like the type checker, a side of unknown type is assumed to
+            // be of the type of the other.
+            Expression::Sum(operands, _) => {
+                operands.iter().rev().map(|operand| self.get_typing(operand)).reduce(|right, left| match (left, right) {
+                    (Typing::Unknown, typing) | (typing, Typing::Unknown) => typing,
+                    (left, right) if left == right => left,
+                    _ => Typing::Unknown
+                }).unwrap_or(Typing::Unknown)
+            }
+        }
+    }
+
+    fn get_summand_typing(&self, summand: &Summand) -> Typing {
+        match summand {
+            Summand::Multiplication(_) => Typing::Integer,
+            Summand::Term(term) => self.get_term_typing(term)
+        }
+    }
+
+    fn get_term_typing(&self, term: &Term) -> Typing {
+        match term {
+            Term::Number(_) => Typing::Integer,
+            Term::String(_) => Typing::String,
+            Term::Expression(expr) => self.get_typing(expr),
+            Term::Variable(var, _) => self.get_variable_typing(var),
+            Term::FunctionCall(call) => self.resolve_function_call(call).ok()
+                .and_then(|(_, (func, _))| func.result.as_ref())
+                .map_or(Typing::Unknown, |result| result.typing.clone())
+        }
+    }
+
+    fn get_variable_typing(&self, var: &VariableName) -> Typing {
+        if var.is_static() {
+            self.get_static_variable_value(var)
+                .map_or(Typing::Unknown, |value| self.get_typing(&value))
+        } else {
+            self.scopes.iter()
+                .rev()
+                .find_map(|scope| scope.runtime_variables.get(var))
+                .cloned()
+                .unwrap_or(Typing::Unknown)
+        }
+    }
+}
+
+/// Infers the type of every expression of the AST and checks them against
+/// the declared annotations, before any code gets generated.
+///
+/// The inferred types are written back to the variable declarations that
+/// don't have an annotation, so that the generator knows how each runtime
+/// variable is represented.
+pub fn check(ast: &mut AST, resolutions: &Resolutions) -> Result<(), Vec<CompilerError>> {
+    let (_, errors) = check_all(ast, resolutions);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The type of each declaration, keyed like [Declaration]s are.
+pub type Types = HashMap<(Position, VariableName), Typing>;
+
+/// Like [check], but also gives the type of every declaration, for tools
+/// working on sources being edited.
+pub fn check_all(ast: &mut AST, resolutions: &Resolutions) -> (Types, Vec<CompilerError>) {
+    let mut checker = TypeChecker {
+        resolutions,
+        types: HashMap::new(),
+        annotations: HashMap::new(),
+        results: HashMap::new(),
+        errors: vec![]
+    };
 
-        todo!()
+    checker.check_statements(&mut ast.statements);
+    (checker.types, checker.errors)
+}
+
+struct TypeChecker<'a> {
+    resolutions: &'a Resolutions,
+    types: Types,
+    /// The type each declaration is annotated with, which the assignments
+    /// of the variable in the same scope keep.
+    annotations: Types,
+    /// The type each function returns, by declaration, once its block has
+    /// been checked. Functions returning nothing map to `None`.
+    results: HashMap<Position, Option<Typing>>,
+    errors: Vec<CompilerError>
+}
+
+impl TypeChecker<'_> {
+    fn check_statements(&mut self, statements: &mut [Statement]) {
+        // Like the generator, the functions of a block are checked before
+        // its other statements, so that the calls know what they return.
+        let (functions, others): (Vec<_>, Vec<_>) = statements.iter_mut()
+            .partition(|statement| matches!(statement, Statement::FunctionDeclaration(_)));
+        for statement in functions.into_iter().chain(others) {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Command(cmd) => {
+                for (_, expr) in &cmd.start {
+                    self.infer(expr);
+                }
+            },
+            Statement::IfStatement(if_stmt) => self.check_if_statement(if_stmt),
+            Statement::VariableAssignment(assignment) => self.check_variable_assignment(assignment),
+            Statement::FunctionDeclaration(func) => {
+                for arg in &func.signature.args {
+                    self.types.insert((func.position.clone(), arg.name.clone()), arg.typing.clone());
+                    self.annotations.insert((func.position.clone(), arg.name.clone()), arg.typing.clone());
+                }
+                self.check_statements(&mut func.block);
+
+                let result = func.result.as_mut().map(|result| {
+                    result.typing = self.infer(&result.expr);
+                    result.typing.clone()
+                });
+                self.results.insert(func.position.clone(), result);
+            },
+            Statement::FunctionCall(call) => self.check_function_call(call),
+            Statement::Test(test) => self.check_statements(&mut test.block),
+            Statement::Assert(assert) => {
+                let typing = self.infer(&assert.expr);
+                self.expect(&Typing::Boolean, &typing, assert.expr.pos());
+            }
+        }
+    }
+
+    fn check_if_statement(&mut self, if_stmt: &mut IfStatement) {
+        let typing = self.infer(&if_stmt.expr);
+        self.expect(&Typing::Boolean, &typing, if_stmt.expr.pos());
+        self.check_statements(&mut if_stmt.block);
+
+        if let Some(else_if) = if_stmt.else_if.as_mut() {
+            self.check_if_statement(else_if);
+        } else if let Some(else_block) = &mut if_stmt.else_block {
+            self.check_statements(else_block);
+        }
+    }
+
+    fn check_variable_assignment(&mut self, assignment: &mut VariableAssignment) {
+        let typing = self.infer(&assignment.value);
+        let signature = &mut assignment.signature;
+
+        // Assigning a variable again without an annotation keeps the one
+        // it was declared with.
+        let declared = self.resolutions.reassignments.get(&assignment.position)
+            .and_then(|previous| self.annotations.get(&(previous.position.clone(), previous.name.clone())))
+            .filter(|annotation| signature.typing == Typing::Unknown && **annotation != Typing::Unknown)
+            .cloned();
+        match declared {
+            Some(annotation) => {
+                if typing != Typing::Unknown && typing != annotation {
+                    let error = format!("mismatched types: expected `{}`, found `{}`", annotation, typing);
+                    let note = format!("note: `{}` is declared as `{}`", signature.name, annotation);
+                    self.errors.push(CompilerError::from((assignment.value.pos().clone(), error)).with_note(note));
+                }
+                signature.typing = annotation;
+            },
+            None => self.expect(&signature.typing, &typing, assignment.value.pos())
+        }
+
+        self.annotations.insert((assignment.position.clone(), signature.name.clone()), signature.typing.clone());
+        if signature.typing == Typing::Unknown {
+            signature.typing = typing;
+        }
+
+        self.types.insert((assignment.position.clone(), signature.name.clone()), signature.typing.clone());
     }
 
-    fn get_typing(&self) -> Typing {
-        // match self {
-        //     Expression::Sum(_, _) => Typing::Unknown,
-        //     Expression::Summand(_) => Typing::Unknown,
-        //     Expression::Boolean(_) => Typing::Boolean,
-        //     Expression::Variable(var) => var.
-        // }
+    fn check_function_call(&mut self, call: &FunctionCall) {
+        let types: Vec<Typing> = call.args.iter().map(|arg| self.infer(arg)).collect();
+
+        // When there are several overloads, picking one is left to the generator,
+        // which reports the calls no overload can accept.
+        let overloads: Vec<_> = self.resolutions.functions.get(&call.position)
+            .into_iter()
+            .flatten()
+            .filter(|(signature, _)| signature.args.len() == call.args.len())
+            .collect();
+
+        if let [(signature, _)] = overloads.as_slice() {
+            for ((arg, typing), sign_arg) in call.args.iter().zip(&types).zip(&signature.args) {
+                self.expect(&sign_arg.typing, typing, arg.pos());
+            }
+        }
+    }
+
+    fn infer(&mut self, expr: &Expression) -> Typing {
+        match expr {
+            Expression::Boolean(_, _) => Typing::Boolean,
+            Expression::Equality(left, right, pos) => {
+                match (self.infer(left), self.infer(right)) {
+                    (left, right) if left != Typing::Unknown && right != Typing::Unknown && left != right => {
+                        self.error(pos, format!("can't compare `{}` with `{}`", left, right));
+                    },
+                    _ => {}
+                }
+                Typing::Boolean
+            },
+            Expression::Summand(summand, pos) => self.infer_summand(summand, pos),
+            Expression::Sum(operands, pos) => {
+                let typings: Vec<_> = operands.iter().map(|operand| self.infer(operand)).collect();
+
+                // Sums are checked from the right, each operand being added
+                // to the sum of the following ones, which is reported at the
+                // position of its first operand.
+                let mut typings = typings.into_iter().enumerate().rev();
+                let (_, mut sum) = typings.next().expect("a sum has operands");
+                for (i, typing) in typings {
+                    let pos = if i == 0 { pos } else { operands[i].pos() };
+                    sum = match (typing, sum) {
+                        (Typing::Boolean, _) | (_, Typing::Boolean) => {
+                            self.error(pos, "can't add booleans");
+                            Typing::Unknown
+                        },
+                        (Typing::Unknown, typing) | (typing, Typing::Unknown) => typing,
+                        (left, right) if left == right => left,
+                        (left, right) => {
+                            self.error(pos, format!("can't add `{}` to `{}`", right, left));
+                            Typing::Unknown
+                        }
+                    };
+                }
+                sum
+            }
+        }
+    }
+
+    fn infer_summand(&mut self, summand: &Summand, pos: &Position) -> Typing {
+        match summand {
+            Summand::Multiplication(terms) => {
+                let typings: Vec<_> = terms.iter().map(|term| self.infer_term(term)).collect();
+
+                // Like sums, each term is multiplied by the product of the
+                // following ones, which is an integer.
+                let is_integer = |typing: &Typing| matches!(typing, Typing::Integer | Typing::Unknown);
+                let mut typings = typings.into_iter().rev();
+                let mut product = typings.next().expect("a product has terms");
+                for typing in typings {
+                    if !is_integer(&typing) || !is_integer(&product) {
+                        self.error(pos, format!("can't multiply `{}` by `{}`", typing, product));
+                    }
+                    product = Typing::Integer;
+                }
+
+                Typing::Integer
+            },
+            Summand::Term(term) => self.infer_term(term)
+        }
+    }
+
+    fn infer_term(&mut self, term: &Term) -> Typing {
+        match term {
+            Term::Number(_) => Typing::Integer,
+            Term::String(_) => Typing::String,
+            Term::Expression(expr) => self.infer(expr),
+            Term::Variable(var, position) => self.resolutions.variables.get(position)
+                .and_then(|decl| self.types.get(&(decl.position.clone(), var.clone())))
+                .cloned()
+                .unwrap_or(Typing::Unknown),
+            Term::FunctionCall(call) => {
+                self.check_function_call(call);
+                self.infer_result(call)
+            }
+        }
+    }
+
+    /// The type of the value `call` returns, if all the overloads it may
+    /// call return values of the same type.
+    fn infer_result(&mut self, call: &FunctionCall) -> Typing {
+        // A function declared after its caller's block isn't checked yet.
+        let results: Option<Vec<_>> = self.resolutions.functions.get(&call.position)
+            .into_iter()
+            .flatten()
+            .filter(|(signature, _)| signature.args.len() == call.args.len())
+            .map(|(_, position)| self.results.get(position).cloned())
+            .collect();
+
+        match results {
+            Some(results) if !results.is_empty() && results.iter().all(Option::is_none) => {
+                self.error(&call.position, format!("`{}` doesn't return a value", call.name));
+                Typing::Unknown
+            },
+            Some(results) => {
+                let mut typings = results.into_iter().flatten();
+                let first = typings.next().unwrap_or(Typing::Unknown);
+                if typings.all(|typing| typing == first) { first } else { Typing::Unknown }
+            },
+            None => Typing::Unknown
+        }
+    }
+
+    fn expect(&mut self, expected: &Typing, actual: &Typing, pos: &Position) {
+        if *expected != Typing::Unknown && *actual != Typing::Unknown && expected != actual {
+            self.error(pos, format!("mismatched types: expected `{}`, found `{}`", expected, actual));
+        }
+    }
+
+    fn error<S: ToString>(&mut self, pos: &Position, error: S) {
+        self.errors.push((pos.clone(), error).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::generator::resolve::resolve;
+    use nom::Finish;
+
+    fn check_src(src: &str) -> Result<AST, Vec<CompilerError>> {
+        let (_, mut ast) = parse(src).finish().unwrap();
+        let resolutions = resolve(&ast, &[]).unwrap();
+        check(&mut ast, &resolutions).map(|_| ast)
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = check_src("$x := 2;\n$y := \"a\" * $x;\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error, "can't multiply `string` by `int`");
+        assert_eq!((errors[0].position.line, errors[0].position.column), (2, 7));
+
+        let errors = check_src("$x: int := \"a\";\n").unwrap_err();
+        assert_eq!(errors[0].error, "mismatched types: expected `int`, found `string`");
+    }
+
+    #[test]
+    fn test_reassignment() {
+        // Assigning a variable again keeps the type it was declared with,
+        // whether it is dynamic or static.
+        for src in ["$x: int := 1;\n$x := \"a\";\n/say #{$x}\n", "x: int := 1;\nx := \"a\";\n"] {
+            let errors = check_src(src).unwrap_err();
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert_eq!(errors[0].error, "mismatched types: expected `int`, found `string`");
+            assert_eq!((errors[0].position.line, errors[0].position.column), (2, 6 + src.starts_with('$') as usize));
+            assert_eq!(errors[0].notes.len(), 1);
+        }
+
+        // The annotation is kept through the assignments without one, and
+        // doesn't apply to the variables shadowing it.
+        assert!(check_src("$x: int := 1;\n$x := 2;\n$x := \"a\";\n").is_err());
+        assert!(check_src("def $f($a: int) {\n$a := \"a\";\n}\n").is_err());
+        assert!(check_src("$x: int := 1;\nif true {\n$x := \"a\";\n}\n").is_ok());
+        assert!(check_src("$x := 1;\n$x := \"a\";\n").is_ok());
+    }
+
+    #[test]
+    fn test_results() {
+        // Calls have the type of the value their function returns, even if
+        // it is declared after them.
+        let errors = check_src("$x: string := $f();\ndef $f() {\n  return 1;\n}\n").unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].error, "mismatched types: expected `string`, found `int`");
+
+        let errors = check_src("def $f() {\n  /say hi\n}\n$x := $f();\n").unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].error, "`$f` doesn't return a value");
+        assert_eq!((errors[0].position.line, errors[0].position.column), (4, 7));
+    }
 
-        todo!()
+    #[test]
+    fn test_inference() {
+        let ast = check_src("a := 1;\n$b := a * 2;\n$c := \"x\" + \"y\";\n$d: bool := true;\n").unwrap();
+        let typings: Vec<Typing> = ast.statements.iter().filter_map(|statement| match statement {
+            Statement::VariableAssignment(assignment) => Some(assignment.signature.typing.clone()),
+            _ => None
+        }).collect();
+        assert_eq!(typings, vec![Typing::Integer, Typing::Integer, Typing::String, Typing::Boolean]);
     }
 }
@@ -0,0 +1,379 @@
+//! A reference interpreter, running the AST directly instead of compiling it.
+//!
+//! It gives the meaning of a program without going through the generator:
+//! static and dynamic values are all computed as the statements run, and
+//! variables are scoped lexically. Comparing what it prints with what the
+//! generated datapack prints in the [Simulator] tests the generator.
+//!
+//! Commands are run by a [Simulator] too, with the runtime variables
+//! mirrored to the `vars` of the runtime storage, so that commands reading
+//! them (e.g. `tellraw` with an `nbt` component) behave like in the
+//! datapack.
+//!
+//! ```
+//! use tag_lang::{check_sources, CompileOptions, Source};
+//! use tag_lang::interpreter::interpret;
+//!
+//! let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+//! let source = Source { name: "test.tag".into(), text: "$x := 2;\n/say #{$x * 3}\n".into() };
+//! let ast = check_sources(&[source], &options).unwrap();
+//! assert_eq!(interpret(&ast, &options).unwrap(), vec!["6"]);
+//! ```
+
+use crate::{CompileOptions, AST};
+use crate::datapack::{Datapack, PackMeta};
+use crate::generator::{Generator, CALLERS};
+use crate::generator::staticness::IsStatic;
+use crate::parser::Position;
+use crate::parser::expression::{Expression, Summand, Term, VariableName};
+use crate::parser::function::{Function, FunctionCall};
+use crate::parser::statement::{Statement, IfStatement, Command};
+use crate::parser::typing::Typing;
+use crate::simulator::Simulator;
+use crate::simulator::nbt::Nbt;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Functions calling each other deeper than this are considered to never end.
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    String(String),
+    Boolean(bool)
+}
+
+impl Value {
+    pub fn typing(&self) -> Typing {
+        match self {
+            Value::Integer(_) => Typing::Integer,
+            Value::String(_) => Typing::String,
+            Value::Boolean(_) => Typing::Boolean
+        }
+    }
+
+    /// The NBT the value is stored as at runtime.
+    pub fn to_nbt(&self) -> Nbt {
+        match self {
+            Value::Integer(n) => Nbt::Int(*n),
+            Value::String(str) => Nbt::String(str.clone()),
+            Value::Boolean(bl) => Nbt::Byte(*bl as i8)
+        }
+    }
+}
+
+/// How the value is written when interpolated in a command.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::String(str) => write!(f, "\"{}\"", str),
+            Value::Boolean(bl) => write!(f, "{}", bl)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpretError {
+    pub position: Position,
+    pub error: String
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.position.line, self.position.column, self.error)
+    }
+}
+
+impl<S: ToString> From<(&Position, S)> for InterpretError {
+    fn from((position, error): (&Position, S)) -> Self {
+        InterpretError { position: position.clone(), error: error.to_string() }
+    }
+}
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<VariableName, Value>,
+    functions: Vec<Function>,
+    /// The index of the compound of `vars` holding the runtime variables
+    /// of the scope, if it has one.
+    vars: Option<usize>
+}
+
+struct Interpreter<'a> {
+    simulator: Simulator<'a>,
+    storage: String,
+    scopes: Vec<Scope>,
+    /// How many compounds `vars` holds.
+    vars_len: usize,
+    /// How many function calls are running.
+    depth: usize
+}
+
+/// Runs `ast`, which must have been checked (see [crate::check_sources]),
+/// and returns everything it sent to the chat. `test` blocks are skipped.
+pub fn interpret(ast: &AST, options: &CompileOptions) -> Result<Vec<String>, InterpretError> {
+    let meta = PackMeta { pack_format: options.target.pack_format(), description: String::new() };
+    let datapack = Datapack::new(options.namespace.clone(), meta, options.target);
+    let mut interpreter = Interpreter {
+        simulator: Simulator::new(&datapack),
+        storage: options.runtime_storage(),
+        scopes: vec![],
+        vars_len: 0,
+        depth: 0
+    };
+
+    interpreter.run_block(&ast.statements, true)?;
+    Ok(interpreter.simulator.chat)
+}
+
+impl Interpreter<'_> {
+    /// Runs `statements` in a new scope, with its own compound in `vars`
+    /// when `runtime` is set.
+    fn run_block(&mut self, statements: &[Statement], runtime: bool) -> Result<(), InterpretError> {
+        self.push_scope(runtime);
+        let result = self.run_statements(statements);
+        self.pop_scope();
+        result
+    }
+
+    fn run_statements(&mut self, statements: &[Statement]) -> Result<(), InterpretError> {
+        // Functions can be called before being declared in their block.
+        for statement in statements {
+            if let Statement::FunctionDeclaration(func) = statement {
+                self.scopes.last_mut().unwrap().functions.push(func.clone());
+            }
+        }
+
+        for statement in statements {
+            self.run_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> Result<(), InterpretError> {
+        match statement {
+            Statement::Command(cmd) => self.run_command(cmd),
+            Statement::IfStatement(if_stmt) => self.run_if_statement(if_stmt),
+            Statement::VariableAssignment(assignment) => {
+                let value = self.evaluate(&assignment.value)?;
+                self.declare(&assignment.signature.name, value);
+                Ok(())
+            },
+            Statement::FunctionCall(call) => self.call(call).map(|_| ()),
+            Statement::FunctionDeclaration(_) | Statement::Test(_) => Ok(()),
+            Statement::Assert(assert) => Err((&assert.position, "`assert` can only be used in a test").into())
+        }
+    }
+
+    fn run_command(&mut self, cmd: &Command) -> Result<(), InterpretError> {
+        let mut command = String::new();
+        for (string, expr) in &cmd.start {
+            command += string;
+            command += &self.evaluate(expr)?.to_string();
+        }
+        command += &cmd.end;
+
+        self.simulator.run_command(&command)
+            .map_err(|err| InterpretError::from((&cmd.position, err.error)))?;
+        Ok(())
+    }
+
+    fn run_if_statement(&mut self, if_stmt: &IfStatement) -> Result<(), InterpretError> {
+        match self.evaluate(&if_stmt.expr)? {
+            Value::Boolean(true) => self.run_block(&if_stmt.block, Generator::requires_scope(&if_stmt.block)),
+            Value::Boolean(false) => match (&*if_stmt.else_if, &if_stmt.else_block) {
+                (Some(else_if), _) => self.run_if_statement(else_if),
+                (None, Some(block)) => self.run_block(block, Generator::requires_scope(block)),
+                (None, None) => Ok(())
+            },
+            value => Err((if_stmt.expr.pos(), format!("expected a `bool`, found `{}`", value.typing())).into())
+        }
+    }
+
+    /// Runs the function `call` calls, giving the value it returns, if any.
+    fn call(&mut self, call: &FunctionCall) -> Result<Option<Value>, InterpretError> {
+        if self.depth == MAX_DEPTH {
+            return Err((&call.position, "functions are nested too deeply").into());
+        }
+
+        let args = call.args.iter()
+            .map(|arg| self.evaluate(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (depth, func) = self.resolve(call, &args)?;
+
+        // The function runs in the scope it was declared in, so the scopes
+        // of the caller are put aside meanwhile, along with their compounds
+        // of `vars` for dynamic functions, like in the datapack.
+        let caller_scopes = self.scopes.split_off(depth + 1);
+        let caller_vars = if func.is_dynamic() {
+            caller_scopes.iter().filter(|scope| scope.vars.is_some()).count()
+        } else {
+            0
+        };
+        for _ in 0..caller_vars {
+            self.run_storage_command(format!("data modify storage {0} {1} append from storage {0} vars[-1]", self.storage, CALLERS));
+            self.run_storage_command(format!("data remove storage {} vars[-1]", self.storage));
+        }
+        self.vars_len -= caller_vars;
+
+        // Macros keep their static arguments in a scope of their own too.
+        let runtime = func.is_dynamic()
+            && (Generator::requires_scope(&func.block) || !func.signature.args.is_empty());
+        self.push_scope(runtime);
+        for (arg, value) in func.signature.args.iter().zip(args) {
+            self.declare(&arg.name, value);
+        }
+        self.depth += 1;
+        let result = self.run_statements(&func.block).and_then(|_| match &func.result {
+            Some(result) => self.evaluate(&result.expr).map(Some),
+            None => Ok(None)
+        });
+        self.depth -= 1;
+        self.pop_scope();
+
+        self.vars_len += caller_vars;
+        for _ in 0..caller_vars {
+            self.run_storage_command(format!("data modify storage {0} vars append from storage {0} {1}[-1]", self.storage, CALLERS));
+            self.run_storage_command(format!("data remove storage {} {}[-1]", self.storage, CALLERS));
+        }
+        self.scopes.extend(caller_scopes);
+
+        result
+    }
+
+    /// The function `call` runs, with the index of the scope declaring it.
+    /// Like the generator, it picks the overload whose static and typed
+    /// arguments match the most arguments of the call.
+    fn resolve(&self, call: &FunctionCall, args: &[Value]) -> Result<(usize, Function), InterpretError> {
+        let mut overloads: Vec<(usize, &Function)> = vec![];
+        for (depth, scope) in self.scopes.iter().enumerate().rev() {
+            for func in &scope.functions {
+                let shadowed = overloads.iter().any(|(_, other)| other.signature == func.signature);
+                if func.signature.name == call.name && !shadowed {
+                    overloads.push((depth, func));
+                }
+            }
+        }
+
+        let candidates: Vec<_> = overloads.into_iter().filter_map(|(depth, func)| {
+            if func.signature.args.len() != args.len() {
+                return None;
+            }
+
+            let mut score = 0;
+            for ((sign, expr), value) in func.signature.args.iter().zip(&call.args).zip(args) {
+                if sign.is_static() && expr.is_dynamic() {
+                    return None;
+                }
+                if sign.typing != Typing::Unknown && sign.typing != value.typing() {
+                    return None;
+                }
+                score += (sign.is_static() && expr.is_static()) as i32 + (sign.typing != Typing::Unknown) as i32;
+            }
+            Some((depth, func, score))
+        }).collect();
+
+        let best_score = candidates.iter().map(|(_, _, score)| *score).max();
+        let best: Vec<_> = candidates.into_iter().filter(|(_, _, score)| Some(*score) == best_score).collect();
+        match best.as_slice() {
+            [(depth, func, _)] => Ok((*depth, (*func).clone())),
+            [] => Err((&call.position, format!("no matching overload for `{}`", call.name)).into()),
+            _ => Err((&call.position, format!("ambiguous call to `{}`", call.name)).into())
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Expression) -> Result<Value, InterpretError> {
+        match expr {
+            Expression::Sum(operands, pos) => {
+                let mut sum = self.evaluate(&operands[0])?;
+                for operand in &operands[1..] {
+                    sum = match (sum, self.evaluate(operand)?) {
+                        (Value::Integer(a), Value::Integer(b)) => Value::Integer(a.wrapping_add(b)),
+                        (Value::String(a), Value::String(b)) => Value::String(a + &b),
+                        (a, b) => return Err((pos, format!("can't add `{}` to `{}`", b.typing(), a.typing())).into())
+                    };
+                }
+                Ok(sum)
+            },
+            Expression::Summand(summand, pos) => self.evaluate_summand(summand, pos),
+            Expression::Boolean(bl, _) => Ok(Value::Boolean(*bl)),
+            Expression::Equality(left, right, _) => {
+                let (left, right) = (self.evaluate(left)?, self.evaluate(right)?);
+                Ok(Value::Boolean(left == right))
+            }
+        }
+    }
+
+    /// Summands don't have a position: errors point at the expression `pos`
+    /// they are part of.
+    fn evaluate_summand(&mut self, summand: &Summand, pos: &Position) -> Result<Value, InterpretError> {
+        match summand {
+            Summand::Multiplication(terms) => {
+                let mut product = self.evaluate_term(&terms[0])?;
+                for term in &terms[1..] {
+                    product = match (product, self.evaluate_term(term)?) {
+                        (Value::Integer(a), Value::Integer(b)) => Value::Integer(a.wrapping_mul(b)),
+                        (a, b) => return Err((pos, format!("can't multiply `{}` by `{}`", a.typing(), b.typing())).into())
+                    };
+                }
+                Ok(product)
+            },
+            Summand::Term(term) => self.evaluate_term(term)
+        }
+    }
+
+    fn evaluate_term(&mut self, term: &Term) -> Result<Value, InterpretError> {
+        match term {
+            Term::Number(n) => Ok(Value::Integer(*n)),
+            Term::String(str) => Ok(Value::String(str.clone())),
+            Term::Expression(expr) => self.evaluate(expr),
+            Term::FunctionCall(call) => self.call(call)?
+                .ok_or_else(|| (&call.position, format!("`{}` doesn't return a value", call.name)).into()),
+            Term::Variable(var, pos) => self.scopes.iter()
+                .rev()
+                .find_map(|scope| scope.variables.get(var))
+                .cloned()
+                .ok_or_else(|| (pos, format!("unknown variable `{}`", var)).into())
+        }
+    }
+
+    fn declare(&mut self, name: &VariableName, value: Value) {
+        if name.is_dynamic() {
+            // Scopes without a compound of their own keep their runtime
+            // variables in the one of the scope around them.
+            let index = self.scopes.iter().rev().find_map(|scope| scope.vars);
+            if let Some(index) = index {
+                let command = format!("data modify storage {} vars[{}].\"{}\" set value {}",
+                                      self.storage, index, name.get_name(), value.to_nbt());
+                self.run_storage_command(command);
+            }
+        }
+        self.scopes.last_mut().unwrap().variables.insert(name.clone(), value);
+    }
+
+    fn push_scope(&mut self, runtime: bool) {
+        let mut scope = Scope::default();
+        if runtime {
+            let command = format!("data modify storage {} vars append value {{}}", self.storage);
+            self.run_storage_command(command);
+            scope.vars = Some(self.vars_len);
+            self.vars_len += 1;
+        }
+        self.scopes.push(scope);
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().expect("no scope");
+        if scope.vars.is_some() {
+            let command = format!("data remove storage {} vars[-1]", self.storage);
+            self.run_storage_command(command);
+            self.vars_len -= 1;
+        }
+    }
+
+    fn run_storage_command(&mut self, command: String) {
+        self.simulator.run_command(&command).expect("the runtime storage can always be written");
+    }
+}
@@ -0,0 +1,335 @@
+//! Compiler for Tag, a programming language that compiles to Minecraft datapacks.
+//!
+//! ```no_run
+//! use tag_lang::{compile_str, CompileOptions};
+//! use tag_lang::datapack::DirectoryWriter;
+//!
+//! let options = CompileOptions { namespace: "hello".into(), ..Default::default() };
+//! let datapack = compile_str("/say hello world\n", options).unwrap();
+//! datapack.write(&mut DirectoryWriter::new("hello")).unwrap();
+//! ```
+
+use nom::Finish;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+
+pub mod parser;
+pub mod generator;
+pub mod errors;
+pub mod datapack;
+pub mod project;
+pub mod target;
+pub mod simulator;
+pub mod testing;
+pub mod interpreter;
+pub mod lsp;
+pub mod repl;
+pub mod cache;
+pub mod build;
+
+pub use crate::errors::Diagnostic;
+pub use crate::datapack::Datapack;
+pub use crate::parser::{AST, FileId};
+pub use crate::target::Target;
+
+use crate::cache::Cache;
+use crate::parser::Position;
+use crate::parser::statement::{Statement, VariableAssignment, VariableSignature};
+use crate::parser::expression::{Expression, Summand, Term, VariableName};
+use crate::parser::typing::Typing;
+use crate::target::Feature;
+
+pub const FILE_EXTENSION: &str = ".tag";
+
+/// The longest objective name before [Feature::LongObjectiveNames].
+pub const MAX_OBJECTIVE_LENGTH: usize = 16;
+
+pub const BOOTSTRAP: &str = include_str!("./data/bootstrap.tag");
+
+/// The [FileId] of [BOOTSTRAP]. The sources given to [compile_sources]
+/// are numbered from 0, in order.
+pub const BOOTSTRAP_FILE: FileId = usize::MAX;
+
+/// The [FileId] of the [Define]s, which have no source.
+pub const DEFINES_FILE: FileId = usize::MAX - 1;
+
+/// The defines set by the compiler, with the option that sets each of them.
+const RESERVED_DEFINES: [(&str, &str); 2] = [("runtime_storage", "storage"), ("runtime_objective", "objective")];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub text: String
+}
+
+/// A value given to a static variable from outside the sources,
+/// e.g. from the `[defines]` of a `tag.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+pub enum Define {
+    Boolean(bool),
+    Integer(i32),
+    String(String)
+}
+
+#[derive(Debug, Clone, Default, Hash)]
+pub struct CompileOptions {
+    pub namespace: String,
+    /// Static variables declared before the sources, in the same scope.
+    /// They can override the ones of [BOOTSTRAP], like `pack_description`,
+    /// but not `runtime_storage` and `runtime_objective`, which come from
+    /// [CompileOptions::storage] and [CompileOptions::objective].
+    pub defines: BTreeMap<String, Define>,
+    /// The Minecraft version the datapack is made for. It sets the default
+    /// `pack_format`, and which features the generated code may use.
+    pub target: Target,
+    /// The storage holding the stack and the runtime variables,
+    /// `<namespace>:runtime` by default.
+    pub storage: Option<String>,
+    /// Annotates the functions with the statements they come from, and makes
+    /// dynamic functions trace their calls. See [Datapack::annotate].
+    pub debug: bool,
+    /// The scoreboard objective used for arithmetic, `__<namespace>__` by default.
+    /// Before 1.18, objective names are limited to [MAX_OBJECTIVE_LENGTH]
+    /// characters: a longer default is shortened to a hash of the namespace.
+    pub objective: Option<String>,
+    /// Compiles the `test` blocks, for [testing::run_tests].
+    pub tests: bool
+}
+
+impl CompileOptions {
+    /// Each datapack gets its own storage and objective, so that two packs
+    /// compiled by tag don't share their stacks.
+    pub fn runtime_storage(&self) -> String {
+        self.storage.clone().unwrap_or_else(|| format!("{}:runtime", self.namespace))
+    }
+
+    pub fn runtime_objective(&self) -> String {
+        if let Some(objective) = &self.objective {
+            return objective.clone();
+        }
+
+        let objective = format!("__{}__", self.namespace);
+        if objective.len() <= MAX_OBJECTIVE_LENGTH || self.target.supports(Feature::LongObjectiveNames) {
+            objective
+        } else {
+            // FNV-1a, which unlike the std hashers is stable across builds.
+            let hash = self.namespace.bytes()
+                .fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+            format!("tag.{:08x}", hash)
+        }
+    }
+}
+
+/// The name of the source `file` is read from, for diagnostics.
+pub fn source_name(sources: &[Source], file: FileId) -> &str {
+    match file {
+        BOOTSTRAP_FILE => "<bootstrap>",
+        DEFINES_FILE => "<defines>",
+        file => sources.get(file).map_or("<unknown>", |source| &source.name)
+    }
+}
+
+/// Where `position` is, as `name:line:column`, for diagnostics.
+pub fn source_location(sources: &[Source], position: &Position) -> String {
+    format!("{}:{}:{}", source_name(sources, position.file), position.line, position.column)
+}
+
+/// The text of the source `file`.
+pub fn source_text(sources: &[Source], file: FileId) -> &str {
+    match file {
+        BOOTSTRAP_FILE => BOOTSTRAP,
+        file => sources.get(file).map_or("", |source| &source.text)
+    }
+}
+
+/// Parses a source file into its AST, without compiling it.
+pub fn parse_str(input: &str) -> Result<AST, Diagnostic> {
+    parse_source(input, 0)
+}
+
+fn parse_source(input: &str, file: FileId) -> Result<AST, Diagnostic> {
+    let (_, ast) = parser::parse_file(input, file).finish()?;
+    Ok(ast)
+}
+
+/// Formats a source file, see [parser::pretty::format].
+pub fn format_str(input: &str) -> Result<String, Diagnostic> {
+    let ast = parse_str(input)?;
+    Ok(parser::pretty::format(&ast, input))
+}
+
+/// Formats each of `sources` like [format_str], rewriting the files whose
+/// formatting changed, or only checking them if `check` is set. Returns the
+/// names of the sources that were (or would be) reformatted, and the errors
+/// of those that can't be parsed.
+pub fn format_files(sources: &[Source], check: bool) -> io::Result<(Vec<String>, Vec<Diagnostic>)> {
+    let mut reformatted = vec![];
+    let mut errors = vec![];
+
+    for (file, source) in sources.iter().enumerate() {
+        let formatted = match parse_source(&source.text, file) {
+            Ok(ast) => parser::pretty::format(&ast, &source.text),
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+
+        if formatted != source.text {
+            if !check {
+                std::fs::write(&source.name, formatted).map_err(|err|
+                    io::Error::new(err.kind(), format!("can't write `{}`: {}", source.name, err)))?;
+            }
+            reformatted.push(source.name.clone());
+        }
+    }
+
+    Ok((reformatted, errors))
+}
+
+/// Compiles a source file to a datapack, reporting every error found.
+pub fn compile_str(input: &str, options: CompileOptions) -> Result<Datapack, Vec<Diagnostic>> {
+    let source = Source { name: "<input>".into(), text: input.into() };
+    compile_sources(&[source], options)
+}
+
+/// Compiles several source files, in order, as if they were a single one.
+pub fn compile_sources(sources: &[Source], options: CompileOptions) -> Result<Datapack, Vec<Diagnostic>> {
+    Compiler::new().map_err(|err| vec![err])?.compile_sources(sources, options)
+}
+
+/// Parses several source files, in order, after the bootstrap and the
+/// defines of `options`, and checks the names and types of the whole AST.
+pub fn check_sources(sources: &[Source], options: &CompileOptions) -> Result<AST, Vec<Diagnostic>> {
+    Compiler::new().map_err(|err| vec![err])?.check_sources(sources, options)
+}
+
+/// Parses several source files, in order, after the bootstrap and the
+/// defines of `options`, without checking them.
+pub fn parse_sources(sources: &[Source], options: &CompileOptions) -> Result<AST, Vec<Diagnostic>> {
+    Compiler::new().map_err(|err| vec![err])?.parse_sources(sources, options)
+}
+
+/// Compiles sources like [compile_sources], parsing [BOOTSTRAP] only once
+/// for all of them, e.g. for the builds of `tag compile --watch`.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    bootstrap: AST
+}
+
+impl Compiler {
+    pub fn new() -> Result<Compiler, Diagnostic> {
+        Ok(Compiler { bootstrap: parse_source(BOOTSTRAP, BOOTSTRAP_FILE)? })
+    }
+
+    pub fn compile_sources(&self, sources: &[Source], options: CompileOptions) -> Result<Datapack, Vec<Diagnostic>> {
+        let ast = self.check_sources(sources, &options)?;
+        let debug = options.debug;
+        let mut datapack = generator::generate(ast, options, sources).map_err(|err| vec![err])?;
+        if debug {
+            datapack.annotate(sources);
+        }
+
+        Ok(datapack)
+    }
+
+    /// Compiles `sources` like [Compiler::compile_sources], reusing what
+    /// `cache` kept from the previous builds.
+    pub fn compile_cached(&self, sources: &[Source], options: CompileOptions, cache: &mut Cache)
+        -> Result<Datapack, Vec<Diagnostic>>
+    {
+        let mut ast = self.parse_with(sources, &options, |source, file| cache.parse(source, file))?;
+        let resolutions = generator::resolve::resolve(&ast, sources)?;
+        generator::typing::check(&mut ast, &resolutions)?;
+        let debug = options.debug;
+        let mut datapack = generator::generate_incremental(ast, options, sources, cache).map_err(|err| vec![err])?;
+        if debug {
+            datapack.annotate(sources);
+        }
+
+        Ok(datapack)
+    }
+
+    pub fn check_sources(&self, sources: &[Source], options: &CompileOptions) -> Result<AST, Vec<Diagnostic>> {
+        let mut ast = self.parse_sources(sources, options)?;
+        let resolutions = generator::resolve::resolve(&ast, sources)?;
+        generator::typing::check(&mut ast, &resolutions)?;
+        Ok(ast)
+    }
+
+    pub fn parse_sources(&self, sources: &[Source], options: &CompileOptions) -> Result<AST, Vec<Diagnostic>> {
+        self.parse_with(sources, options, |source, file| parse_source(&source.text, file))
+    }
+
+    fn parse_with<F>(&self, sources: &[Source], options: &CompileOptions, mut parse: F) -> Result<AST, Vec<Diagnostic>>
+        where F: FnMut(&Source, FileId) -> Result<AST, Diagnostic>
+    {
+        let position = Position { file: DEFINES_FILE, ..Default::default() };
+        let mut errors: Vec<_> = RESERVED_DEFINES.iter()
+            .filter(|(name, _)| options.defines.contains_key(*name))
+            .map(|(name, option)| Diagnostic::from((position.clone(), format!("`{}` is set by the compiler, and can't be defined", name)))
+                .with_note(format!("help: set the `{}` option instead", option)))
+            .collect();
+
+        let mut ast = self.bootstrap.clone();
+        let mut defines = options.defines.clone();
+        defines.entry("pack_format".into()).or_insert_with(|| Define::Integer(options.target.pack_format()));
+        // Sources reading the runtime state themselves, e.g. with `tellraw`,
+        // refer to these rather than hard-coding them.
+        defines.insert("runtime_storage".into(), Define::String(options.runtime_storage()));
+        defines.insert("runtime_objective".into(), Define::String(options.runtime_objective()));
+        ast.statements.extend(defines_statements(&defines));
+
+        for (file, source) in sources.iter().enumerate() {
+            match parse(source, file) {
+                Ok(source_ast) => ast = ast + source_ast,
+                Err(err) => errors.push(err)
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(ast)
+    }
+}
+
+fn defines_statements(defines: &BTreeMap<String, Define>) -> Vec<Statement> {
+    let position = Position { file: DEFINES_FILE, ..Default::default() };
+
+    defines.iter().map(|(name, define)| {
+        let term = |term| Expression::Summand(Summand::Term(term), position.clone());
+        let value = match define {
+            Define::Boolean(bl) => Expression::Boolean(*bl, position.clone()),
+            Define::Integer(n) => term(Term::Number(*n)),
+            Define::String(str) => term(Term::String(str.clone()))
+        };
+
+        Statement::VariableAssignment(VariableAssignment {
+            signature: VariableSignature { name: VariableName::Static(name.clone()), typing: Typing::Unknown },
+            value,
+            position: position.clone()
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_defines() {
+        let mut options = CompileOptions { namespace: "test".into(), storage: Some("test:state".into()), ..Default::default() };
+        let datapack = compile_str("/say #{runtime_storage}\n", options.clone()).unwrap();
+        assert!(datapack.functions["0"].contains(&"say \"test:state\"".to_string()), "{:?}", datapack.functions);
+
+        options.defines.insert("runtime_storage".into(), Define::String("other:storage".into()));
+        let errors = compile_str("/say #{runtime_storage}\n", options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error, "`runtime_storage` is set by the compiler, and can't be defined");
+        assert_eq!(errors[0].position.file, DEFINES_FILE);
+        assert_eq!(errors[0].notes, ["help: set the `storage` option instead"]);
+    }
+}
@@ -0,0 +1,269 @@
+//! What the language server knows of a document: the AST of the sources it
+//! is compiled with, its names and their types.
+//!
+//! Positions of the AST are byte offsets in the sources, while the protocol
+//! counts lines and UTF-16 code units, hence the conversions below.
+
+use crate::{generator, parse_sources, CompileOptions, Diagnostic, FileId, Source};
+use crate::parser::{Position, AST};
+use crate::parser::function::Function;
+use crate::parser::statement::Statement;
+use crate::parser::expression::VariableName;
+use crate::parser::typing::Typing;
+use crate::generator::resolve::{resolve_all, scope_at, Resolutions};
+use crate::generator::typing::{check_all, Types};
+use lsp_types::{CompletionItem, CompletionItemKind, CompletionTextEdit, Range, TextEdit};
+
+pub struct Analysis {
+    /// The sources compiled together, the document being `sources[file]`.
+    pub sources: Vec<Source>,
+    pub file: FileId,
+    ast: AST,
+    resolutions: Resolutions,
+    types: Types
+}
+
+/// Checks the sources the way `tag compile` does, giving the analysis of
+/// the document if it could be parsed, and the errors of every source.
+pub fn analyze(sources: Vec<Source>, file: FileId, options: CompileOptions) -> (Option<Analysis>, Vec<Diagnostic>) {
+    let mut ast = match parse_sources(&sources, &options) {
+        Ok(ast) => ast,
+        Err(errors) => return (None, errors)
+    };
+
+    let (resolutions, mut errors) = resolve_all(&ast, &sources);
+    let (types, type_errors) = check_all(&mut ast, &resolutions);
+    errors.extend(type_errors);
+
+    // Some errors, like calls no overload accepts, are only found while
+    // generating the datapack.
+    if errors.is_empty() {
+        let statements = ast.statements.clone();
+        if let Err(err) = generator::generate(AST { statements }, options, &sources) {
+            errors.push(err);
+        }
+    }
+
+    (Some(Analysis { sources, file, ast, resolutions, types }), errors)
+}
+
+impl Analysis {
+    pub fn text(&self) -> &str {
+        &self.sources[self.file].text
+    }
+
+    fn contains(&self, position: &Position, offset: usize) -> bool {
+        position.file == self.file && position.offset <= offset && offset <= position.offset + position.length
+    }
+
+    /// The declarations of the variable or the function used at `offset`.
+    pub fn definitions(&self, offset: usize) -> Vec<Position> {
+        let variable = self.resolutions.variables.iter()
+            .find(|(usage, _)| self.contains(usage, offset))
+            .map(|(_, declaration)| declaration.position.clone());
+        if let Some(position) = variable {
+            return vec![position];
+        }
+
+        self.resolutions.functions.iter()
+            .find(|(call, _)| self.contains(call, offset))
+            .map(|(_, overloads)| overloads.iter().map(|(_, position)| position.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The signature of the function, or the type of the variable, at
+    /// `offset`, as source.
+    pub fn hover(&self, offset: usize) -> Option<String> {
+        let variable = |name: &VariableName, typing: Option<&Typing>| match typing {
+            Some(typing) if *typing != Typing::Unknown => format!("{}: {}", name, typing),
+            _ => name.to_string()
+        };
+
+        if let Some(declaration) = self.resolutions.variables.iter()
+            .find(|(usage, _)| self.contains(usage, offset))
+            .map(|(_, declaration)| declaration)
+        {
+            let typing = self.types.get(&(declaration.position.clone(), declaration.name.clone()));
+            return Some(variable(&declaration.name, typing));
+        }
+
+        if let Some(((_, name), typing)) = self.types.iter()
+            .find(|((position, _), _)| self.contains(position, offset))
+        {
+            return Some(variable(name, Some(typing)));
+        }
+
+        if let Some(overloads) = self.resolutions.functions.iter()
+            .find(|(call, _)| self.contains(call, offset))
+            .map(|(_, overloads)| overloads)
+        {
+            let signatures: Vec<String> = overloads.iter()
+                .map(|(signature, _)| format!("def {}", signature))
+                .collect();
+            return Some(signatures.join("\n"));
+        }
+
+        // A function is positioned at its `def`, followed by its name.
+        functions(&self.ast.statements).into_iter()
+            .find(|func| {
+                let name = Position { length: "def ".len() + func.signature.name.to_string().len(), ..func.position.clone() };
+                self.contains(&name, offset)
+            })
+            .map(|func| format!("def {}", func.signature))
+    }
+
+    /// The variables and functions visible from `offset`, replacing the
+    /// name being typed in `range`.
+    pub fn completions(&self, offset: usize, range: Range) -> Vec<CompletionItem> {
+        // Names are visible from the start of the statement being typed,
+        // so that `$total := $t` doesn't complete `$total`.
+        let text = self.text();
+        let start = text[..floor_char_boundary(text, offset)]
+            .rfind([';', '{', '}', '\n'])
+            .map_or(0, |i| i + 1);
+        let visible = scope_at(&self.ast, self.file, start);
+        let item = |label: String, kind, detail| CompletionItem {
+            label: label.clone(),
+            kind: Some(kind),
+            detail: Some(detail),
+            filter_text: Some(label.clone()),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text: label })),
+            ..Default::default()
+        };
+
+        let variables = visible.variables.into_iter().map(|declaration| {
+            let typing = self.types.get(&(declaration.position, declaration.name.clone()))
+                .cloned()
+                .unwrap_or(Typing::Unknown);
+            item(declaration.name.to_string(), CompletionItemKind::VARIABLE, typing.to_string())
+        });
+        let functions = visible.functions.into_iter().map(|(signature, _)|
+            item(signature.name.to_string(), CompletionItemKind::FUNCTION, format!("def {}", signature)));
+
+        variables.chain(functions).collect()
+    }
+}
+
+/// The functions declared in `statements`, at any depth.
+fn functions(statements: &[Statement]) -> Vec<&Function> {
+    let mut found = vec![];
+
+    for statement in statements {
+        match statement {
+            Statement::FunctionDeclaration(func) => {
+                found.push(func);
+                found.extend(functions(&func.block));
+            },
+            Statement::IfStatement(if_stmt) => {
+                let mut if_stmt = Some(if_stmt);
+                while let Some(current) = if_stmt {
+                    found.extend(functions(&current.block));
+                    if let Some(else_block) = &current.else_block {
+                        found.extend(functions(else_block));
+                    }
+                    if_stmt = current.else_if.as_ref().as_ref();
+                }
+            },
+            Statement::Test(test) => found.extend(functions(&test.block)),
+            _ => {}
+        }
+    }
+
+    found
+}
+
+/// The protocol position of the byte `offset` of `text`.
+pub fn to_lsp_position(text: &str, offset: usize) -> lsp_types::Position {
+    let offset = floor_char_boundary(text, offset);
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+
+    lsp_types::Position {
+        line: text[..line_start].matches('\n').count() as u32,
+        character: text[line_start..offset].encode_utf16().count() as u32
+    }
+}
+
+/// The byte offset of the protocol `position` in `text`, clamped to the
+/// line it is on.
+pub fn to_offset(text: &str, position: lsp_types::Position) -> usize {
+    let line_start = match position.line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len()
+        }
+    };
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if c == '\n' || units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// The range of `position` in `text`, cut at the end of its line: errors
+/// are positioned at the rest of the input they couldn't parse.
+pub fn to_range(text: &str, position: &Position) -> Range {
+    let start = floor_char_boundary(text, position.offset);
+    let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    let end = floor_char_boundary(text, (position.offset + position.length).min(line_end));
+
+    Range { start: to_lsp_position(text, start), end: to_lsp_position(text, end) }
+}
+
+/// The range of the name ending at the byte `offset` of `text`, with its
+/// `$` if it is dynamic.
+pub fn name_range(text: &str, offset: usize) -> Range {
+    let end = floor_char_boundary(text, offset);
+    let mut start = text[..end].rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\'')).map_or(0, |i| i + 1);
+    if text[..start].ends_with('$') {
+        start -= 1;
+    }
+
+    Range { start: to_lsp_position(text, start), end: to_lsp_position(text, end) }
+}
+
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions() {
+        let text = "a := \"é\";\n/say #{a}\n";
+        let offset = text.find("#{a}").unwrap() + 2;
+
+        let position = to_lsp_position(text, offset);
+        assert_eq!((position.line, position.character), (1, 7));
+        assert_eq!(to_offset(text, position), offset);
+        assert_eq!(to_lsp_position(text, text.find(';').unwrap()).character, 8);
+        assert_eq!(to_offset(text, lsp_types::Position { line: 0, character: 99 }), text.find('\n').unwrap());
+    }
+
+    #[test]
+    fn test_macro_definitions() {
+        // The body of a macro refers to the variables around its
+        // declaration, even where its caller shadows them.
+        let text = "$x := 1;\ndef $m(n) {\n$y := $x;\n}\nif true {\n$x := \"a\";\n$m(1);\n}\n";
+        let sources = vec![Source { name: "test.tag".into(), text: text.into() }];
+        let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+        let (analysis, errors) = analyze(sources, 0, options);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let analysis = analysis.unwrap();
+        let offset = text.find("$y := $x").unwrap() + "$y := $".len();
+        let definitions: Vec<_> = analysis.definitions(offset).iter().map(|position| position.line).collect();
+        assert_eq!(definitions, vec![1]);
+        assert_eq!(analysis.hover(offset).as_deref(), Some("$x: int"));
+    }
+}
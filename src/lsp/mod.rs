@@ -0,0 +1,255 @@
+//! A language server for `.tag` files, speaking the Language Server
+//! Protocol over stdio (see `tag lsp`).
+//!
+//! Documents are checked the way `tag compile` checks them, with the other
+//! sources of their project if they are part of one. The server publishes
+//! their errors, and answers go-to-definition, hover and completion
+//! requests from the last version of them that could be parsed.
+
+mod analysis;
+
+use crate::{CompileOptions, Diagnostic, FileId, Source, BOOTSTRAP_FILE, DEFINES_FILE};
+use crate::parser::Position;
+use crate::project::Project;
+use self::analysis::{analyze, name_range, to_offset, to_range, Analysis};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, PublishDiagnostics};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, DiagnosticSeverity, GotoDefinitionResponse, Hover, HoverContents,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Url
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+pub type LspError = Box<dyn Error + Send + Sync>;
+
+/// Serves the client connected to stdin and stdout, until it exits.
+pub fn run() -> Result<(), LspError> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Serves the client of `connection`, until it shuts the server down.
+pub fn serve(connection: Connection) -> Result<(), LspError> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["$".into()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection.sender.send(Message::Response(server.handle_request(request)))?;
+            },
+            Message::Notification(notification) => {
+                for notification in server.handle_notification(notification) {
+                    connection.sender.send(Message::Notification(notification))?;
+                }
+            },
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Document>
+}
+
+struct Document {
+    text: String,
+    /// The last analysis of the document that could be parsed.
+    analysis: Option<Analysis>
+}
+
+impl Server {
+    fn handle_request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            <GotoDefinition as lsp_types::request::Request>::METHOD => respond::<GotoDefinition, _>(request, |params| {
+                let (analysis, offset) = self.locate(&params.text_document_position_params)?;
+                let locations: Vec<Location> = analysis.definitions(offset).iter()
+                    .filter_map(|position| location(analysis, position))
+                    .collect();
+                Some(GotoDefinitionResponse::Array(locations))
+            }),
+            <HoverRequest as lsp_types::request::Request>::METHOD => respond::<HoverRequest, _>(request, |params| {
+                let (analysis, offset) = self.locate(&params.text_document_position_params)?;
+                analysis.hover(offset).map(|value| Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("```tag\n{}\n```", value)
+                    }),
+                    range: None
+                })
+            }),
+            <Completion as lsp_types::request::Request>::METHOD => respond::<Completion, _>(request, |params| {
+                let position = &params.text_document_position;
+                let (analysis, offset) = self.locate(position)?;
+                // The analysis may be of an older version of the document.
+                let text = &self.documents.get(&position.text_document.uri)?.text;
+                let range = name_range(text, to_offset(text, position.position));
+                Some(CompletionResponse::Array(analysis.completions(offset, range)))
+            }),
+            _ => Response::new_err(request.id, ErrorCode::MethodNotFound as i32,
+                                   format!("unsupported request `{}`", request.method))
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Vec<Notification> {
+        use lsp_types::notification::Notification as _;
+
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                if let Ok(params) = notification.extract::<<DidOpenTextDocument as lsp_types::notification::Notification>::Params>(DidOpenTextDocument::METHOD) {
+                    let document = Document { text: params.text_document.text, analysis: None };
+                    self.documents.insert(params.text_document.uri, document);
+                }
+                self.check()
+            },
+            DidChangeTextDocument::METHOD => {
+                if let Ok(params) = notification.extract::<<DidChangeTextDocument as lsp_types::notification::Notification>::Params>(DidChangeTextDocument::METHOD) {
+                    // Documents are synchronized as a whole.
+                    if let (Some(document), Some(change)) = (self.documents.get_mut(&params.text_document.uri), params.content_changes.into_iter().last()) {
+                        document.text = change.text;
+                    }
+                }
+                self.check()
+            },
+            DidCloseTextDocument::METHOD => {
+                let mut notifications = vec![];
+                if let Ok(params) = notification.extract::<<DidCloseTextDocument as lsp_types::notification::Notification>::Params>(DidCloseTextDocument::METHOD) {
+                    self.documents.remove(&params.text_document.uri);
+                    notifications.push(publish(params.text_document.uri, vec![]));
+                }
+                notifications.extend(self.check());
+                notifications
+            },
+            _ => vec![]
+        }
+    }
+
+    /// Checks every open document, as they may depend on each other, and
+    /// publishes their errors.
+    fn check(&mut self) -> Vec<Notification> {
+        let uris: Vec<Url> = self.documents.keys().cloned().collect();
+        let mut notifications = vec![];
+
+        for uri in uris {
+            let (sources, file, options) = self.sources(&uri);
+            let text = sources[file].text.clone();
+            let (analysis, errors) = analyze(sources, file, options);
+
+            let diagnostics = errors.iter()
+                .filter(|err| matches!(err.position.file, f if f == file || f == BOOTSTRAP_FILE || f == DEFINES_FILE))
+                .map(|err| diagnostic(&text, err, file))
+                .collect();
+            notifications.push(publish(uri.clone(), diagnostics));
+
+            let document = self.documents.get_mut(&uri).expect("the document is open");
+            if analysis.is_some() {
+                document.analysis = analysis;
+            }
+        }
+
+        notifications
+    }
+
+    /// The sources the document at `uri` is compiled with: the entries of
+    /// its project, the open ones as they are being edited, or else the
+    /// document on its own.
+    fn sources(&self, uri: &Url) -> (Vec<Source>, FileId, CompileOptions) {
+        let text = self.documents[uri].text.clone();
+        let path = uri.to_file_path().ok();
+        let name = path.as_ref().map_or_else(|| uri.to_string(), |path| path.display().to_string());
+
+        let project = path.as_ref()
+            .and_then(|path| Project::discover(path.parent()?).ok())
+            .filter(|project| project.entries().iter().any(|entry| Some(entry) == path.as_ref()));
+        if let Some(project) = project {
+            if let Ok(options) = project.options() {
+                let sources: Vec<Source> = project.entries().into_iter().map(|entry| {
+                    let open = Url::from_file_path(&entry).ok().and_then(|uri| self.documents.get(&uri));
+                    let text = match open {
+                        Some(document) => document.text.clone(),
+                        None => std::fs::read_to_string(&entry).unwrap_or_default()
+                    };
+                    Source { name: entry.display().to_string(), text }
+                }).collect();
+
+                if let Some(file) = sources.iter().position(|source| source.name == name) {
+                    return (sources, file, CompileOptions { tests: true, ..options });
+                }
+            }
+        }
+
+        let namespace = path.as_ref()
+            .and_then(|path| path.file_stem())
+            .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+        let options = CompileOptions { namespace, tests: true, ..Default::default() };
+        (vec![Source { name, text }], 0, options)
+    }
+
+    /// The analysis of the document at `params`, and the offset of the
+    /// position in it.
+    fn locate(&self, params: &TextDocumentPositionParams) -> Option<(&Analysis, usize)> {
+        let analysis = self.documents.get(&params.text_document.uri)?.analysis.as_ref()?;
+        Some((analysis, to_offset(analysis.text(), params.position)))
+    }
+}
+
+fn respond<R, F>(request: Request, handler: F) -> Response
+    where R: lsp_types::request::Request,
+          F: FnOnce(R::Params) -> R::Result
+{
+    let id = request.id.clone();
+    match request.extract::<R::Params>(R::METHOD) {
+        Ok((id, params)) => Response::new_ok(id, handler(params)),
+        Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string())
+    }
+}
+
+fn publish(uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    use lsp_types::notification::Notification as _;
+    Notification::new(PublishDiagnostics::METHOD.into(), PublishDiagnosticsParams { uri, diagnostics, version: None })
+}
+
+/// The error `err` of the source `file`, whose text is `text`. Errors of
+/// the bootstrap and the defines are shown at the start of it.
+fn diagnostic(text: &str, err: &Diagnostic, file: FileId) -> lsp_types::Diagnostic {
+    let range = if err.position.file == file { to_range(text, &err.position) } else { Range::default() };
+    let message = std::iter::once(err.error.clone()).chain(err.notes.iter().cloned()).collect::<Vec<_>>().join("\n");
+
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("tag".into()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Where `position` is, if it is in one of the sources of `analysis`.
+fn location(analysis: &Analysis, position: &Position) -> Option<Location> {
+    let source = analysis.sources.get(position.file)?;
+    let uri = Url::from_file_path(PathBuf::from(&source.name)).ok()
+        .or_else(|| Url::parse(&source.name).ok())?;
+    Some(Location { uri, range: to_range(&source.text, position) })
+}
@@ -1,97 +1,368 @@
-#![allow(dead_code)]
-#![allow(unused_imports)]
-
-use nom_greedyerror::convert_error;
-use nom::Err::*;
-use crate::errors::CompilerError;
-use std::io;
-use std::io::BufRead;
-use clap::{App, SubCommand, Arg};
+use clap::{App, SubCommand, Arg, ArgMatches};
+use std::io::{self, Read, StdoutLock, Write};
 use std::path::{Path, PathBuf};
-use std::error::Error;
-use nom::Finish;
+use tag_lang::{compile_sources, errors, format_files, format_str, repl, testing, Compiler, CompileOptions, Diagnostic, Source, Target, FILE_EXTENSION};
+use tag_lang::build::{watch, Build, ZIP_EXTENSION};
+use tag_lang::cache::CACHE_DIR;
+use tag_lang::project::Project;
+use tag_lang::repl::Session;
 
-mod parser;
-mod generator;
-mod errors;
+/// The `FILE` standing for the standard input.
+const STDIN_PATH: &str = "-";
 
-pub const FILE_EXTENSION: &str = ".tag";
+/// The name of the source read from the standard input, in diagnostics.
+const STDIN_NAME: &str = "<stdin>";
 
-pub const BOOTSTRAP: &str = include_str!("./data/bootstrap.tag");
-
-pub struct CompileOptions {
-    pub outdir: PathBuf,
-    pub namespace: String
-}
+/// The namespace of the functions generated by `tag repl`, by default.
+const REPL_NAMESPACE: &str = "repl";
 
 fn main() {
+    let file = Arg::with_name("FILE")
+        .validator(|f|
+            if f.ends_with(FILE_EXTENSION) || f == STDIN_PATH {
+                Ok(())
+            } else {
+                Err(format!("File must have the {} file extension, or be {} for the standard input", FILE_EXTENSION, STDIN_PATH))
+            }
+        );
+    let namespace = Arg::with_name("namespace")
+        .help("Set the namespace of the datapack")
+        .takes_value(true)
+        .short("n")
+        .long("name");
+    let target = Arg::with_name("target")
+        .help("Minecraft version the datapack is made for, e.g. 1.20.4")
+        .takes_value(true)
+        .short("t")
+        .long("target")
+        .validator(|version| version.parse::<Target>().map(|_| ()));
+
     let matches = App::new("Tag Compiler")
         .version(env!["CARGO_PKG_VERSION"])
         .author(env!["CARGO_PKG_AUTHORS"])
         .about(env!["CARGO_PKG_DESCRIPTION"])
         .subcommand(SubCommand::with_name("compile")
-            .about("Compile a Tag source file to a datapack")
-            .arg(Arg::with_name("FILE")
-                .help("The input file to compile")
-                .validator(|f|
-                    if f.ends_with(FILE_EXTENSION) {
-                        Ok(())
-                    } else {
-                        Err(format!("File must have the {} file extension", FILE_EXTENSION))
-                    }
-                )
-                .required(true))
-            .arg(Arg::with_name("namespace")
-                .help("Set the namespace of the datapack")
-                .takes_value(true)
-                .short("n")
-                .long("name"))
+            .about("Compile a Tag source file, or the current project, to a datapack")
+            .arg(file.clone()
+                .help("The input file to compile, or - to read it from the standard input. \
+                       Without it, the project of the current directory is compiled"))
+            .arg(namespace.clone())
+            .arg(Arg::with_name("stdout")
+                .help("Print the generated functions instead of writing the datapack")
+                .long("stdout")
+                .conflicts_with_all(&["outdir", "format"]))
             .arg(Arg::with_name("outdir")
-                .help("Directory in which the datapack will be generated")
+                .help("Directory (or .zip archive) in which the datapack will be generated")
                 .takes_value(true)
                 .short("o")
-                .long("outdir")))
+                .long("outdir"))
+            .arg(Arg::with_name("format")
+                .help("Output format of the datapack, inferred from the output path by default")
+                .takes_value(true)
+                .possible_values(&["dir", "zip"])
+                .short("f")
+                .long("format"))
+            .arg(target.clone())
+            .arg(Arg::with_name("watch")
+                .help("Keep running, compiling the datapack again whenever its sources change")
+                .long("watch")
+                .short("w")
+                .conflicts_with("stdout"))
+            .arg(Arg::with_name("no-cache")
+                .help("Compile everything again, without reading or writing the .tag-cache directory")
+                .long("no-cache"))
+            .arg(Arg::with_name("debug")
+                .help("Annotate the functions with their source, and trace the calls of dynamic functions")
+                .long("debug")))
+        .subcommand(SubCommand::with_name("test")
+            .about("Run the tests of a Tag source file, or of the current project")
+            .arg(file.clone()
+                .help("The input file to test, or - to read it from the standard input. \
+                       Without it, the project of the current directory is tested"))
+            .arg(namespace.clone())
+            .arg(target.clone()))
+        .subcommand(SubCommand::with_name("repl")
+            .about("Evaluate Tag statements and expressions interactively, printing the commands they generate")
+            .arg(namespace)
+            .arg(target))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Format Tag source files, or the sources of the current project")
+            .arg(file
+                .multiple(true)
+                .help("The files to format, or - to format the standard input to the standard output. \
+                       Without them, the sources of the project of the current directory are formatted"))
+            .arg(Arg::with_name("check")
+                .help("Only check that the files are formatted, failing if any of them isn't")
+                .long("check")))
+        .subcommand(SubCommand::with_name("lsp")
+            .about("Start a language server for Tag sources, speaking LSP over stdio"))
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("compile") {
-        let file = matches.value_of("FILE").unwrap();
-        let namespace = matches.value_of("namespace").map_or(
-            PathBuf::from(file).file_stem().unwrap().to_str().unwrap().to_string(),
-            ToString::to_string
-        );
-        let outdir = matches.value_of("outdir")
-            .unwrap_or(&file[0..file.len() - FILE_EXTENSION.len()]);
-        let input = match std::fs::read_to_string(file) {
-            Ok(input) => input,
-            Err(err) => clap::Error::with_description(err.to_string().as_str(), clap::ErrorKind::ValueValidation)
-                .exit()
-        };
-        let input = input.as_str();
-        let options = CompileOptions {
-            outdir: PathBuf::from(outdir),
-            namespace: namespace.to_string()
+        if matches.is_present("watch") && matches.value_of("FILE") == Some(STDIN_PATH) {
+            clap::Error::with_description("the standard input can't be watched", clap::ErrorKind::ArgumentConflict).exit();
+        }
+        let build = load_build(matches).unwrap_or_else(|err| fail(err));
+        let compiler = Compiler::new().unwrap_or_else(|err| {
+            report(&[err], &[]);
+            std::process::exit(1);
+        });
+
+        if matches.is_present("watch") {
+            let mut inputs = build.inputs.clone();
+            let mut build = Some(build);
+            // The build is loaded again on each change: the manifest of a
+            // project may have changed its entries.
+            let err = watch(inputs.clone(), || {
+                match build.take().map_or_else(|| load_build(matches), Ok) {
+                    Ok(build) => {
+                        if compile(&compiler, &build, false) {
+                            print(|out| writeln!(out, "compiled `{}`", build.output.display()));
+                        }
+                        inputs = build.inputs;
+                    },
+                    // The inputs are watched until they can be loaded again.
+                    Err(err) => eprintln!("error: {}", err)
+                }
+                print(|out| writeln!(out, "watching for changes..."));
+                inputs.clone()
+            });
+            if let Err(err) = err {
+                fail(format!("can't watch the sources: {}", err));
+            }
+        } else if !compile(&compiler, &build, matches.is_present("stdout")) {
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("test") {
+        let mut build = load_build(matches).unwrap_or_else(|err| fail(err));
+        build.options.tests = true;
+        test(build);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("repl") {
+        repl(matches);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let sources = match matches.values_of("FILE") {
+            Some(files) => files.map(|file| read_source(file).unwrap_or_else(|err| fail(err))).collect(),
+            None => project()
+                .and_then(|project| project.read_sources().map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| fail(err))
         };
+        format(&sources, matches.is_present("check"));
+    }
+
+    if matches.subcommand_matches("lsp").is_some() {
+        if let Err(err) = tag_lang::lsp::run() {
+            fail(err);
+        }
+    }
+}
+
+/// The build of `FILE`, or of the project of the current directory.
+fn load_build(matches: &ArgMatches) -> Result<Build, String> {
+    match matches.value_of("FILE") {
+        Some(file) => file_build(file, matches),
+        None => project_build(matches)
+    }
+}
 
-        let result = compile(input, options);
+fn file_build(file: &str, matches: &ArgMatches) -> Result<Build, String> {
+    let namespace = match matches.value_of("namespace") {
+        Some(namespace) => namespace.to_string(),
+        None if file == STDIN_PATH => clap::Error::with_description(
+            "a namespace must be given with --name when reading from the standard input",
+            clap::ErrorKind::MissingRequiredArgument
+        ).exit(),
+        None => PathBuf::from(file).file_stem().unwrap().to_str().unwrap().to_string()
+    };
+    let source = read_source(file)?;
+    let stem = if file == STDIN_PATH { &namespace } else { &file[0..file.len() - FILE_EXTENSION.len()] };
+    let (output, zip) = output(matches, PathBuf::from(stem));
 
-        if let Ok(result) = result {
-        } else if let Err(err) = result {
-            err.format(input);
+    Ok(Build {
+        sources: vec![source],
+        options: CompileOptions {
+            namespace,
+            target: target(matches),
+            debug: matches.is_present("debug"),
+            ..Default::default()
+        },
+        output,
+        zip,
+        icon: None,
+        inputs: if file == STDIN_PATH { vec![] } else { vec![PathBuf::from(file)] },
+        cache: if file == STDIN_PATH || matches.is_present("no-cache") {
+            None
+        } else {
+            // Each file has its own cache, or the builds of the files of a
+            // directory would prune the entries of each other.
+            let path = Path::new(file);
+            path.parent().map(|dir| dir.join(CACHE_DIR).join(path.file_stem().unwrap()))
         }
+    })
+}
+
+/// Reads the source `file`, or the standard input if it is [STDIN_PATH].
+fn read_source(file: &str) -> Result<Source, String> {
+    if file == STDIN_PATH {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)
+            .map_err(|err| format!("can't read the standard input: {}", err))?;
+        return Ok(Source { name: STDIN_NAME.into(), text });
     }
 
-    // let stdin = io::stdin();
-    // let lines: String = stdin.lock().lines()
-    //     .filter_map(|x| x.ok())
-    //     .collect::<Vec<String>>()
-    //     .join("\n") + "\n";
-    // let input = lines.as_str();
+    std::fs::read_to_string(file)
+        .map(|text| Source { name: file.to_string(), text })
+        .map_err(|err| format!("can't read `{}`: {}", file, err))
+}
+
+/// The project of the current directory.
+fn project() -> Result<Project, String> {
+    std::env::current_dir()
+        .map_err(|err| err.to_string())
+        .and_then(|dir| Project::discover(dir).map_err(|err| err.to_string()))
+}
+
+fn project_build(matches: &ArgMatches) -> Result<Build, String> {
+    let mut build = Build::project(&project()?).map_err(|err| err.to_string())?;
+
+    if let Some(namespace) = matches.value_of("namespace") {
+        build.options.namespace = namespace.to_string();
+    }
+    if matches.is_present("target") {
+        build.options.target = target(matches);
+    }
+    build.options.debug = matches.is_present("debug");
+    let (output, zip) = output(matches, build.output);
+    build.output = output;
+    build.zip = zip;
+    if matches.is_present("no-cache") {
+        build.cache = None;
+    }
+
+    Ok(build)
+}
+
+fn target(matches: &ArgMatches) -> Target {
+    matches.value_of("target").map_or_else(Target::default, |version| version.parse().unwrap())
+}
+
+/// The output path and whether it is a zip archive, with `default` being used
+/// when no path is given.
+fn output(matches: &ArgMatches, default: PathBuf) -> (PathBuf, bool) {
+    let output = matches.value_of("outdir").map_or(default, PathBuf::from);
+    let is_zip_path = output.to_string_lossy().ends_with(ZIP_EXTENSION);
+
+    match matches.value_of("format") {
+        Some("zip") if !is_zip_path && matches.value_of("outdir").is_none() => {
+            let mut output = output.into_os_string();
+            output.push(ZIP_EXTENSION);
+            (output.into(), true)
+        },
+        Some(format) => (output, format == "zip"),
+        None => (output, is_zip_path)
+    }
+}
+
+/// Compiles `build`, then writes it or prints its functions if `stdout` is
+/// set, reporting its errors. Returns whether it succeeded.
+fn compile(compiler: &Compiler, build: &Build, stdout: bool) -> bool {
+    let result = build.compile(compiler).map_err(|errors| report(&errors, &build.sources))
+        .and_then(|datapack| if stdout {
+            print(|out| datapack.print(out));
+            Ok(())
+        } else {
+            build.write(datapack).map_err(|err| eprintln!("error: {}", err))
+        });
+    result.is_ok()
+}
+
+fn test(build: Build) {
+    let datapack = compile_sources(&build.sources, build.options.clone()).unwrap_or_else(|errors| {
+        report(&errors, &build.sources);
+        std::process::exit(1);
+    });
+
+    let outcomes = testing::run_tests(&datapack);
+    print(|out| testing::report(out, &outcomes, &datapack, &build.sources));
+    if !outcomes.iter().all(|outcome| outcome.passed()) {
+        std::process::exit(1);
+    }
+}
+
+fn repl(matches: &ArgMatches) {
+    let options = CompileOptions {
+        namespace: matches.value_of("namespace").unwrap_or(REPL_NAMESPACE).to_string(),
+        target: target(matches),
+        ..Default::default()
+    };
+    let mut session = Session::new(options).unwrap_or_else(|errors| {
+        report(&errors, &[]);
+        std::process::exit(1);
+    });
+
+    let stderr = io::stderr();
+    let color = termion::is_tty(&stderr);
+    print(|out| repl::run(&mut session, io::stdin().lock(), out, &mut stderr.lock(), color));
+}
+
+/// Formats `sources`, the standard input being formatted to the standard
+/// output.
+fn format(sources: &[Source], check: bool) {
+    let (stdin, files): (Vec<_>, Vec<_>) = sources.iter().cloned().partition(|source| source.name == STDIN_NAME);
+    let mut failed = false;
+
+    for source in &stdin {
+        match format_str(&source.text) {
+            Ok(formatted) if !check => print(|out| out.write_all(formatted.as_bytes())),
+            Ok(formatted) if formatted != source.text => {
+                print(|out| writeln!(out, "would reformat {}", source.name));
+                failed = true;
+            },
+            Ok(_) => {},
+            Err(err) => {
+                report(&[err], std::slice::from_ref(source));
+                failed = true;
+            }
+        }
+    }
+
+    let (reformatted, errors) = format_files(&files, check).unwrap_or_else(|err| fail(err));
+    report(&errors, &files);
+    if check {
+        print(|out| reformatted.iter().try_for_each(|name| writeln!(out, "would reformat {}", name)));
+    }
+
+    if failed || !errors.is_empty() || (check && !reformatted.is_empty()) {
+        std::process::exit(1);
+    }
+}
+
+/// Writes to the standard output with `write`. A closed pipe, e.g. when the
+/// output goes through `head`, ends the program normally.
+fn print<F: FnOnce(&mut StdoutLock) -> io::Result<()>>(write: F) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match write(&mut out).and_then(|_| out.flush()) {
+        Ok(()) => {},
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(err) => fail(err)
+    }
+}
+
+/// Reports `errors` on the standard error, in colour if it is a terminal.
+fn report(errors: &[Diagnostic], sources: &[Source]) {
+    let stderr = io::stderr();
+    let color = termion::is_tty(&stderr);
+    // The diagnostics can't be reported anywhere else.
+    let _ = errors::report(&mut stderr.lock(), errors, sources, color);
 }
 
-fn compile(input: &str, options: CompileOptions) -> Result<(), CompilerError> {
-    let (_, bootstrap) = parser::parse(BOOTSTRAP).finish()?;
-    let (_, ast) = parser::parse(input).finish()?;
-    let ast = bootstrap + ast;
-    dbg!(&ast);
-    generator::generate(ast, options)
+fn fail<E: ToString>(err: E) -> ! {
+    eprintln!("error: {}", err.to_string());
+    std::process::exit(1);
 }
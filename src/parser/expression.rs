@@ -2,49 +2,56 @@ use crate::parser::{Span, err_msg, Position};
 use crate::parser::function::parse_function_call;
 use crate::parser::function::FunctionCall;
 use nom::branch::alt;
-use nom::combinator::{map, opt};
-use nom::sequence::{separated_pair, delimited};
-use crate::parser::{ws, ParseResult, identifier};
+use nom::combinator::{map, opt, consumed, not, verify};
+use nom::multi::many0;
+use nom::sequence::{delimited, preceded, terminated};
+use crate::parser::{ws, nested, ParseResult, identifier};
+use crate::errors::CompilerError;
 use nom::bytes::complete::{tag, take_until};
-use nom::character::complete::{digit1, alphanumeric0, one_of, char};
-use crate::parser::typing::parse_typing;
-use nom::Parser;
+use nom::character::complete::{digit1, anychar};
 use nom_locate::position;
-use nom::bytes::complete::escaped;
+use std::fmt;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expression {
-    Sum(Summand, Box<Expression>, Position),
+    /// `a + b + ...`, with at least two operands. They are summands, but
+    /// the last one, which may be a boolean the type checker reports.
+    Sum(Vec<Expression>, Position),
     Summand(Summand, Position),
-    Boolean(bool, Position)
+    Boolean(bool, Position),
+    /// `a == b`, comparing values of the same type.
+    Equality(Box<Expression>, Box<Expression>, Position)
 }
 
 impl Expression {
     pub fn pos(&self) -> &Position {
         match self {
-            Expression::Sum(_, _, pos) => pos,
+            Expression::Sum(_, pos) => pos,
             Expression::Summand(_, pos) => pos,
-            Expression::Boolean(_, pos) => pos
+            Expression::Boolean(_, pos) => pos,
+            Expression::Equality(_, _, pos) => pos
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Summand {
-    Multiplication(Term, Box<Summand>),
+    /// `a * b * ...`, with at least two terms.
+    Multiplication(Vec<Term>),
     Term(Term)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Term {
     Number(i32),
     String(String),
     FunctionCall(FunctionCall),
-    Variable(VariableName),
+    Variable(VariableName, Position),
     Expression(Box<Expression>)
 }
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+#[derive(Debug, Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VariableName {
     Dynamic(String),
     Static(String)
@@ -59,34 +66,93 @@ impl VariableName {
     }
 }
 
+impl fmt::Display for VariableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableName::Dynamic(name) => write!(f, "${}", name),
+            VariableName::Static(name) => write!(f, "{}", name)
+        }
+    }
+}
+
 pub(in super) fn parse_expression(input: Span) -> ParseResult<Expression> {
     let (_, pos) = position(input)?;
-    err_msg("invalid expression", alt((
-        map(tag("true"), move |_| Expression::Boolean(true, pos.into())),
-        map(tag("false"), move |_| Expression::Boolean(false, pos.into())),
+    let (input, left) = parse_sum(input)?;
 
-        map(separated_pair(parse_summand, ws(tag("+")), parse_expression),
-            move |(summand, expression)|
-                Expression::Sum(summand, Box::new(expression), pos.into())),
+    match preceded(ws(tag("==")), parse_sum)(input) {
+        Ok((input, right)) => Ok((input, Expression::Equality(Box::new(left), Box::new(right), pos.into()))),
+        Err(nom::Err::Error(_)) => Ok((input, left)),
+        Err(err) => Err(err)
+    }
+}
+
+fn parse_sum(input: Span) -> ParseResult<Expression> {
+    let (_, pos) = position(input)?;
+    err_msg("invalid expression", alt((
+        map(parse_boolean, move |value| Expression::Boolean(value, pos.into())),
+        move |input| {
+            // The operands are parsed in a loop rather than recursively, so
+            // that a long sum doesn't nest as deep as it is long. Each
+            // summand is only parsed once: backtracking on it would take
+            // exponential time in the nesting of parentheses.
+            let (mut input, summand) = parse_summand(input)?;
+            let mut operands = vec![Expression::Summand(summand, pos.into())];
+            loop {
+                match preceded(ws(tag("+")), parse_operand)(input) {
+                    Ok((rest, operand)) => {
+                        input = rest;
+                        let boolean = matches!(operand, Expression::Boolean(_, _));
+                        operands.push(operand);
+                        // A boolean ends the sum.
+                        if boolean {
+                            break;
+                        }
+                    },
+                    Err(nom::Err::Error(_)) => break,
+                    Err(err) => return Err(err)
+                }
+            }
 
-        map(parse_summand, move |summand|
-            Expression::Summand(summand, pos.into())),
+            if operands.len() == 1 {
+                return Ok((input, operands.remove(0)));
+            }
+            Ok((input, Expression::Sum(operands, pos.into())))
+        }
     )))(input)
 }
 
-pub(in super) fn parse_summand(input: Span) -> ParseResult<Summand> {
+/// An operand of a sum after the first one.
+fn parse_operand(input: Span) -> ParseResult<Expression> {
+    let (_, pos) = position(input)?;
     alt((
-        |input| {
-            let (input, term) = parse_term(input)?;
-            let (input, _) = ws(tag("*"))(input)?;
-            let (input, summand) = parse_summand(input)?;
-
-            Ok((input, Summand::Multiplication(term, Box::new(summand))))
-        },
-        map(parse_term, |term| Summand::Term(term))
+        map(parse_boolean, move |value| Expression::Boolean(value, pos.into())),
+        map(parse_summand, move |summand| Expression::Summand(summand, pos.into()))
     ))(input)
 }
 
+/// `true` or `false`, but not the start of an identifier like `trueish`.
+fn parse_boolean(input: Span) -> ParseResult<bool> {
+    terminated(
+        alt((
+            map(tag("true"), |_| true),
+            map(tag("false"), |_| false)
+        )),
+        not(verify(anychar, |c| c.is_ascii_alphanumeric() || *c == '_' || *c == '\''))
+    )(input)
+}
+
+pub(in super) fn parse_summand(input: Span) -> ParseResult<Summand> {
+    let (input, term) = parse_term(input)?;
+    let (input, mut terms) = many0(preceded(ws(tag("*")), parse_term))(input)?;
+
+    Ok((input, if terms.is_empty() {
+        Summand::Term(term)
+    } else {
+        terms.insert(0, term);
+        Summand::Multiplication(terms)
+    }))
+}
+
 pub(in super) fn parse_term(input: Span) -> ParseResult<Term> {
     alt((
         map(delimited(tag("\""),
@@ -94,19 +160,26 @@ pub(in super) fn parse_term(input: Span) -> ParseResult<Term> {
             tag("\"")),
             |str: Span| Term::String(str.fragment().to_string())),
 
-        map(digit1,
-            |d: Span| Term::Number(d.fragment().to_string().parse().unwrap())),
+        map(parse_number, Term::Number),
 
-        map(parse_function_call, |call| Term::FunctionCall(call)),
+        map(parse_function_call, Term::FunctionCall),
 
-        map(parse_variable, |var| Term::Variable(var)),
+        map(consumed(parse_variable), |(span, var)| Term::Variable(var, span.into())),
 
         delimited(ws(tag("(")),
-                  map(parse_expression, |expr| Term::Expression(Box::new(expr))),
+                  map(nested(parse_expression), |expr| Term::Expression(Box::new(expr))),
                   ws(tag(")")))
     ))(input)
 }
 
+fn parse_number(input: Span) -> ParseResult<i32> {
+    let (input, digits) = digit1(input)?;
+    match digits.fragment().parse() {
+        Ok(number) => Ok((input, number)),
+        Err(_) => Err(CompilerError::fail(digits, "integer literal is too large"))
+    }
+}
+
 pub(in super) fn parse_variable(input: Span) -> ParseResult<VariableName> {
     opt(tag("$"))(input).and_then(|(input, dollar)|
         map(identifier, |name|
@@ -1,43 +1,65 @@
 use crate::parser::{Span, Position};
-use nom::combinator::cut;
-use nom::error::context;
+use nom::combinator::{cut, consumed, not, opt};
+use nom::multi::many0;
 use crate::parser::expression::parse_expression;
-use nom::branch::alt;
 use crate::parser::expression::Expression;
 use crate::parser::statement::VariableSignature;
 use crate::parser::Statement;
-use crate::parser::statement::parse_block;
+use crate::parser::statement::parse_statement;
 use nom::combinator::map;
 use crate::parser::typing::parse_declaration_typing;
 use crate::parser::expression::parse_variable;
-use nom::sequence::pair;
+use nom::sequence::{pair, preceded, terminated};
 use nom::multi::separated_list0;
 use crate::parser::delimited;
 use crate::parser::typing::Typing;
 use crate::parser::expression::VariableName;
-use crate::parser::ws;
-use crate::parser::identifier;
+use crate::parser::{ws, nested};
 use nom::bytes::complete::tag;
 use crate::parser::ParseResult;
 use crate::generator::staticness::IsStatic;
-use nom::character::complete::{multispace0, multispace1};
 use nom_locate::position;
 use crate::errors::CompilerError;
+use std::fmt;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Function {
     pub signature: FunctionSignature,
     pub block: Vec<Statement>,
+    /// The `return` ending the block, if the function returns a value.
+    pub result: Option<Return>,
     pub position: Position
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// `return expr;`, which can only end the block of a dynamic function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Return {
+    pub expr: Expression,
+    /// The type of `expr`, inferred by the type checker.
+    pub typing: Typing,
+    pub position: Position
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FunctionSignature {
     pub name: VariableName,
     pub args: Vec<VariableSignature>
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for FunctionSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter()
+            .map(|arg| match arg.typing {
+                Typing::Unknown => arg.name.to_string(),
+                _ => format!("{}: {}", arg.name, arg.typing)
+            })
+            .collect();
+        write!(f, "{}({})", self.name, args.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionCall {
     pub name: VariableName,
     pub args: Vec<Expression>,
@@ -53,7 +75,7 @@ pub fn parse_function(input: Span) -> ParseResult<Function> {
         map(pair(parse_variable, parse_declaration_typing),
             |(name, typing)| VariableSignature { name, typing })
     )), ws(tag(")")))(input)?;
-    let (input, block) = parse_block(input)?;
+    let (input, (block, result)) = parse_body(input)?;
 
     let dyn_args: Vec<VariableSignature> = args.iter()
         .filter(|arg| arg.name.is_dynamic())
@@ -72,24 +94,53 @@ pub fn parse_function(input: Span) -> ParseResult<Function> {
     if name.is_static() && block.is_dynamic() {
         return Err(CompilerError::fail(position, "can't use dynamic statements in a static function"));
     }
+    if let (VariableName::Static(_), Some(result)) = (&name, &result) {
+        return Err(CompilerError::fail(result.position.clone(), "can't return a value from a static function"));
+    }
+
+    Ok((input, Function { signature, block, result, position: position.into() }))
+}
+
+/// The block of a function, which a `return` may end.
+fn parse_body(input: Span) -> ParseResult<(Vec<Statement>, Option<Return>)> {
+    let (input, _) = ws(tag("{"))(input)?;
+    let (input, (block, result)) = nested(pair(
+        many0(ws(parse_body_statement)),
+        opt(ws(parse_return))
+    ))(input)?;
+
+    match ws(tag::<_, _, CompilerError>("}"))(input) {
+        Ok((input, _)) => Ok((input, (block, result))),
+        Err(_) if result.is_some() => Err(CompilerError::fail(input, "`return` must be the last statement of a function")),
+        Err(err) => Err(err)
+    }
+}
+
+/// A statement of a function's block, which stops at its `return`.
+fn parse_body_statement(input: Span) -> ParseResult<Statement> {
+    preceded(not(tag("return ")), parse_statement)(input)
+}
+
+pub(in super) fn parse_return(input: Span) -> ParseResult<Return> {
+    let (input, position) = position(input)?;
+    let (input, expr) = preceded(tag("return "), cut(terminated(ws(parse_expression), ws(tag(";")))))(input)?;
 
-    Ok((input, Function { signature, block, position: position.into() }))
+    Ok((input, Return { expr, typing: Typing::Unknown, position: position.into() }))
 }
 
 pub fn parse_function_call(input: Span) -> ParseResult<FunctionCall> {
-    let (_, position) = position(input)?;
-    let (input, name) = parse_variable(input)?;
+    let (input, (position, name)) = consumed(parse_variable)(input)?;
     let (input, args) = delimited(
         ws(tag("(")),
         separated_list0(
             ws(tag(",")),
-            parse_expression,
+            nested(parse_expression),
         ),
         ws(tag(")"))
     )(input)?;
 
     if name.is_static() && args.iter().any(|arg| arg.is_dynamic()) {
-        panic!("can't call a static function with dynamic arguments")
+        return Err(CompilerError::fail(position, "can't call a static function with dynamic arguments"));
     }
 
     Ok((input, FunctionCall {
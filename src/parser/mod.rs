@@ -2,26 +2,27 @@ pub mod expression;
 pub mod statement;
 pub mod function;
 pub mod typing;
+pub mod pretty;
+// An operator-precedence parser meant to replace the recursive descent of
+// expressions, not wired in yet.
+#[allow(dead_code)]
 mod shunting_yard;
 
 use nom_locate::LocatedSpan;
-use nom::error::ErrorKind;
 use nom::combinator::{all_consuming, verify};
-use nom::{IResult, Offset, Parser};
-use nom::multi::{many0, many1};
+use nom::IResult;
+use nom::multi::many0;
 use nom::sequence::{delimited, terminated};
-use nom::bytes::complete::tag;
-use nom::character::complete::{not_line_ending, line_ending as eol, multispace0, alpha1, alphanumeric0, one_of, anychar};
-use nom::error::ParseError;
+use nom::character::complete::{not_line_ending, line_ending as eol, multispace0, alpha1, one_of, anychar};
 use crate::parser::statement::{Statement, parse_statement};
-use nom_greedyerror::GreedyError;
+use crate::parser::expression::Expression;
 use crate::errors::CompilerError;
-use nom::Err;
 use std::ops::Add;
 use nom::branch::alt;
-use nom::character::is_alphanumeric;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct AST {
     pub statements: Vec<Statement>
 }
@@ -31,15 +32,31 @@ impl Add for AST {
 
     fn add(self, other: AST) -> Self::Output {
         AST {
-            statements: vec![self.statements, other.statements].concat()
+            statements: [self.statements, other.statements].concat()
         }
     }
 }
 
-pub type Span<'a> = LocatedSpan<&'a str>;
+/// Identifies a source file, when several of them are compiled together.
+pub type FileId = usize;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+pub type Span<'a> = LocatedSpan<&'a str, SpanState>;
+
+/// What the parser carries along its input, besides its location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanState {
+    pub file: FileId,
+    /// How many [nested] parsers the input is parsed by.
+    pub nesting: usize
+}
+
+fn new_span(input: &str, file: FileId) -> Span<'_> {
+    Span::new_extra(input, SpanState { file, nesting: 0 })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
 pub struct Position {
+    pub file: FileId,
     pub offset: usize,
     pub length: usize,
     pub line: usize,
@@ -49,6 +66,7 @@ pub struct Position {
 impl From<Span<'_>> for Position {
     fn from(span: Span) -> Self {
         Position {
+            file: span.extra.file,
             offset: span.location_offset(),
             length: span.fragment().len(),
             line: span.location_line() as usize,
@@ -57,16 +75,14 @@ impl From<Span<'_>> for Position {
     }
 }
 
-impl Default for Position {
-    fn default() -> Self {
-        Position { offset: 0, length: 0, line: 0, column: 0 }
-    }
-}
-
 type ParseResult<'a, T> = IResult<Span<'a>, T, CompilerError>;
 
-pub fn parse(input: &str) -> ParseResult<AST> {
-    let input = Span::new(input);
+pub fn parse(input: &str) -> ParseResult<'_, AST> {
+    parse_file(input, 0)
+}
+
+pub fn parse_file(input: &str, file: FileId) -> ParseResult<'_, AST> {
+    let input = new_span(input, file);
     let (input, statements) = all_consuming(many0(ws(parse_statement)))(input)?;
 
     Ok((input, AST {
@@ -74,31 +90,51 @@ pub fn parse(input: &str) -> ParseResult<AST> {
     }))
 }
 
+/// Parses a lone expression, e.g. one typed in `tag repl`.
+pub fn parse_expression_file(input: &str, file: FileId) -> ParseResult<'_, Expression> {
+    let input = new_span(input, file);
+    all_consuming(ws(expression::parse_expression))(input)
+}
+
 fn identifier(input: Span) -> ParseResult<String> {
     err_msg("invalid identifier", |input| {
         let (input, first) = alpha1(input)?;
         let (input, second) = many0(alt((
             one_of("_'"),
-            verify(anychar, |char| is_alphanumeric(*char as u8))
+            verify(anychar, |char| char.is_ascii_alphanumeric())
         )))(input)?;
 
         Ok((input, first.fragment().to_string() + second.iter().collect::<String>().as_str()))
     })(input)
 }
 
+/// How deeply blocks and expressions can be nested. Deeper sources are
+/// rejected, as every later pass recurses over the AST.
+pub const MAX_NESTING: usize = 64;
+
+/// Runs `parser` one level of nesting deeper, failing past `MAX_NESTING`
+/// rather than overflowing the stack.
+fn nested<'a, T, F>(mut parser: F) -> impl FnMut(Span<'a>) -> ParseResult<T>
+    where F: FnMut(Span<'a>) -> ParseResult<T>
+{
+    move |mut input: Span<'a>| {
+        let depth = input.extra.nesting;
+        if depth >= MAX_NESTING {
+            return Err(CompilerError::fail(input, "too deeply nested"));
+        }
+
+        input.extra.nesting = depth + 1;
+        let (mut input, value) = parser(input)?;
+        input.extra.nesting = depth;
+        Ok((input, value))
+    }
+}
+
 fn read_line(input: Span) -> ParseResult<String> {
     let (input, line) = terminated(not_line_ending, eol)(input)?;
     Ok((input, line.fragment().to_string()))
 }
 
-fn end_of_line(input: Span) -> ParseResult<Span> {
-    if input.is_empty() {
-        Ok((input.clone(), input))
-    } else {
-        eol(input)
-    }
-}
-
 fn ws<'a, T, F>(inner: F) -> impl FnMut(Span<'a>) -> ParseResult<T>
     where F: Fn(Span<'a>) -> ParseResult<T>
 {
@@ -115,11 +151,13 @@ pub fn err_msg<'a, S, T, F>(msg: S, mut parser: F)
           F: FnMut(Span<'a>) -> ParseResult<T>
 {
     move |input| {
-        parser(input).map_err(|err|
-           err.map(|comp_err| CompilerError {
-               error: msg.to_string(),
-               position: comp_err.position
-           })
-        )
+        // Failures keep their own, more precise, message.
+        parser(input).map_err(|err| match err {
+            nom::Err::Error(comp_err) => nom::Err::Error(CompilerError {
+                error: msg.to_string(),
+                ..comp_err
+            }),
+            err => err
+        })
     }
 }
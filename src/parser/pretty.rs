@@ -0,0 +1,277 @@
+//! Prints an AST back to source, in the style of the examples: blocks
+//! indented with tabs, and function declarations and tests separated from
+//! their neighbours by a blank line.
+//!
+//! Parsing the printed source gives back the same AST, positions aside.
+//! Commands are printed as they were written, apart from the expressions
+//! they interpolate.
+
+use crate::parser::AST;
+use crate::parser::statement::{Statement, IfStatement, Command, VariableAssignment, VariableSignature};
+use crate::parser::function::{Function, FunctionCall};
+use crate::parser::expression::{Expression, Summand, Term};
+use crate::parser::typing::Typing;
+
+pub fn print(ast: &AST) -> String {
+    let mut printer = Printer { out: String::new(), depth: 0, source: None };
+    printer.print_statements(&ast.statements);
+    printer.out
+}
+
+/// Prints `ast`, parsed from `source`, keeping the blank lines separating
+/// its statements (several of them in a row being kept as one).
+pub fn format(ast: &AST, source: &str) -> String {
+    let mut printer = Printer { out: String::new(), depth: 0, source: Some(source) };
+    printer.print_statements(&ast.statements);
+    printer.out
+}
+
+pub fn print_expression(expr: &Expression) -> String {
+    let mut printer = Printer { out: String::new(), depth: 0, source: None };
+    printer.print_expression(expr);
+    printer.out
+}
+
+struct Printer<'a> {
+    out: String,
+    depth: usize,
+    /// The source the AST was parsed from, if it is being formatted.
+    source: Option<&'a str>
+}
+
+impl Printer<'_> {
+    fn line(&mut self) {
+        self.out += &"\t".repeat(self.depth);
+    }
+
+    fn print_statements(&mut self, statements: &[Statement]) {
+        for (i, statement) in statements.iter().enumerate() {
+            let separated = |statement: &Statement| matches!(statement, Statement::FunctionDeclaration(_) | Statement::Test(_));
+            if i > 0 && (separated(statement) || separated(&statements[i - 1]) || self.blank_line_before(statement)) {
+                self.out += "\n";
+            }
+            self.print_statement(statement);
+        }
+    }
+
+    /// Whether the source has a blank line right before `statement`.
+    fn blank_line_before(&self, statement: &Statement) -> bool {
+        let source = match self.source {
+            Some(source) => source,
+            None => return false
+        };
+
+        let before = source.get(..statement.pos().offset).unwrap_or_default().trim_end();
+        // An `if` statement is positioned at its condition.
+        let before = match statement {
+            Statement::IfStatement(_) => before.strip_suffix("if").map_or(before, str::trim_end),
+            _ => before
+        };
+        let whitespace = &source[before.len()..statement.pos().offset];
+        whitespace.chars().take_while(|c| c.is_whitespace()).filter(|c| *c == '\n').count() > 1
+    }
+
+    /// Whether the source has a blank line right before `offset`.
+    fn blank_line_at(&self, offset: usize) -> bool {
+        let source = match self.source {
+            Some(source) => source,
+            None => return false
+        };
+
+        let before = source.get(..offset).unwrap_or_default().trim_end();
+        source[before.len()..offset].chars().filter(|c| *c == '\n').count() > 1
+    }
+
+    fn print_block(&mut self, statements: &[Statement]) {
+        self.out += "{\n";
+        self.depth += 1;
+        self.print_statements(statements);
+        self.depth -= 1;
+        self.line();
+        self.out += "}";
+    }
+
+    fn print_statement(&mut self, statement: &Statement) {
+        self.line();
+        match statement {
+            Statement::Command(cmd) => self.print_command(cmd),
+            Statement::IfStatement(if_stmt) => {
+                self.print_if_statement(if_stmt);
+                self.out += "\n";
+            },
+            Statement::VariableAssignment(assignment) => {
+                self.print_variable_assignment(assignment);
+                self.out += ";\n";
+            },
+            Statement::FunctionDeclaration(func) => {
+                self.print_function(func);
+                self.out += "\n";
+            },
+            Statement::FunctionCall(call) => {
+                self.print_function_call(call);
+                self.out += ";\n";
+            },
+            Statement::Test(test) => {
+                self.out += &format!("test \"{}\" ", test.name);
+                self.print_block(&test.block);
+                self.out += "\n";
+            },
+            Statement::Assert(assert) => {
+                self.out += "assert ";
+                self.print_expression(&assert.expr);
+                self.out += ";\n";
+            }
+        }
+    }
+
+    fn print_command(&mut self, cmd: &Command) {
+        self.out += "/";
+        for (string, expr) in &cmd.start {
+            self.out += string;
+            self.out += "#{";
+            self.print_expression(expr);
+            self.out += "}";
+        }
+        self.out += &cmd.end;
+        self.out += "\n";
+    }
+
+    fn print_if_statement(&mut self, if_stmt: &IfStatement) {
+        self.out += "if ";
+        self.print_expression(&if_stmt.expr);
+        self.out += " ";
+        self.print_block(&if_stmt.block);
+
+        if let Some(else_if) = if_stmt.else_if.as_ref() {
+            self.out += " else ";
+            self.print_if_statement(else_if);
+        } else if let Some(else_block) = &if_stmt.else_block {
+            self.out += " else ";
+            self.print_block(else_block);
+        }
+    }
+
+    fn print_variable_assignment(&mut self, assignment: &VariableAssignment) {
+        self.print_signature(&assignment.signature);
+        self.out += " := ";
+        self.print_expression(&assignment.value);
+    }
+
+    fn print_signature(&mut self, signature: &VariableSignature) {
+        self.out += &signature.name.to_string();
+        if signature.typing != Typing::Unknown {
+            self.out += &format!(": {}", signature.typing);
+        }
+    }
+
+    fn print_function(&mut self, func: &Function) {
+        self.out += &format!("def {}(", func.signature.name);
+        for (i, arg) in func.signature.args.iter().enumerate() {
+            if i > 0 {
+                self.out += ", ";
+            }
+            self.print_signature(arg);
+        }
+        self.out += ") {\n";
+        self.depth += 1;
+        self.print_statements(&func.block);
+        if let Some(result) = &func.result {
+            if !func.block.is_empty() && self.blank_line_at(result.position.offset) {
+                self.out += "\n";
+            }
+            self.line();
+            self.out += "return ";
+            self.print_expression(&result.expr);
+            self.out += ";\n";
+        }
+        self.depth -= 1;
+        self.line();
+        self.out += "}";
+    }
+
+    fn print_function_call(&mut self, call: &FunctionCall) {
+        self.out += &format!("{}(", call.name);
+        for (i, arg) in call.args.iter().enumerate() {
+            if i > 0 {
+                self.out += ", ";
+            }
+            self.print_expression(arg);
+        }
+        self.out += ")";
+    }
+
+    fn print_expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Sum(operands, _) => {
+                for (i, operand) in operands.iter().enumerate() {
+                    if i > 0 {
+                        self.out += " + ";
+                    }
+                    self.print_expression(operand);
+                }
+            },
+            Expression::Summand(summand, _) => self.print_summand(summand),
+            Expression::Boolean(value, _) => self.out += &value.to_string(),
+            Expression::Equality(left, right, _) => {
+                self.print_expression(left);
+                self.out += " == ";
+                self.print_expression(right);
+            }
+        }
+    }
+
+    fn print_summand(&mut self, summand: &Summand) {
+        match summand {
+            Summand::Multiplication(terms) => {
+                for (i, term) in terms.iter().enumerate() {
+                    if i > 0 {
+                        self.out += " * ";
+                    }
+                    self.print_term(term);
+                }
+            },
+            Summand::Term(term) => self.print_term(term)
+        }
+    }
+
+    fn print_term(&mut self, term: &Term) {
+        match term {
+            Term::Number(n) => self.out += &n.to_string(),
+            Term::String(str) => self.out += &format!("\"{}\"", str),
+            Term::FunctionCall(call) => self.print_function_call(call),
+            Term::Variable(var, _) => self.out += &var.to_string(),
+            Term::Expression(expr) => {
+                self.out += "(";
+                self.print_expression(expr);
+                self.out += ")";
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use nom::Finish;
+
+    #[test]
+    fn test_print() {
+        let src = "def $log($content, interpret: bool) {\n\t/tellraw @a #{$content + \"!\"} #{interpret}\n}\n\n\
+                   def $double($n) {\n\treturn $n * 2;\n}\n\n\
+                   $x := (1 + 2) * $double(1) + 3;\nif $x == 9 {\n\t$log(\"a\", true);\n} else if false {\n} else {\n\t/say b\n}\n";
+        let (_, ast) = parse(src).finish().unwrap();
+        assert_eq!(print(&ast), src);
+    }
+
+    #[test]
+    fn test_format() {
+        let src = "def $f($a,$b){/say #{ $a+$b*2 }   done\n\n\n  return   $a;}\n$x:int:=1;\n\n\n\n  if $x==1 {\n\n$f(1,2);}\n/say\n";
+        let formatted = "def $f($a, $b) {\n\t/say #{$a + $b * 2}   done\n\n\treturn $a;\n}\n\n$x: int := 1;\n\nif $x == 1 {\n\t$f(1, 2);\n}\n/say\n";
+        let (_, ast) = parse(src).finish().unwrap();
+        assert_eq!(format(&ast, src), formatted);
+
+        let (_, ast) = parse(formatted).finish().unwrap();
+        assert_eq!(format(&ast, formatted), formatted);
+    }
+}
@@ -2,8 +2,6 @@ use crate::parser::{Span, ParseResult, ws};
 use nom::branch::alt;
 use nom::combinator::map;
 use nom::bytes::complete::tag;
-use nom::multi::many0;
-use nom::sequence::pair;
 use std::cmp::Ordering;
 use nom::character::complete::digit1;
 use std::str::FromStr;
@@ -21,7 +19,7 @@ macro_rules! precedence {
         impl Operator {
             fn get_precedence(&self) -> i32 {
                 match self {
-                    $($key => $value),*
+                    $($name::$key => $value),*
                 }
             }
         }
@@ -55,18 +53,11 @@ impl Operator {
     }
 
     fn is_unary(&self) -> bool {
-        match self {
-            Operator::Neg => true,
-            Operator::Not => true,
-            _ => false
-        }
+        matches!(self, Operator::Neg | Operator::Not)
     }
 
     fn is_sentinel(&self) -> bool {
-        match self {
-            Operator::Sentinel => true,
-            _ => false
-        }
+        matches!(self, Operator::Sentinel)
     }
 }
 
@@ -150,7 +141,7 @@ fn p<'a>(
         operators.pop();
         input = input2;
     } else if let Ok((input2, op)) = unary(input) {
-        push_operator(input2, op, operators, operands);
+        push_operator(input2, op, operators, operands)?;
         let (input2, _) = p(input2, operators, operands)?;
         input = input2;
     } else {
@@ -161,7 +152,7 @@ fn p<'a>(
 }
 
 fn e<'a>(
-    mut input: Span<'a>,
+    input: Span<'a>,
     operators: &mut Vec<Operator>,
     operands: &mut Vec<Tree>
 ) -> ParseResult<'a, ()> {
@@ -186,7 +177,7 @@ fn push_operator<'a>(
     operands: &mut Vec<Tree>
 ) -> ParseResult<'a, ()> {
     while operators.last().ok_or(CompilerError::syntax_error(input))? > &operator {
-        pop_operator(input, operators, operands);
+        pop_operator(input, operators, operands)?;
     }
     operators.push(convert_operator(operator));
 
@@ -227,10 +218,11 @@ fn shunting_yard(input: Span) -> ParseResult<Tree> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::new_span;
 
     #[test]
     fn test() {
-        let tree = shunting_yard(Span::new("(1 && 2) || 3"));
+        let tree = shunting_yard(new_span("(1 && 2) || 3", 0));
         println!("{:?}", tree);
     }
 }
@@ -2,42 +2,49 @@ use crate::parser::{Span, Position};
 use crate::parser::function::parse_function_call;
 use crate::parser::function::FunctionCall;
 use nom::bytes::complete::take_until;
-use nom::combinator::{into, cut};
-use nom::multi::many1;
-use nom::character::is_newline;
+use nom::combinator::{cut, consumed};
 use nom::combinator::verify;
-use nom::character::complete::anychar;
 use nom::sequence::pair;
-use crate::parser::end_of_line;
-use crate::parser::function::parse_function;
+use crate::parser::function::{parse_function, parse_return};
+use crate::errors::CompilerError;
 use crate::parser::function::Function;
-use crate::parser::{ParseResult, ws, read_line};
+use crate::parser::{ParseResult, ws, nested, read_line};
 use nom::branch::alt;
-use nom::combinator::{map, success};
+use nom::combinator::map;
 use crate::parser::expression::{Expression, VariableName, parse_expression, parse_variable};
 use nom::sequence::{preceded, delimited, terminated};
 use nom::bytes::complete::tag;
 use nom::multi::many0;
 use crate::parser::typing::{Typing, parse_declaration_typing};
-use std::iter::FromIterator;
-use crate::generator::staticness::IsStatic;
-use nom::character::complete::alphanumeric0;
 use nom_locate::position;
-use nom_greedyerror::{GreedyErrorKind, GreedyError};
-use nom::error::{make_error, ErrorKind};
-use crate::errors::CompilerError;
-use crate::parser::expression;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Statement {
     Command(Command),
     IfStatement(IfStatement),
     VariableAssignment(VariableAssignment),
     FunctionDeclaration(Function),
-    FunctionCall(FunctionCall)
+    FunctionCall(FunctionCall),
+    Test(Test),
+    Assert(Assert)
+}
+
+impl Statement {
+    pub fn pos(&self) -> &Position {
+        match self {
+            Statement::Command(cmd) => &cmd.position,
+            Statement::IfStatement(if_stmt) => if_stmt.expr.pos(),
+            Statement::VariableAssignment(assignment) => &assignment.position,
+            Statement::FunctionDeclaration(func) => &func.position,
+            Statement::FunctionCall(call) => &call.position,
+            Statement::Test(test) => &test.position,
+            Statement::Assert(assert) => &assert.position
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IfStatement {
     pub expr: Expression,
     pub block: Vec<Statement>,
@@ -45,20 +52,35 @@ pub struct IfStatement {
     pub else_if: Box<Option<IfStatement>>
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Command {
     pub start: Vec<(String, Expression)>,
     pub end: String,
     pub position: Position
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// `test "name" { ... }`, only compiled for `tag test`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Test {
+    pub name: String,
+    pub block: Vec<Statement>,
+    pub position: Position
+}
+
+/// `assert expr;`, failing the test running it when `expr` is false.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Assert {
+    pub expr: Expression,
+    pub position: Position
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct VariableSignature {
     pub name: VariableName,
     pub typing: Typing
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VariableAssignment {
     pub signature: VariableSignature,
     pub value: Expression,
@@ -68,23 +90,32 @@ pub struct VariableAssignment {
 pub(in super) fn parse_block(input: Span) -> ParseResult<Vec<Statement>> {
     delimited(
         ws(tag("{")),
-        many0(ws(parse_statement)),
+        nested(many0(ws(parse_statement))),
         ws(tag("}"))
     )(input)
 }
 
 pub(in super) fn parse_statement(input: Span) -> ParseResult<Statement> {
+    // Functions parse the `return` ending their block themselves.
+    if let Ok((_, result)) = parse_return(input) {
+        return Err(CompilerError::fail(result.position, "`return` can only end the block of a function"));
+    }
+
     alt((
         map(parse_command,
-            |cmd| Statement::Command(cmd)),
+            Statement::Command),
         map(parse_if_statement,
-            |if_stmt| Statement::IfStatement(if_stmt)),
+            Statement::IfStatement),
+        map(parse_test,
+            Statement::Test),
+        map(terminated(parse_assert, ws(tag(";"))),
+            Statement::Assert),
         map(terminated(parse_variable_declaration, ws(tag(";"))),
-            |var| Statement::VariableAssignment(var)),
+            Statement::VariableAssignment),
         map(parse_function, 
-            |function| Statement::FunctionDeclaration(function)),
+            Statement::FunctionDeclaration),
         map(terminated(parse_function_call, ws(tag(";"))),
-            |call| Statement::FunctionCall(call))
+            Statement::FunctionCall)
     ))(input)
 }
 
@@ -110,7 +141,7 @@ pub(in super) fn parse_if_statement(input: Span) -> ParseResult<IfStatement> {
     // >   }
     // > }
     if let Ok((input, else_if))
-    = preceded(ws(tag("else ")), parse_if_statement)(input)
+    = preceded(ws(tag("else ")), nested(parse_if_statement))(input)
     {
         Ok((input, IfStatement {
             expr,
@@ -137,6 +168,22 @@ pub(in super) fn parse_if_statement(input: Span) -> ParseResult<IfStatement> {
     }
 }
 
+pub(in super) fn parse_test(input: Span) -> ParseResult<Test> {
+    let (input, position) = position(input)?;
+    let (input, _) = ws(tag("test "))(input)?;
+    let (input, name) = delimited(tag("\""), take_until("\""), tag("\""))(input)?;
+    let (input, block) = parse_block(input)?;
+
+    Ok((input, Test { name: name.fragment().to_string(), block, position: position.into() }))
+}
+
+pub(in super) fn parse_assert(input: Span) -> ParseResult<Assert> {
+    let (input, position) = position(input)?;
+    let (input, expr) = preceded(tag("assert "), ws(parse_expression))(input)?;
+
+    Ok((input, Assert { expr, position: position.into() }))
+}
+
 pub fn parse_command(input: Span) -> ParseResult<Command> {
     let (input, position) = position(input)?;
     let (input, _) = tag("/")(input)?;
@@ -144,21 +191,13 @@ pub fn parse_command(input: Span) -> ParseResult<Command> {
         pair(
             verify(
                 take_until("#{"),
-                |string: &Span| string.fragment().chars().all(|c| !is_newline(c as u8))
+                |string: &Span| !string.fragment().contains('\n')
             ),
             delimited(tag("#{"), cut(ws(parse_expression)), tag("}"))
         )
     )(input)?;
     let (input, end) = read_line(input)?;
 
-    for (_, expr) in &start {
-        if expr.is_dynamic() {
-            return Err(CompilerError::fail(expr.pos().clone(),
-                                           "can't interpolate a dynamic value in a command"));
-        }
-    }
-
-
     let start: Vec<(String, Expression)> = start.into_iter()
         .map(|(a, b)| (a.fragment().to_string(), b))
         .collect();
@@ -169,8 +208,7 @@ pub fn parse_command(input: Span) -> ParseResult<Command> {
 pub(in super) fn parse_variable_declaration(input: Span)
     -> ParseResult<VariableAssignment>
 {
-    let (_, position) = position(input)?;
-    let (input, name) = parse_variable(input)?;
+    let (input, (position, name)) = consumed(parse_variable)(input)?;
     let (input, typing) = ws(parse_declaration_typing)(input)?;
     let (input, value) = preceded(ws(tag(":=")), parse_expression)(input)?;
 
@@ -1,11 +1,13 @@
 use crate::parser::{Span, err_msg};
 use crate::parser::{ParseResult, ws};
 use nom::branch::alt;
-use nom::combinator::{map, opt, map_res, success, cut};
+use nom::combinator::{map, success};
 use nom::bytes::complete::tag;
 use nom::sequence::preceded;
+use std::fmt;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Typing {
     Integer,
     String,
@@ -13,10 +15,22 @@ pub enum Typing {
     Unknown
 }
 
+impl fmt::Display for Typing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Typing::Integer => write!(f, "int"),
+            Typing::String => write!(f, "string"),
+            Typing::Boolean => write!(f, "bool"),
+            Typing::Unknown => write!(f, "unknown")
+        }
+    }
+}
+
 pub(in super) fn parse_typing(input: Span) -> ParseResult<Typing> {
     err_msg("invalid type", alt((
         map(tag("int"), |_| Typing::Integer),
         map(tag("string"), |_| Typing::String),
+        map(tag("bool"), |_| Typing::Boolean),
     )))(input)
 }
 
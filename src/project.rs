@@ -0,0 +1,187 @@
+//! Projects, described by a `tag.toml` manifest:
+//!
+//! ```toml
+//! [pack]
+//! namespace = "hello"
+//! description = "Says hello"
+//! entry = ["src/main.tag"]
+//! output = "build/hello.zip"
+//! target = "1.20.4"
+//! icon = "pack.png"
+//! storage = "hello:runtime"
+//! objective = "__hello__"
+//!
+//! [defines]
+//! greeting = "hello world"
+//! ```
+
+use crate::{CompileOptions, Define, Source, Target};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+pub const MANIFEST_FILE: &str = "tag.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub pack: PackManifest,
+    #[serde(default)]
+    pub defines: BTreeMap<String, Define>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackManifest {
+    pub namespace: String,
+    pub description: Option<String>,
+    /// The source files, compiled in order.
+    pub entry: Entries,
+    /// Where the datapack is written, relative to the project. A path ending
+    /// with `.zip` gives an archive. Defaults to `build/<namespace>`.
+    pub output: Option<PathBuf>,
+    /// The Minecraft version the datapack is made for, e.g. `1.16.5`.
+    pub target: Option<String>,
+    pub icon: Option<PathBuf>,
+    /// The storage and objective used at runtime, derived from the
+    /// namespace by default.
+    pub storage: Option<String>,
+    pub objective: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Entries {
+    One(PathBuf),
+    Many(Vec<PathBuf>)
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    NotFound(PathBuf),
+    Io(PathBuf, io::Error),
+    Manifest(PathBuf, toml::de::Error),
+    Target(String)
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::NotFound(dir) =>
+                write!(f, "no `{}` found in `{}` or any parent directory", MANIFEST_FILE, dir.display()),
+            ProjectError::Io(path, err) => write!(f, "can't read `{}`: {}", path.display(), err),
+            ProjectError::Manifest(path, err) => write!(f, "invalid manifest `{}`: {}", path.display(), err),
+            ProjectError::Target(err) => write!(f, "invalid target: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    /// The directory containing the manifest.
+    pub root: PathBuf,
+    pub manifest: Manifest
+}
+
+impl Project {
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Project, ProjectError> {
+        let root = root.as_ref().to_path_buf();
+        let path = root.join(MANIFEST_FILE);
+        let manifest = fs::read_to_string(&path).map_err(|err| ProjectError::Io(path.clone(), err))?;
+        let manifest = toml::from_str(&manifest).map_err(|err| ProjectError::Manifest(path, err))?;
+
+        Ok(Project { root, manifest })
+    }
+
+    /// Loads the project containing `dir`, looking for a manifest
+    /// in `dir` and its ancestors.
+    pub fn discover<P: AsRef<Path>>(dir: P) -> Result<Project, ProjectError> {
+        let dir = dir.as_ref();
+        dir.ancestors()
+            .find(|dir| dir.join(MANIFEST_FILE).is_file())
+            .map_or_else(|| Err(ProjectError::NotFound(dir.to_path_buf())), Project::load)
+    }
+
+    pub fn entries(&self) -> Vec<PathBuf> {
+        let entries = match &self.manifest.pack.entry {
+            Entries::One(entry) => vec![entry.clone()],
+            Entries::Many(entries) => entries.clone()
+        };
+        entries.into_iter().map(|entry| self.root.join(entry)).collect()
+    }
+
+    pub fn output(&self) -> PathBuf {
+        let pack = &self.manifest.pack;
+        self.root.join(pack.output.clone().unwrap_or_else(|| Path::new("build").join(&pack.namespace)))
+    }
+
+    pub fn read_sources(&self) -> Result<Vec<Source>, ProjectError> {
+        self.entries().into_iter().map(|path| {
+            let text = fs::read_to_string(&path).map_err(|err| ProjectError::Io(path.clone(), err))?;
+            Ok(Source { name: path.display().to_string(), text })
+        }).collect()
+    }
+
+    pub fn read_icon(&self) -> Result<Option<Vec<u8>>, ProjectError> {
+        self.manifest.pack.icon.as_ref().map(|icon| {
+            let path = self.root.join(icon);
+            fs::read(&path).map_err(|err| ProjectError::Io(path, err))
+        }).transpose()
+    }
+
+    pub fn options(&self) -> Result<CompileOptions, ProjectError> {
+        let pack = &self.manifest.pack;
+        let mut defines = self.manifest.defines.clone();
+
+        if let Some(description) = &pack.description {
+            defines.insert("pack_description".into(), Define::String(description.clone()));
+        }
+        let target = match &pack.target {
+            Some(target) => target.parse().map_err(ProjectError::Target)?,
+            None => Target::default()
+        };
+
+        Ok(CompileOptions {
+            namespace: pack.namespace.clone(),
+            defines,
+            target,
+            storage: pack.storage.clone(),
+            objective: pack.objective.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join(MANIFEST_FILE), r#"
+            [pack]
+            namespace = "hello"
+            description = "Says hello"
+            entry = "src/main.tag"
+            target = "1.15.2"
+
+            [defines]
+            greeting = "hello world"
+            times = 3
+        "#).unwrap();
+
+        let project = Project::discover(dir.path().join("src")).unwrap();
+        assert_eq!(project.entries(), vec![dir.path().join("src/main.tag")]);
+        assert_eq!(project.output(), dir.path().join("build/hello"));
+
+        let options = project.options().unwrap();
+        assert_eq!(options.target.pack_format(), 5);
+        assert_eq!(options.defines["pack_description"], Define::String("Says hello".into()));
+        assert_eq!(options.defines["times"], Define::Integer(3));
+    }
+}
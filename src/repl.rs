@@ -0,0 +1,252 @@
+//! Evaluates sources one input at a time, for `tag repl`.
+//!
+//! Every input is checked and generated after the ones before it, as if
+//! they were a single source: it can use their variables and functions.
+//! A static expression gives its value, while statements and dynamic
+//! expressions give the commands they generate.
+//!
+//! ```
+//! use tag_lang::CompileOptions;
+//! use tag_lang::repl::{Output, Session};
+//!
+//! let options = CompileOptions { namespace: "repl".into(), ..Default::default() };
+//! let mut session = Session::new(options).unwrap();
+//! session.eval("a := 20;\n").unwrap();
+//! assert_eq!(session.eval("a * 2 + 2").unwrap(), Output::Value("42".into()));
+//! ```
+
+use crate::{check_sources, errors, generator, CompileOptions, Diagnostic, Source};
+use crate::datapack::print_function;
+use crate::generator::Generator;
+use crate::generator::staticness::IsStatic;
+use crate::parser::{self, AST};
+use crate::parser::statement::{Command, Statement};
+use crate::parser::expression::Expression;
+use nom::Finish;
+use std::io::{self, BufRead, Write};
+
+/// The name of the inputs, in diagnostics.
+pub const INPUT_NAME: &str = "<input>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// The value of a static expression.
+    Value(String),
+    /// The lines generated for each function, the top level coming first.
+    Commands(Vec<(String, Vec<String>)>)
+}
+
+pub struct Session {
+    options: CompileOptions,
+    /// Every input, the ones that failed included, numbered like the
+    /// sources of [crate::compile_sources].
+    sources: Vec<Source>,
+    /// The statements of the bootstrap, the defines and the inputs
+    /// evaluated so far.
+    ast: AST,
+    generator: Generator
+}
+
+enum Input {
+    Statements(Vec<Statement>),
+    Expression(Expression)
+}
+
+impl Session {
+    pub fn new(options: CompileOptions) -> Result<Session, Vec<Diagnostic>> {
+        let ast = check_sources(&[], &options)?;
+        let generator = Generator::with_prelude(ast.statements.clone(), &options).map_err(|err| vec![err])?;
+        Ok(Session { options, sources: vec![], ast, generator })
+    }
+
+    /// The inputs given so far, to report diagnostics.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.options.namespace
+    }
+
+    /// Evaluates `input`, either statements or an expression. If it fails,
+    /// the session is left as it was before.
+    pub fn eval(&mut self, input: &str) -> Result<Output, Vec<Diagnostic>> {
+        let file = self.sources.len();
+        self.sources.push(Source { name: INPUT_NAME.into(), text: input.into() });
+        self.generator.set_sources(&self.sources);
+
+        let input = match parser::parse_file(input, file).finish() {
+            Ok((_, ast)) => Input::Statements(ast.statements),
+            Err(err) => match parser::parse_expression_file(input, file).finish() {
+                Ok((_, expr)) => Input::Expression(expr),
+                Err(_) => return Err(vec![err])
+            }
+        };
+
+        // An expression is checked as if it was interpolated in a command.
+        let statements = match &input {
+            Input::Statements(statements) => statements.clone(),
+            Input::Expression(expr) => vec![Statement::Command(Command {
+                start: vec![(String::new(), expr.clone())],
+                end: String::new(),
+                position: expr.pos().clone()
+            })]
+        };
+        let mut ast = AST { statements: self.ast.statements.clone() };
+        ast.statements.extend(statements);
+        let resolutions = generator::resolve::resolve(&ast, &self.sources)?;
+        generator::typing::check(&mut ast, &resolutions)?;
+        let checked = ast.statements.split_off(self.ast.statements.len());
+
+        match input {
+            Input::Statements(_) => {
+                let output = self.generator.preview(|ctx| ctx.generate_statements(checked.clone()))
+                    .map_err(|err| vec![err])?;
+                self.ast.statements.extend(checked);
+                Ok(Output::Commands(output))
+            },
+            Input::Expression(expr) if expr.is_static() => {
+                expr.to_string(&self.generator).map(Output::Value).map_err(|err| vec![err])
+            },
+            Input::Expression(expr) => {
+                let output = self.generator.preview(|ctx| {
+                    ctx.generate_expression(expr)?;
+                    ctx.generate_pop_expression();
+                    Ok(())
+                });
+                output.map(Output::Commands).map_err(|err| vec![err])
+            }
+        }
+    }
+}
+
+/// Reads inputs from `input` until its end, writing what they give to `out`
+/// and their diagnostics to `diagnostics`, in colour if `color` is set. An
+/// input goes on, line after line, until its blocks are closed.
+pub fn run<R, W, E>(session: &mut Session, input: R, out: &mut W, diagnostics: &mut E, color: bool) -> io::Result<()>
+    where R: BufRead, W: Write, E: Write
+{
+    let mut lines = input.lines();
+    loop {
+        let mut input = String::new();
+        loop {
+            write!(out, "{}", if input.is_empty() { "> " } else { ". " })?;
+            out.flush()?;
+            match lines.next() {
+                Some(line) => input += &(line? + "\n"),
+                None => return writeln!(out)
+            }
+            if open_blocks(&input) <= 0 {
+                break;
+            }
+        }
+
+        match session.eval(&input) {
+            Ok(Output::Value(value)) => writeln!(out, "{}", value)?,
+            Ok(Output::Commands(functions)) => for (name, lines) in functions {
+                // The top level is printed on its own.
+                if name == "0" {
+                    lines.iter().try_for_each(|line| writeln!(out, "{}", line))?;
+                } else {
+                    print_function(out, session.namespace(), &name, &lines)?;
+                }
+            },
+            Err(errors) => errors::report(diagnostics, &errors, session.sources(), color)?
+        }
+    }
+}
+
+/// How many blocks `input` leaves open. The braces of commands and
+/// strings don't open blocks: commands go on until the end of the line.
+fn open_blocks(input: &str) -> i32 {
+    let mut open = 0;
+    let mut chars = input.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '{' => open += 1,
+            '}' => open -= 1,
+            '"' => { chars.by_ref().find(|char| *char == '"'); },
+            '/' => { chars.by_ref().find(|char| *char == '\n'); },
+            _ => {}
+        }
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(CompileOptions { namespace: "repl".into(), ..Default::default() }).unwrap()
+    }
+
+    fn errors(result: Result<Output, Vec<Diagnostic>>) -> Vec<String> {
+        result.unwrap_err().into_iter().map(|err| err.error).collect()
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut session = session();
+        assert_eq!(session.eval("\"a\" + \"b\"").unwrap(), Output::Value("\"ab\"".into()));
+        assert_eq!(session.eval("x := 2;\n").unwrap(), Output::Commands(vec![]));
+        assert_eq!(session.eval("x == 2").unwrap(), Output::Value("true".into()));
+
+        let commands = match session.eval("$y := x;\n").unwrap() {
+            Output::Commands(commands) => commands,
+            output => panic!("{:?}", output)
+        };
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].0, "0");
+        assert!(commands[0].1.iter().any(|line| line.contains("stack[-1].@ set value 2")), "{:?}", commands);
+
+        let commands = match session.eval("def $f() {\n/say hi\n}\n").unwrap() {
+            Output::Commands(commands) => commands,
+            output => panic!("{:?}", output)
+        };
+        assert!(commands.contains(&("1".into(), vec!["say hi".into()])), "{:?}", commands);
+        assert!(matches!(session.eval("$y + 1").unwrap(), Output::Commands(_)));
+        assert!(matches!(session.eval("$f();\n").unwrap(), Output::Commands(_)));
+    }
+
+    #[test]
+    fn test_failed_input() {
+        let mut session = session();
+        assert_eq!(errors(session.eval("$z := $y;\n")), ["unknown variable `$y`"]);
+        assert_eq!(errors(session.eval("$z")), ["unknown variable `$z`"]);
+        assert_eq!(session.sources().len(), 2);
+
+        let overflow = "def $f(n) {\n\t$f(n);\n}\n$g := 1;\n$f(1);\n";
+        assert_eq!(errors(session.eval(overflow)), ["too many nested macro expansions"]);
+        assert_eq!(errors(session.eval("$g")), ["unknown variable `$g`"]);
+        assert!(session.eval("$g := 1;\n$g").is_err());
+        assert!(session.eval("$g := 1;\n").is_ok());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut out = vec![];
+        let mut diagnostics = vec![];
+        run(&mut session(), "x := 1;\ndef $f() {\n/say hi\n}\nx + 1\n$y\n".as_bytes(), &mut out, &mut diagnostics, false).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "> > . . # function repl:1\nsay hi\n> 2\n> > \n");
+        let diagnostics = String::from_utf8(diagnostics).unwrap();
+        assert!(diagnostics.starts_with("--> <input>:1:1\n"), "{}", diagnostics);
+        assert!(!diagnostics.contains('\x1b'), "{}", diagnostics);
+    }
+
+    #[test]
+    fn test_run_braces() {
+        // Only the braces of blocks keep an input going, not the ones of
+        // commands or strings.
+        let mut out = vec![];
+        let mut diagnostics = vec![];
+        let input = "/say {\ns := \"{\";\ndef $f() {\n\t/say }\n}\n";
+        run(&mut session(), input.as_bytes(), &mut out, &mut diagnostics, false).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "> say {\n> > . . # function repl:1\nsay }\n> \n");
+        assert!(diagnostics.is_empty(), "{}", String::from_utf8(diagnostics).unwrap());
+    }
+}
@@ -0,0 +1,635 @@
+//! Runs the functions of a [Datapack] without Minecraft.
+//!
+//! Only the commands the compiler emits are understood: `data` on storages,
+//! `scoreboard` objectives and players, `execute` with `if`/`unless data`,
+//! `if`/`unless score`, `store` and `run`, `function` (macros included),
+//! `say` and `tellraw`. Anything else is a [SimulationError].
+//!
+//! ```
+//! use tag_lang::{compile_str, CompileOptions};
+//! use tag_lang::simulator::Simulator;
+//!
+//! let options = CompileOptions { namespace: "test".into(), ..Default::default() };
+//! let datapack = compile_str("/say hello world\n", options).unwrap();
+//! let mut simulator = Simulator::new(&datapack);
+//! simulator.load().unwrap();
+//! assert_eq!(simulator.chat, vec!["hello world"]);
+//! ```
+
+pub mod nbt;
+pub mod path;
+mod text;
+
+use crate::datapack::Datapack;
+use self::nbt::{Nbt, Compound, parse_snbt};
+use self::path::{NbtPath, parse_path};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Functions calling each other deeper than this are considered to never end.
+const MAX_DEPTH: usize = 512;
+
+/// Like Minecraft's `maxCommandChainLength` game rule.
+const MAX_COMMANDS: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationError {
+    /// The function (with its namespace) and line, starting at 1, of the
+    /// command that failed, when it was run from a function.
+    pub function: Option<(String, usize)>,
+    pub command: String,
+    pub error: String
+}
+
+impl SimulationError {
+    fn new<S: ToString>(error: S) -> Self {
+        SimulationError { function: None, command: String::new(), error: error.to_string() }
+    }
+
+    /// Sets where the error happened, unless a nested command already did.
+    fn within(mut self, function: Option<(String, usize)>, command: &str) -> Self {
+        if self.command.is_empty() {
+            self.function = function;
+            self.command = command.to_string();
+        }
+        self
+    }
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((function, line)) = &self.function {
+            write!(f, "{}:{}: ", function, line)?;
+        }
+        write!(f, "{}", self.error)?;
+        if !self.command.is_empty() {
+            write!(f, " (in `{}`)", self.command)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+type CommandResult = Result<Option<i32>, SimulationError>;
+
+/// The state of a world running a datapack: its storages, scoreboards
+/// and chat.
+///
+/// Commands give `Some(result)` when they succeed and `None` when they
+/// fail the way they would in game (e.g. `data get` on a missing value),
+/// which doesn't stop the function running them.
+#[derive(Debug, Clone)]
+pub struct Simulator<'a> {
+    datapack: &'a Datapack,
+    storages: BTreeMap<String, Nbt>,
+    /// The scores of each objective, by holder.
+    objectives: BTreeMap<String, BTreeMap<String, i32>>,
+    /// Every message sent by `say` and `tellraw`, as plain text.
+    pub chat: Vec<String>,
+    /// How many commands have been run from functions.
+    pub commands_run: usize,
+    depth: usize
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(datapack: &'a Datapack) -> Self {
+        Simulator {
+            datapack,
+            storages: BTreeMap::new(),
+            objectives: BTreeMap::new(),
+            chat: vec![],
+            commands_run: 0,
+            depth: 0
+        }
+    }
+
+    /// Runs the functions of the `minecraft:load` tag, like the game does
+    /// when the datapack is loaded.
+    pub fn load(&mut self) -> Result<(), SimulationError> {
+        self.run_command("function #minecraft:load").map(|_| ())
+    }
+
+    /// Runs `function`, with or without the namespace of the datapack.
+    pub fn run_function(&mut self, function: &str) -> Result<(), SimulationError> {
+        let function = if function.contains(':') {
+            function.to_string()
+        } else {
+            format!("{}:{}", self.datapack.namespace, function)
+        };
+        self.call(&function, None).map(|_| ())
+    }
+
+    pub fn run_command(&mut self, command: &str) -> CommandResult {
+        self.command(command).map_err(|err| err.within(None, command))
+    }
+
+    /// The whole content of a storage, if anything was written to it.
+    pub fn storage(&self, storage: &str) -> Option<&Nbt> {
+        self.storages.get(storage)
+    }
+
+    /// The first value at `path` in `storage`.
+    pub fn get(&self, storage: &str, path: &str) -> Option<&Nbt> {
+        let path = NbtPath::parse(path).ok()?;
+        path.get(self.storage(storage)?).into_iter().next()
+    }
+
+    pub fn score(&self, holder: &str, objective: &str) -> Option<i32> {
+        self.objectives.get(objective)?.get(holder).copied()
+    }
+
+    pub fn objectives(&self) -> impl Iterator<Item = &String> {
+        self.objectives.keys()
+    }
+
+    fn call(&mut self, function: &str, args: Option<&Compound>) -> CommandResult {
+        if let Some(tag) = function.strip_prefix('#') {
+            let functions = self.datapack.function_tags.get(tag).cloned().unwrap_or_default();
+            for function in &functions {
+                self.call(function, args)?;
+            }
+            return Ok(Some(functions.len() as i32));
+        }
+
+        let datapack = self.datapack;
+        let lines = function.split_once(':')
+            .filter(|(namespace, _)| *namespace == datapack.namespace)
+            .and_then(|(_, name)| datapack.functions.get(name))
+            .ok_or_else(|| SimulationError::new(format!("unknown function `{}`", function)))?;
+
+        if self.depth == MAX_DEPTH {
+            return Err(SimulationError::new("functions are nested too deeply"));
+        }
+        self.depth += 1;
+
+        for (i, line) in lines.iter().enumerate() {
+            let location = Some((function.to_string(), i + 1));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.commands_run += 1;
+            if self.commands_run > MAX_COMMANDS {
+                self.depth -= 1;
+                return Err(SimulationError::new("too many commands were run").within(location, line));
+            }
+
+            let result = match line.strip_prefix('$') {
+                Some(line) => expand_macro(line, args).and_then(|command| self.command(&command)),
+                None => self.command(line)
+            };
+            if let Err(err) = result {
+                self.depth -= 1;
+                return Err(err.within(location, line));
+            }
+        }
+
+        self.depth -= 1;
+        Ok(Some(0))
+    }
+
+    fn command(&mut self, command: &str) -> CommandResult {
+        let mut reader = Reader(command);
+        match reader.word()? {
+            "data" => self.data(&mut reader),
+            "scoreboard" => self.scoreboard(&mut reader),
+            "execute" => self.execute(&mut reader),
+            "function" => {
+                let function = reader.word()?;
+                let args = if reader.is_empty() {
+                    None
+                } else {
+                    reader.expect("with")?;
+                    reader.expect("storage")?;
+                    let storage = reader.word()?;
+                    let path = reader.path()?;
+                    match self.get_values(storage, &path).first() {
+                        Some(Nbt::Compound(args)) => Some(args.clone()),
+                        _ => return Ok(None)
+                    }
+                };
+                self.call(function, args.as_ref())
+            },
+            "say" => {
+                self.chat.push(reader.rest().to_string());
+                Ok(Some(1))
+            },
+            "tellraw" => {
+                reader.word()?;
+                let component = serde_json::from_str(reader.rest())
+                    .map_err(|err| SimulationError::new(format!("invalid text component: {}", err)))?;
+                let text = text::render(&component, self);
+                self.chat.push(text);
+                Ok(Some(1))
+            },
+            command => Err(SimulationError::new(format!("unsupported command `{}`", command)))
+        }
+    }
+
+    fn data(&mut self, reader: &mut Reader) -> CommandResult {
+        let action = reader.word()?;
+        reader.expect("storage")?;
+        let storage = reader.word()?.to_string();
+
+        match action {
+            "get" => {
+                let path = reader.path()?;
+                let scale = if reader.is_empty() { 1.0 } else { reader.number()? };
+                Ok(self.get_values(&storage, &path).first().map(|value| value.get_result(scale)))
+            },
+            "remove" => {
+                let path = reader.path()?;
+                let count = path.remove(self.storage_mut(&storage));
+                Ok(Some(count as i32).filter(|count| *count > 0))
+            },
+            "merge" => {
+                let value = reader.snbt()?;
+                merge(self.storage_mut(&storage), value);
+                Ok(Some(1))
+            },
+            "modify" => {
+                let path = reader.path()?;
+                let operation = reader.word()?;
+                let values = match reader.word()? {
+                    "value" => vec![reader.snbt()?],
+                    "from" => {
+                        reader.expect("storage")?;
+                        let source = reader.word()?;
+                        let source_path = reader.path()?;
+                        self.get_values(source, &source_path).into_iter().take(1).collect()
+                    },
+                    source => return Err(SimulationError::new(format!("unsupported source `{}`", source)))
+                };
+                let value = match values.into_iter().next() {
+                    Some(value) => value,
+                    None => return Ok(None)
+                };
+
+                let root = self.storage_mut(&storage);
+                let count = match operation {
+                    "set" => path.set(root, &value),
+                    "merge" => {
+                        let mut count = 0;
+                        path.visit_mut(root, Some(Nbt::Compound(Compound::new())), |target| {
+                            merge(target, value.clone());
+                            count += 1;
+                        });
+                        count
+                    },
+                    "append" | "prepend" => {
+                        let mut count = 0;
+                        path.visit_mut(root, Some(Nbt::List(vec![])), |target| {
+                            // Lists only hold values of a single type.
+                            let same_type = |first: &Nbt| std::mem::discriminant(first) == std::mem::discriminant(&value);
+                            match target {
+                                Nbt::List(list) if list.first().is_none_or(same_type) => {
+                                    let index = if operation == "append" { list.len() } else { 0 };
+                                    list.insert(index, value.clone());
+                                    count += 1;
+                                },
+                                _ => {}
+                            }
+                        });
+                        count
+                    },
+                    operation => return Err(SimulationError::new(format!("unsupported operation `{}`", operation)))
+                };
+                Ok(Some(count as i32).filter(|count| *count > 0))
+            },
+            action => Err(SimulationError::new(format!("unsupported action `data {}`", action)))
+        }
+    }
+
+    fn scoreboard(&mut self, reader: &mut Reader) -> CommandResult {
+        match (reader.word()?, reader.word()?) {
+            ("objectives", "add") => {
+                let objective = reader.word()?;
+                if self.objectives.contains_key(objective) {
+                    return Ok(None);
+                }
+                self.objectives.insert(objective.to_string(), BTreeMap::new());
+                Ok(Some(1))
+            },
+            ("objectives", "remove") => {
+                let objective = reader.word()?;
+                self.objective_mut(objective)?;
+                self.objectives.remove(objective);
+                Ok(Some(1))
+            },
+            ("players", "get") => {
+                let (holder, objective) = (reader.word()?, reader.word()?);
+                Ok(self.objective_mut(objective)?.get(holder).copied())
+            },
+            ("players", action @ ("set" | "add" | "remove")) => {
+                let (holder, objective) = (reader.word()?, reader.word()?);
+                let value: i32 = reader.number()?;
+                let score = self.objective_mut(objective)?.entry(holder.to_string()).or_insert(0);
+                *score = match action {
+                    "set" => value,
+                    "add" => score.wrapping_add(value),
+                    _ => score.wrapping_sub(value)
+                };
+                Ok(Some(*score))
+            },
+            ("players", "reset") => {
+                let holder = reader.word()?;
+                for (_, scores) in self.objectives.iter_mut() {
+                    scores.remove(holder);
+                }
+                Ok(Some(1))
+            },
+            ("players", "operation") => {
+                let (target, target_objective) = (reader.word()?, reader.word()?);
+                let operation = reader.word()?;
+                let (source, source_objective) = (reader.word()?, reader.word()?);
+
+                let b = *self.objective_mut(source_objective)?.entry(source.to_string()).or_insert(0);
+                let a = *self.objective_mut(target_objective)?.entry(target.to_string()).or_insert(0);
+                let (a, b) = match operation {
+                    "=" => (b, b),
+                    "+=" => (a.wrapping_add(b), b),
+                    "-=" => (a.wrapping_sub(b), b),
+                    "*=" => (a.wrapping_mul(b), b),
+                    // Dividing by zero leaves the score unchanged.
+                    "/=" => (if b == 0 { a } else { (a as f64 / b as f64).floor() as i32 }, b),
+                    "%=" => (if b == 0 { a } else { a.rem_euclid(b) }, b),
+                    "<" => (a.min(b), b),
+                    ">" => (a.max(b), b),
+                    "><" => (b, a),
+                    operation => return Err(SimulationError::new(format!("unknown operation `{}`", operation)))
+                };
+                self.objective_mut(source_objective)?.insert(source.to_string(), b);
+                self.objective_mut(target_objective)?.insert(target.to_string(), a);
+                Ok(Some(a))
+            },
+            (a, b) => Err(SimulationError::new(format!("unsupported command `scoreboard {} {}`", a, b)))
+        }
+    }
+
+    fn execute(&mut self, reader: &mut Reader) -> CommandResult {
+        let mut stores = vec![];
+
+        let result = loop {
+            match reader.word()? {
+                "run" => break self.command(reader.rest())?,
+                condition @ ("if" | "unless") => {
+                    let passed = self.condition(reader)? == (condition == "if");
+                    if reader.is_empty() {
+                        break Some(passed as i32).filter(|_| passed);
+                    } else if !passed {
+                        return Ok(None);
+                    }
+                },
+                "store" => {
+                    let kind = reader.word()?;
+                    if kind != "result" && kind != "success" {
+                        return Err(SimulationError::new(format!("unsupported store `{}`", kind)));
+                    }
+                    let target = match reader.word()? {
+                        "score" => StoreTarget::Score(reader.word()?.to_string(), reader.word()?.to_string()),
+                        "storage" => {
+                            let storage = reader.word()?.to_string();
+                            let path = reader.path()?;
+                            let typing = reader.word()?.to_string();
+                            StoreTarget::Storage(storage, path, typing, reader.number()?)
+                        },
+                        target => return Err(SimulationError::new(format!("unsupported store target `{}`", target)))
+                    };
+                    stores.push((kind == "success", target));
+                },
+                subcommand => return Err(SimulationError::new(format!("unsupported subcommand `execute {}`", subcommand)))
+            }
+        };
+
+        for (success, target) in stores {
+            let value = match (success, result) {
+                (true, result) => result.is_some() as i32,
+                (false, result) => result.unwrap_or(0)
+            };
+            self.store(target, value)?;
+        }
+        Ok(result)
+    }
+
+    fn condition(&mut self, reader: &mut Reader) -> Result<bool, SimulationError> {
+        match reader.word()? {
+            "data" => {
+                reader.expect("storage")?;
+                let storage = reader.word()?;
+                let path = reader.path()?;
+                Ok(!self.get_values(storage, &path).is_empty())
+            },
+            "score" => {
+                let (holder, objective) = (reader.word()?, reader.word()?);
+                let score = self.objective_mut(objective)?.get(holder).copied();
+                let comparison = reader.word()?;
+
+                if comparison == "matches" {
+                    let range = reader.word()?;
+                    let (min, max) = match range.split_once("..") {
+                        Some((min, max)) => (min, max),
+                        None => (range, range)
+                    };
+                    let bound = |bound: &str, default| if bound.is_empty() {
+                        Ok(default)
+                    } else {
+                        bound.parse().map_err(|_| SimulationError::new(format!("invalid range `{}`", range)))
+                    };
+                    let (min, max) = (bound(min, i32::MIN)?, bound(max, i32::MAX)?);
+                    return Ok(score.is_some_and(|score| min <= score && score <= max));
+                }
+
+                let (other, other_objective) = (reader.word()?, reader.word()?);
+                let other = self.objective_mut(other_objective)?.get(other).copied();
+                Ok(match (score, other) {
+                    (Some(a), Some(b)) => match comparison {
+                        "<" => a < b,
+                        "<=" => a <= b,
+                        "=" => a == b,
+                        ">=" => a >= b,
+                        ">" => a > b,
+                        comparison => return Err(SimulationError::new(format!("unknown comparison `{}`", comparison)))
+                    },
+                    _ => false
+                })
+            },
+            condition => Err(SimulationError::new(format!("unsupported condition `{}`", condition)))
+        }
+    }
+
+    fn store(&mut self, target: StoreTarget, value: i32) -> Result<(), SimulationError> {
+        match target {
+            StoreTarget::Score(holder, objective) => {
+                self.objective_mut(&objective)?.insert(holder, value);
+            },
+            StoreTarget::Storage(storage, path, typing, scale) => {
+                let value = value as f64 * scale;
+                let value = match typing.as_str() {
+                    "byte" => Nbt::Byte(value as i8),
+                    "short" => Nbt::Short(value as i16),
+                    "int" => Nbt::Int(value as i32),
+                    "long" => Nbt::Long(value as i64),
+                    "float" => Nbt::Float(value as f32),
+                    "double" => Nbt::Double(value),
+                    typing => return Err(SimulationError::new(format!("unknown type `{}`", typing)))
+                };
+                path.set(self.storage_mut(&storage), &value);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_values(&self, storage: &str, path: &NbtPath) -> Vec<Nbt> {
+        self.storage(storage).map_or(vec![], |root| path.get(root).into_iter().cloned().collect())
+    }
+
+    fn storage_mut(&mut self, storage: &str) -> &mut Nbt {
+        self.storages.entry(storage.to_string()).or_insert_with(|| Nbt::Compound(Compound::new()))
+    }
+
+    fn objective_mut(&mut self, objective: &str) -> Result<&mut BTreeMap<String, i32>, SimulationError> {
+        self.objectives.get_mut(objective)
+            .ok_or_else(|| SimulationError::new(format!("unknown objective `{}`", objective)))
+    }
+}
+
+enum StoreTarget {
+    Score(String, String),
+    /// The storage, path, NBT type and scale.
+    Storage(String, NbtPath, String, f64)
+}
+
+/// Replaces the `$(name)` of a macro line with the arguments of the call.
+fn expand_macro(line: &str, args: Option<&Compound>) -> Result<String, SimulationError> {
+    let args = args.ok_or_else(|| SimulationError::new("macro line in a function called without arguments"))?;
+    let mut expanded = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find("$(") {
+        let end = rest[start..].find(')')
+            .ok_or_else(|| SimulationError::new("unterminated macro argument"))? + start;
+        let name = &rest[start + 2..end];
+        let value = args.get(name)
+            .ok_or_else(|| SimulationError::new(format!("missing macro argument `{}`", name)))?;
+
+        expanded += &rest[..start];
+        expanded += &value.to_text();
+        rest = &rest[end + 1..];
+    }
+
+    Ok(expanded + rest)
+}
+
+/// Merges the entries of `value` into `target`, recursively.
+fn merge(target: &mut Nbt, value: Nbt) {
+    match (target, value) {
+        (Nbt::Compound(target), Nbt::Compound(value)) => {
+            for (key, value) in value {
+                match target.get_mut(&key) {
+                    Some(entry) if matches!((&*entry, &value), (Nbt::Compound(_), Nbt::Compound(_))) => merge(entry, value),
+                    _ => { target.insert(key, value); }
+                }
+            }
+        },
+        (target, value) => *target = value
+    }
+}
+
+/// Reads the arguments of a command one by one.
+struct Reader<'a>(&'a str);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+
+    fn rest(&mut self) -> &'a str {
+        std::mem::take(&mut self.0).trim()
+    }
+
+    fn word(&mut self) -> Result<&'a str, SimulationError> {
+        let input = self.0.trim_start();
+        let end = input.find(char::is_whitespace).unwrap_or(input.len());
+        self.0 = &input[end..];
+
+        match &input[..end] {
+            "" => Err(SimulationError::new("missing argument")),
+            word => Ok(word)
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), SimulationError> {
+        match self.word()? {
+            word if word == expected => Ok(()),
+            word => Err(SimulationError::new(format!("expected `{}`, found `{}`", expected, word)))
+        }
+    }
+
+    fn number<N: std::str::FromStr>(&mut self) -> Result<N, SimulationError> {
+        let word = self.word()?;
+        word.parse().map_err(|_| SimulationError::new(format!("invalid number `{}`", word)))
+    }
+
+    fn path(&mut self) -> Result<NbtPath, SimulationError> {
+        let input = self.0.trim_start();
+        let (rest, path) = parse_path(input)
+            .map_err(|_| SimulationError::new(format!("invalid NBT path `{}`", input)))?;
+        self.0 = rest;
+        Ok(path)
+    }
+
+    fn snbt(&mut self) -> Result<Nbt, SimulationError> {
+        let input = self.0.trim_start();
+        let (rest, nbt) = parse_snbt(input)
+            .map_err(|_| SimulationError::new(format!("invalid NBT `{}`", input)))?;
+        self.0 = rest;
+        Ok(nbt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_str, CompileOptions};
+
+    fn compile(src: &str) -> Datapack {
+        let options = CompileOptions { namespace: "test".into(), target: "1.20.4".parse().unwrap(), ..Default::default() };
+        compile_str(src, options).unwrap()
+    }
+
+    #[test]
+    fn test_simulate() {
+        let datapack = compile("$x := 3;\ndef $log($v) {\n  /say #{$v}\n}\n$log($x);\nif true {\n  /tellraw @a [\"a\", {\"text\": \"b\"}]\n}\n");
+        let mut simulator = Simulator::new(&datapack);
+        simulator.load().unwrap();
+
+        assert_eq!(simulator.chat, vec!["3", "ab"]);
+        assert_eq!(simulator.get("test:runtime", "stack"), Some(&Nbt::List(vec![])));
+        assert_eq!(simulator.get("test:runtime", "vars"), Some(&Nbt::List(vec![])));
+        assert!(simulator.objectives().any(|objective| objective == "__test__"));
+
+        simulator.run_function("uninstall").unwrap();
+        assert_eq!(simulator.objectives().count(), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut datapack = compile("/say hi\n");
+        datapack.functions.insert("bad".into(), vec!["say ok".into(), "kill @e".into()]);
+        let mut simulator = Simulator::new(&datapack);
+
+        let err = simulator.run_function("bad").unwrap_err();
+        assert_eq!(err.function, Some(("test:bad".into(), 2)));
+        assert_eq!(err.to_string(), "test:bad:2: unsupported command `kill` (in `kill @e`)");
+
+        simulator.run_command("scoreboard objectives add o dummy").unwrap();
+        simulator.run_command("scoreboard players set a o 7").unwrap();
+        simulator.run_command("scoreboard players set b o 2").unwrap();
+        simulator.run_command("execute store result storage s x int 1 run scoreboard players operation a o /= b o").unwrap();
+        assert_eq!(simulator.get("s", "x"), Some(&Nbt::Int(3)));
+        assert_eq!(simulator.run_command("execute if score a o matches 4.. run say no").unwrap(), None);
+        assert_eq!(simulator.run_command("data get storage s y").unwrap(), None);
+    }
+}
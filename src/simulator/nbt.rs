@@ -0,0 +1,211 @@
+//! NBT values, and their textual representation (SNBT).
+
+use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{char, multispace0};
+use nom::combinator::map;
+use nom::error::{Error, ErrorKind};
+use nom::multi::separated_list0;
+use nom::sequence::{delimited, separated_pair, terminated};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Compound = BTreeMap<String, Nbt>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(Compound)
+}
+
+impl Nbt {
+    pub fn parse(input: &str) -> Result<Nbt, String> {
+        match terminated(parse_snbt, multispace0)(input) {
+            Ok(("", nbt)) => Ok(nbt),
+            _ => Err(format!("invalid NBT `{}`", input))
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Nbt::Byte(n) => Some(n as f64),
+            Nbt::Short(n) => Some(n as f64),
+            Nbt::Int(n) => Some(n as f64),
+            Nbt::Long(n) => Some(n as f64),
+            Nbt::Float(n) => Some(n as f64),
+            Nbt::Double(n) => Some(n),
+            _ => None
+        }
+    }
+
+    /// The result of `data get` on this value, multiplied by `scale`.
+    pub fn get_result(&self, scale: f64) -> i32 {
+        match self {
+            Nbt::String(str) => str.chars().count() as i32,
+            Nbt::List(list) => list.len() as i32,
+            Nbt::Compound(compound) => compound.len() as i32,
+            number => (number.as_f64().unwrap() * scale).floor() as i32
+        }
+    }
+
+    /// The text shown for this value in chat or in a macro: strings are
+    /// shown without quotes.
+    pub fn to_text(&self) -> String {
+        match self {
+            Nbt::String(str) => str.clone(),
+            nbt => nbt.to_string()
+        }
+    }
+
+    /// Whether every entry of `pattern` is found in `self`, which is how
+    /// paths like `{condition:1b}` filter values.
+    pub fn matches(&self, pattern: &Nbt) -> bool {
+        match (pattern, self) {
+            (Nbt::Compound(pattern), Nbt::Compound(compound)) => pattern.iter()
+                .all(|(key, pattern)| compound.get(key).is_some_and(|value| value.matches(pattern))),
+            (Nbt::List(pattern), Nbt::List(list)) if pattern.is_empty() => list.is_empty(),
+            (Nbt::List(pattern), Nbt::List(list)) => pattern.iter()
+                .all(|pattern| list.iter().any(|value| value.matches(pattern))),
+            (pattern, value) => pattern == value
+        }
+    }
+}
+
+impl fmt::Display for Nbt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Nbt::Byte(n) => write!(f, "{}b", n),
+            Nbt::Short(n) => write!(f, "{}s", n),
+            Nbt::Int(n) => write!(f, "{}", n),
+            Nbt::Long(n) => write!(f, "{}L", n),
+            Nbt::Float(n) => write!(f, "{}f", n),
+            Nbt::Double(n) => write!(f, "{}d", n),
+            Nbt::String(str) => write!(f, "{}", quote(str)),
+            Nbt::List(list) => {
+                let values: Vec<String> = list.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", values.join(","))
+            },
+            Nbt::Compound(compound) => {
+                let entries: Vec<String> = compound.iter().map(|(key, value)| {
+                    if !key.is_empty() && key.chars().all(is_unquoted_char) {
+                        format!("{}:{}", key, value)
+                    } else {
+                        format!("{}:{}", quote(key), value)
+                    }
+                }).collect();
+                write!(f, "{{{}}}", entries.join(","))
+            }
+        }
+    }
+}
+
+fn quote(str: &str) -> String {
+    format!("\"{}\"", str.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn is_unquoted_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-.+".contains(c)
+}
+
+fn ws<'a, O, F>(parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+    where F: FnMut(&'a str) -> IResult<&'a str, O>
+{
+    delimited(multispace0, parser, multispace0)
+}
+
+pub(in super) fn parse_snbt(input: &str) -> IResult<&str, Nbt> {
+    alt((
+        map(parse_compound, Nbt::Compound),
+        map(delimited(
+            char('['),
+            separated_list0(char(','), ws(parse_snbt)),
+            ws(char(']'))
+        ), Nbt::List),
+        map(parse_quoted, Nbt::String),
+        map(take_while1(is_unquoted_char), parse_unquoted)
+    ))(input)
+}
+
+pub(in super) fn parse_compound(input: &str) -> IResult<&str, Compound> {
+    map(delimited(
+        char('{'),
+        separated_list0(char(','), ws(separated_pair(parse_key, ws(char(':')), parse_snbt))),
+        ws(char('}'))
+    ), |entries| entries.into_iter().collect())(input)
+}
+
+fn parse_key(input: &str) -> IResult<&str, String> {
+    alt((
+        parse_quoted,
+        map(take_while1(is_unquoted_char), ToString::to_string)
+    ))(input)
+}
+
+/// A string between double or single quotes, with `\` escaping the next
+/// character.
+pub(in super) fn parse_quoted(input: &str) -> IResult<&str, String> {
+    let (input, quote) = alt((char('"'), char('\'')))(input)?;
+    let mut str = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) => str.push(c),
+                None => break
+            },
+            c if c == quote => return Ok((&input[i + c.len_utf8()..], str)),
+            c => str.push(c)
+        }
+    }
+
+    Err(nom::Err::Error(Error::new(input, ErrorKind::Char)))
+}
+
+/// Numbers (with their type suffix), booleans, or else plain strings.
+fn parse_unquoted(token: &str) -> Nbt {
+    match token {
+        "true" => return Nbt::Byte(1),
+        "false" => return Nbt::Byte(0),
+        _ => {}
+    }
+
+    let (number, suffix) = match token.chars().last() {
+        Some(c) if "bBsSlLfFdD".contains(c) => (&token[..token.len() - 1], Some(c.to_ascii_lowercase())),
+        _ => (token, None)
+    };
+    let number = match suffix {
+        Some('b') => number.parse().ok().map(Nbt::Byte),
+        Some('s') => number.parse().ok().map(Nbt::Short),
+        Some('l') => number.parse().ok().map(Nbt::Long),
+        Some('f') => number.parse().ok().map(Nbt::Float),
+        Some('d') => number.parse().ok().map(Nbt::Double),
+        _ => number.parse().ok().map(Nbt::Int)
+            .or_else(|| number.contains('.').then(|| number.parse().ok().map(Nbt::Double)).flatten())
+    };
+
+    number.unwrap_or_else(|| Nbt::String(token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snbt() {
+        let nbt = Nbt::parse(r#"{a: 1b, "b c": [1, 2.5, 3L], d: 'it\'s', e: {}, f: true, g: hello}"#).unwrap();
+        assert_eq!(nbt.to_string(), r#"{a:1b,"b c":[1,2.5d,3L],d:"it's",e:{},f:1b,g:"hello"}"#);
+        assert_eq!(Nbt::parse(&nbt.to_string()).unwrap(), nbt);
+        assert!(nbt.matches(&Nbt::parse("{a: 1b, e: {}}").unwrap()));
+        assert!(!nbt.matches(&Nbt::parse("{a: 0b}").unwrap()));
+        assert!(Nbt::parse("{a: 1").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! NBT paths, e.g. `vars[-1]."x"` or `{condition:1b}`.
+
+use crate::simulator::nbt::{Nbt, Compound, parse_compound, parse_quoted};
+use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{char, digit1, multispace0};
+use nom::combinator::{map, map_res, opt, recognize};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// `key` or `"key"`.
+    Key(String),
+    /// `[index]`, negative indices counting from the end.
+    Index(i32),
+    /// `[]`.
+    AllElements,
+    /// `[{...}]`: the elements matching a compound.
+    MatchElements(Compound),
+    /// `{...}`: the current compound, if it matches.
+    MatchObject(Compound)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NbtPath {
+    pub nodes: Vec<Node>,
+    source: String
+}
+
+impl fmt::Display for NbtPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+pub(in super) fn parse_path(input: &str) -> IResult<&str, NbtPath> {
+    let (rest, nodes) = parse_nodes(input)?;
+    let source = input[..input.len() - rest.len()].to_string();
+    Ok((rest, NbtPath { nodes: nodes.into_iter().flatten().collect(), source }))
+}
+
+fn parse_nodes(input: &str) -> IResult<&str, Vec<Vec<Node>>> {
+    let (input, first) = alt((
+        map(parse_compound, |compound| vec![Node::MatchObject(compound)]),
+        parse_key_node
+    ))(input)?;
+    let (input, mut nodes) = many0(alt((
+        preceded(char('.'), parse_key_node),
+        map(parse_index, |index| vec![index])
+    )))(input)?;
+
+    nodes.insert(0, first);
+    Ok((input, nodes))
+}
+
+/// A key, which can be followed by compounds to match and indices.
+fn parse_key_node(input: &str) -> IResult<&str, Vec<Node>> {
+    let (input, key) = alt((
+        parse_quoted,
+        map(take_while1(|c: char| !" .[]{}\"'".contains(c)), ToString::to_string)
+    ))(input)?;
+    let (input, filter) = opt(parse_compound)(input)?;
+
+    let mut nodes = vec![Node::Key(key)];
+    nodes.extend(filter.map(Node::MatchObject));
+    Ok((input, nodes))
+}
+
+fn parse_index(input: &str) -> IResult<&str, Node> {
+    delimited(
+        char('['),
+        delimited(multispace0, alt((
+            map(parse_compound, Node::MatchElements),
+            map(map_res(recognize(pair(opt(char('-')), digit1)), str::parse), Node::Index),
+            map(multispace0, |_| Node::AllElements)
+        )), multispace0),
+        char(']')
+    )(input)
+}
+
+fn resolve_index(index: i32, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index as i64 } else { index as i64 };
+    if index >= 0 && (index as usize) < len { Some(index as usize) } else { None }
+}
+
+/// Sets `target` to `value`, returning 1 if it changed.
+fn replace(target: &mut Nbt, value: &Nbt) -> usize {
+    if target == value {
+        return 0;
+    }
+    *target = value.clone();
+    1
+}
+
+/// The container created for a missing key, when `node` comes after it.
+fn container_for(node: &Node) -> Nbt {
+    match node {
+        Node::Index(_) | Node::AllElements | Node::MatchElements(_) => Nbt::List(vec![]),
+        Node::Key(_) | Node::MatchObject(_) => Nbt::Compound(Compound::new())
+    }
+}
+
+impl NbtPath {
+    pub fn parse(input: &str) -> Result<NbtPath, String> {
+        match parse_path(input) {
+            Ok(("", path)) => Ok(path),
+            _ => Err(format!("invalid NBT path `{}`", input))
+        }
+    }
+
+    /// Every value found at the path.
+    pub fn get<'a>(&self, root: &'a Nbt) -> Vec<&'a Nbt> {
+        let mut values = vec![root];
+        for node in &self.nodes {
+            values = values.into_iter().flat_map(|value| -> Vec<&Nbt> {
+                match (node, value) {
+                    (Node::Key(key), Nbt::Compound(compound)) => compound.get(key).into_iter().collect(),
+                    (Node::Index(index), Nbt::List(list)) => resolve_index(*index, list.len())
+                        .map(|index| &list[index]).into_iter().collect(),
+                    (Node::AllElements, Nbt::List(list)) => list.iter().collect(),
+                    (Node::MatchElements(pattern), Nbt::List(list)) => list.iter()
+                        .filter(|value| value.matches(&Nbt::Compound(pattern.clone())))
+                        .collect(),
+                    (Node::MatchObject(pattern), value) if value.matches(&Nbt::Compound(pattern.clone())) => vec![value],
+                    _ => vec![]
+                }
+            }).collect();
+        }
+        values
+    }
+
+    /// Replaces every value found at the path with `value`, creating the
+    /// missing compounds and lists along the way. Returns how many values
+    /// were changed: like in game, setting a value to itself doesn't count.
+    pub fn set(&self, root: &mut Nbt, value: &Nbt) -> usize {
+        let (last, parents) = self.nodes.split_last().expect("paths have at least one node");
+        let mut count = 0;
+
+        visit(root, parents, Some(container_for(last)), &mut |parent| {
+            match (last, parent) {
+                (Node::Key(key), Nbt::Compound(compound)) => match compound.get_mut(key) {
+                    Some(target) => count += replace(target, value),
+                    None => {
+                        compound.insert(key.clone(), value.clone());
+                        count += 1;
+                    }
+                },
+                (Node::Index(index), Nbt::List(list)) => {
+                    if let Some(index) = resolve_index(*index, list.len()) {
+                        count += replace(&mut list[index], value);
+                    }
+                },
+                (Node::AllElements, Nbt::List(list)) => {
+                    count += list.iter_mut().map(|element| replace(element, value)).sum::<usize>();
+                },
+                (Node::MatchElements(pattern), Nbt::List(list)) => {
+                    let pattern = Nbt::Compound(pattern.clone());
+                    for element in list.iter_mut().filter(|element| element.matches(&pattern)) {
+                        count += replace(element, value);
+                    }
+                },
+                _ => {}
+            }
+        });
+
+        count
+    }
+
+    /// Runs `f` on every value found at the path, creating the last one
+    /// with `create` if it is missing.
+    pub fn visit_mut<F: FnMut(&mut Nbt)>(&self, root: &mut Nbt, create: Option<Nbt>, mut f: F) {
+        visit(root, &self.nodes, create, &mut f);
+    }
+
+    /// Removes every value found at the path. Returns how many were removed.
+    pub fn remove(&self, root: &mut Nbt) -> usize {
+        let (last, parents) = self.nodes.split_last().expect("paths have at least one node");
+        let mut count = 0;
+
+        visit(root, parents, None, &mut |parent| {
+            match (last, parent) {
+                (Node::Key(key), Nbt::Compound(compound)) => {
+                    count += compound.remove(key).map_or(0, |_| 1);
+                },
+                (Node::Index(index), Nbt::List(list)) => {
+                    if let Some(index) = resolve_index(*index, list.len()) {
+                        list.remove(index);
+                        count += 1;
+                    }
+                },
+                (Node::AllElements, Nbt::List(list)) => {
+                    count += list.len();
+                    list.clear();
+                },
+                (Node::MatchElements(pattern), Nbt::List(list)) => {
+                    let pattern = Nbt::Compound(pattern.clone());
+                    let len = list.len();
+                    list.retain(|element| !element.matches(&pattern));
+                    count += len - list.len();
+                },
+                _ => {}
+            }
+        });
+
+        count
+    }
+}
+
+/// Runs `f` on the values at `nodes` from `nbt`. Missing keys get created
+/// when `create` is given, the last one with `create` itself.
+fn visit(nbt: &mut Nbt, nodes: &[Node], create: Option<Nbt>, f: &mut dyn FnMut(&mut Nbt)) {
+    let (node, rest) = match nodes.split_first() {
+        Some(split) => split,
+        None => return f(nbt)
+    };
+
+    match (node, nbt) {
+        (Node::Key(key), Nbt::Compound(compound)) => {
+            if let Some(create) = &create {
+                let container = rest.first().map_or_else(|| create.clone(), container_for);
+                compound.entry(key.clone()).or_insert(container);
+            }
+            if let Some(value) = compound.get_mut(key) {
+                visit(value, rest, create, f);
+            }
+        },
+        (Node::Index(index), Nbt::List(list)) => {
+            if let Some(index) = resolve_index(*index, list.len()) {
+                visit(&mut list[index], rest, create, f);
+            }
+        },
+        (Node::AllElements, Nbt::List(list)) => {
+            for element in list {
+                visit(element, rest, create.clone(), f);
+            }
+        },
+        (Node::MatchElements(pattern), Nbt::List(list)) => {
+            let pattern = Nbt::Compound(pattern.clone());
+            for element in list.iter_mut().filter(|element| element.matches(&pattern)) {
+                visit(element, rest, create.clone(), f);
+            }
+        },
+        (Node::MatchObject(pattern), nbt) if nbt.matches(&Nbt::Compound(pattern.clone())) => {
+            visit(nbt, rest, create, f);
+        },
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let path = |path: &str| NbtPath::parse(path).unwrap();
+        let mut root = Nbt::Compound(Compound::new());
+
+        assert_eq!(path("stack[-1].@").set(&mut root, &Nbt::Int(1)), 0);
+        path("stack").visit_mut(&mut root, Some(Nbt::List(vec![])), |list| {
+            if let Nbt::List(list) = list { list.push(Nbt::Compound(Compound::new())) }
+        });
+        assert_eq!(path("stack[-1].@").set(&mut root, &Nbt::Int(1)), 1);
+        assert_eq!(path("vars.\"x y\"").set(&mut root, &Nbt::Byte(1)), 1);
+        assert_eq!(path("vars.\"x y\"").set(&mut root, &Nbt::Byte(1)), 0);
+        assert_eq!(root.to_string(), r#"{stack:[{"@":1}],vars:{"x y":1b}}"#);
+
+        assert_eq!(path("{vars:{\"x y\":1b}}").get(&root).len(), 1);
+        assert_eq!(path("stack[{\"@\":2}]").get(&root).len(), 0);
+        assert_eq!(path("stack[]").remove(&mut root), 1);
+        assert_eq!(path("stack").get(&root), vec![&Nbt::List(vec![])]);
+    }
+}
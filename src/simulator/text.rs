@@ -0,0 +1,60 @@
+//! Plain text rendering of the JSON text components given to `tellraw`.
+
+use crate::simulator::Simulator;
+use crate::simulator::path::NbtPath;
+use serde_json::Value;
+
+/// The text of `component`, without its formatting. Selectors can't be
+/// resolved without players, so they render as themselves.
+pub(in super) fn render(component: &Value, simulator: &Simulator) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(components) => components.iter().map(|component| render(component, simulator)).collect(),
+        Value::Object(object) => {
+            let get = |key: &str| object.get(key).and_then(Value::as_str);
+
+            let mut text = if let Some(text) = get("text") {
+                text.to_string()
+            } else if let Some(key) = get("translate") {
+                key.to_string()
+            } else if let Some(score) = object.get("score") {
+                let name = score.get("name").and_then(Value::as_str).unwrap_or_default();
+                let objective = score.get("objective").and_then(Value::as_str).unwrap_or_default();
+                simulator.score(name, objective).map_or_else(String::new, |score| score.to_string())
+            } else if let (Some(path), Some(storage)) = (get("nbt"), get("storage")) {
+                render_nbt(path, storage, object.get("interpret") == Some(&Value::Bool(true)), simulator)
+            } else if let Some(selector) = get("selector") {
+                selector.to_string()
+            } else {
+                String::new()
+            };
+
+            if let Some(Value::Array(extra)) = object.get("extra") {
+                text.extend(extra.iter().map(|component| render(component, simulator)));
+            }
+            text
+        },
+        value => value.to_string()
+    }
+}
+
+fn render_nbt(path: &str, storage: &str, interpret: bool, simulator: &Simulator) -> String {
+    let path = match NbtPath::parse(path) {
+        Ok(path) => path,
+        Err(_) => return String::new()
+    };
+
+    let root = match simulator.storage(storage) {
+        Some(root) => root,
+        None => return String::new()
+    };
+
+    let values: Vec<String> = path.get(root).into_iter().map(|value| {
+        let text = value.to_text();
+        match serde_json::from_str(&text) {
+            Ok(component) if interpret => render(&component, simulator),
+            _ => text
+        }
+    }).collect();
+    values.join(", ")
+}
@@ -0,0 +1,144 @@
+//! The Minecraft version a datapack is compiled for.
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version(major, minor, 0) => write!(f, "{}.{}", major, minor),
+            Version(major, minor, patch) => write!(f, "{}.{}.{}", major, minor, patch)
+        }
+    }
+}
+
+/// The first version of each `pack_format`, sorted.
+const PACK_FORMATS: &[(Version, i32)] = &[
+    (Version(1, 13, 0), 4),
+    (Version(1, 15, 0), 5),
+    (Version(1, 16, 2), 6),
+    (Version(1, 17, 0), 7),
+    (Version(1, 18, 0), 8),
+    (Version(1, 18, 2), 9),
+    (Version(1, 19, 0), 10),
+    (Version(1, 19, 4), 12),
+    (Version(1, 20, 0), 15),
+    (Version(1, 20, 2), 18),
+    (Version(1, 20, 3), 26),
+    (Version(1, 20, 5), 41),
+    (Version(1, 21, 0), 48),
+    (Version(1, 21, 2), 57),
+    (Version(1, 21, 4), 61)
+];
+
+/// The last version known by the compiler.
+const LATEST: Version = Version(1, 21, 4);
+
+/// Features of Minecraft that some lowerings depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Function macros (`$say $(arg)` and `function ... with ...`), used to
+    /// interpolate dynamic values in commands.
+    Macros,
+    /// Scoreboard objective names longer than 16 characters.
+    LongObjectiveNames
+}
+
+impl Feature {
+    pub fn since(&self) -> Version {
+        match self {
+            Feature::Macros => Version(1, 20, 2),
+            Feature::LongObjectiveNames => Version(1, 18, 0)
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Feature::Macros => "interpolating a dynamic value in a command",
+            Feature::LongObjectiveNames => "an objective name longer than 16 characters"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Target {
+    pub version: Version
+}
+
+impl Target {
+    pub fn pack_format(&self) -> i32 {
+        PACK_FORMATS.iter()
+            .rev()
+            .find(|(version, _)| *version <= self.version)
+            .map(|(_, pack_format)| *pack_format)
+            .expect("targets are never older than the first pack format")
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.version >= feature.since()
+    }
+
+    /// The name of the folders containing functions, and function tags.
+    /// 1.21 made it singular.
+    pub fn functions_dir(&self) -> &'static str {
+        if self.version >= Version(1, 21, 0) { "function" } else { "functions" }
+    }
+}
+
+/// 1.16.5 is the version the compiler originally targeted.
+impl Default for Target {
+    fn default() -> Self {
+        Target { version: Version(1, 16, 5) }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.version.fmt(f)
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<u32> = s.split('.')
+            .map(|part| part.parse().map_err(|_| format!("invalid Minecraft version `{}`", s)))
+            .collect::<Result<_, _>>()?;
+
+        let version = match parts.as_slice() {
+            [major, minor] => Version(*major, *minor, 0),
+            [major, minor, patch] => Version(*major, *minor, *patch),
+            _ => return Err(format!("invalid Minecraft version `{}`", s))
+        };
+
+        if version < PACK_FORMATS[0].0 || version > LATEST {
+            return Err(format!("unsupported Minecraft version `{}` (supported: {} to {})",
+                               s, PACK_FORMATS[0].0, LATEST));
+        }
+
+        Ok(Target { version })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_format() {
+        let target = |s: &str| s.parse::<Target>().unwrap();
+        assert_eq!(target("1.16.5").pack_format(), 6);
+        assert_eq!(target("1.20.4").pack_format(), 26);
+        assert_eq!(target("1.21").pack_format(), 48);
+        assert_eq!(target("1.21").functions_dir(), "function");
+        assert!(!target("1.20.1").supports(Feature::Macros));
+        assert!(target("1.20.2").supports(Feature::Macros));
+        assert!("1.12.2".parse::<Target>().is_err());
+        assert!("1.x".parse::<Target>().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Runs the `test` blocks of the sources in the [Simulator].
+//!
+//! Each test is compiled to its own function, which nothing calls. The
+//! runner loads the datapack, then runs that function: every `assert`
+//! whose expression is false appends its id to the `failures` list of the
+//! runtime storage.
+//!
+//! ```
+//! use tag_lang::{compile_str, CompileOptions};
+//! use tag_lang::testing::run_tests;
+//!
+//! let options = CompileOptions { namespace: "test".into(), tests: true, ..Default::default() };
+//! let datapack = compile_str("test \"sum\" {\n  $x := 1 + 1;\n  assert $x == 2;\n}\n", options).unwrap();
+//! assert!(run_tests(&datapack).iter().all(|outcome| outcome.passed()));
+//! ```
+
+use crate::{source_location, Source};
+use crate::datapack::Datapack;
+use crate::generator::FAILURES;
+use crate::parser::Position;
+use crate::simulator::{Simulator, SimulationError};
+use crate::simulator::nbt::Nbt;
+use serde::{Serialize, Deserialize};
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    /// The function running the test, without its namespace.
+    pub function: String,
+    pub position: Position
+}
+
+/// The tests of a datapack compiled with [crate::CompileOptions::tests].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestSuite {
+    /// The storage the failures are written to.
+    pub storage: String,
+    pub tests: Vec<TestCase>,
+    /// The position of each assertion, by id.
+    pub assertions: Vec<Position>
+}
+
+impl TestSuite {
+    pub fn new(storage: String) -> Self {
+        TestSuite { storage, tests: vec![], assertions: vec![] }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestOutcome<'a> {
+    pub test: &'a TestCase,
+    /// The assertions that failed, in the order they were run.
+    pub failures: Vec<&'a Position>,
+    /// The error that stopped the test, if any.
+    pub error: Option<SimulationError>,
+    /// Everything the test sent to the chat.
+    pub chat: Vec<String>
+}
+
+impl TestOutcome<'_> {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty() && self.error.is_none()
+    }
+}
+
+/// Runs every test of `datapack`, each in its own [Simulator].
+pub fn run_tests(datapack: &Datapack) -> Vec<TestOutcome<'_>> {
+    let suite = match &datapack.tests {
+        Some(suite) => suite,
+        None => return vec![]
+    };
+
+    suite.tests.iter().map(|test| {
+        let mut simulator = Simulator::new(datapack);
+        let error = simulator.load()
+            .and_then(|_| {
+                let chat = simulator.chat.len();
+                let result = simulator.run_function(&test.function);
+                // Only keep the messages of the test itself.
+                simulator.chat.drain(..chat);
+                result
+            })
+            .err();
+
+        let failures = match simulator.get(&suite.storage, FAILURES) {
+            Some(Nbt::List(ids)) => ids.iter()
+                .filter_map(|id| suite.assertions.get(id.as_f64()? as usize))
+                .collect(),
+            _ => vec![]
+        };
+
+        TestOutcome { test, failures, error, chat: simulator.chat }
+    }).collect()
+}
+
+/// Writes the outcome of each test to `out`, like `tag test`, pointing
+/// the failures back to `sources`, then how many tests passed.
+pub fn report<W: io::Write>(out: &mut W, outcomes: &[TestOutcome], datapack: &Datapack, sources: &[Source])
+    -> io::Result<()>
+{
+    let location = |position: &Position| source_location(sources, position);
+
+    for outcome in outcomes {
+        if outcome.passed() {
+            writeln!(out, "test {} ... ok", outcome.test.name)?;
+            continue;
+        }
+
+        writeln!(out, "test {} ... FAILED", outcome.test.name)?;
+        for failure in &outcome.failures {
+            writeln!(out, "    --> {}: assertion failed", location(failure))?;
+        }
+        if let Some(err) = &outcome.error {
+            // Commands run by the test point back to the statement they
+            // were generated for.
+            let position = err.function.as_ref().and_then(|(function, line)| {
+                let name = function.split_once(':').map_or(function.as_str(), |(_, name)| name);
+                datapack.source_map.get(name, *line)
+            });
+            match position {
+                Some(position) => writeln!(out, "    --> {}: {} (in `{}`)", location(position), err.error, err.command)?,
+                None => writeln!(out, "    --> {}: {}", location(&outcome.test.position), err)?
+            }
+        }
+        for message in &outcome.chat {
+            writeln!(out, "    [chat] {}", message)?;
+        }
+    }
+
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    writeln!(out)?;
+    writeln!(out, "test result: {}. {} passed; {} failed",
+             if failed == 0 { "ok" } else { "FAILED" }, outcomes.len() - failed, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_str, CompileOptions};
+
+    #[test]
+    fn test_run_tests() {
+        let src = "def $log($n) {\n  /say #{$n}\n}\n\
+                   test \"passes\" {\n  $x := 2;\n  assert $x == 2;\n  assert \"a\" == \"a\";\n}\n\
+                   test \"fails\" {\n  $y := true;\n  $log(4);\n  assert $y == false;\n  assert true;\n  assert 1 == 2;\n}\n";
+        let options = |tests| CompileOptions { namespace: "test".into(), target: "1.20.4".parse().unwrap(), tests, ..Default::default() };
+        let datapack = compile_str(src, options(true)).unwrap();
+        let outcomes = run_tests(&datapack);
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes[0].passed(), "{:?}", outcomes[0]);
+        assert_eq!(outcomes[1].test.name, "fails");
+        assert_eq!(outcomes[1].chat, vec!["4"]);
+        let lines: Vec<_> = outcomes[1].failures.iter().map(|pos| pos.line).collect();
+        assert_eq!(lines, vec![12, 14]);
+
+        let mut out = vec![];
+        let sources = [Source { name: "t.tag".into(), text: src.into() }];
+        report(&mut out, &outcomes, &datapack, &sources).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("test passes ... ok\ntest fails ... FAILED\n    --> t.tag:12:"), "{}", out);
+        assert!(out.contains("    [chat] 4\n"), "{}", out);
+        assert!(out.ends_with("\ntest result: FAILED. 1 passed; 1 failed\n"), "{}", out);
+
+        // Without the tests, only the function of `$log` and its macro remain.
+        let datapack = compile_str(src, options(false)).unwrap();
+        assert!(datapack.tests.is_none());
+        assert_eq!(datapack.functions.keys().collect::<Vec<_>>(), vec!["0", "1", "2", "uninstall"]);
+    }
+    #[test]
+    fn test_returned_values() {
+        // Tests can check the values functions return.
+        let src = "def $double($n) {\n  return $n * 2;\n}\n\
+                   test \"doubling works\" {\n  $x := $double(2);\n  assert $x == 4;\n  assert $double($x) == 6;\n}\n";
+        let options = CompileOptions { namespace: "test".into(), tests: true, ..Default::default() };
+        let datapack = compile_str(src, options).unwrap();
+        let outcomes = run_tests(&datapack);
+
+        assert_eq!(outcomes.len(), 1);
+        let lines: Vec<_> = outcomes[0].failures.iter().map(|pos| pos.line).collect();
+        assert_eq!(lines, vec![7]);
+        assert!(outcomes[0].error.is_none(), "{:?}", outcomes[0]);
+    }
+}
@@ -0,0 +1,109 @@
+//! Runs `tag` on sources piped to its standard input, or watched.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tag-lang"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let output = run(&["compile", "-", "--name", "pipe", "--stdout"], "def $f() {\n/say hi\n}\n$f();\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("# function pipe:0\n"), "{}", stdout);
+    assert!(stdout.contains("\nfunction pipe:1\n"), "{}", stdout);
+    assert!(stdout.contains("# function pipe:1\nsay hi\n"), "{}", stdout);
+
+    let output = run(&["compile", "-", "--stdout"], "/say hi\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--name"));
+
+    // Diagnostics go to the standard error, without colours when it isn't a terminal.
+    let output = run(&["compile", "-", "--name", "pipe", "--stdout"], "$x :=\n");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("--> <stdin>:1:1\n"), "{}", stderr);
+    assert!(!stderr.contains('\x1b'), "{}", stderr);
+
+    let output = run(&["fmt", "-"], "$x:=1;\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "$x := 1;\n");
+}
+
+#[test]
+fn test_closed_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tag-lang"))
+        .args(["compile", "-", "--name", "pipe", "--stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Enough output to fill the pipe, which is closed without being read.
+    let source = "/say hi\n".repeat(10_000);
+    drop(child.stdout.take());
+    child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// Waits for the watch to print `line`, skipping the lines before it.
+fn wait_for(lines: &mpsc::Receiver<String>, line: &str) {
+    loop {
+        match lines.recv_timeout(Duration::from_secs(10)) {
+            Ok(received) if received == line => return,
+            Ok(_) => continue,
+            Err(err) => panic!("never printed `{}`: {}", line, err)
+        }
+    }
+}
+
+#[test]
+fn test_watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("watched.tag");
+    let output = dir.path().join("out");
+    std::fs::write(&source, "/say one\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tag-lang"))
+        .args(["compile", source.to_str().unwrap(), "--watch", "-o", output.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let (sender, lines) = mpsc::channel();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    std::thread::spawn(move || stdout.lines().map_while(Result::ok).try_for_each(|line| sender.send(line)));
+
+    let compiled = format!("compiled `{}`", output.display());
+    let function = output.join("data/watched/functions/0.mcfunction");
+    wait_for(&lines, &compiled);
+    wait_for(&lines, "watching for changes...");
+    assert!(std::fs::read_to_string(&function).unwrap().contains("say one"));
+
+    // A broken or missing source is reported, and doesn't stop the watch.
+    std::fs::write(&source, "/say #{\n").unwrap();
+    wait_for(&lines, "watching for changes...");
+    std::fs::remove_file(&source).unwrap();
+    wait_for(&lines, "watching for changes...");
+    std::fs::write(&source, "/say two\n").unwrap();
+    wait_for(&lines, &compiled);
+    assert!(std::fs::read_to_string(&function).unwrap().contains("say two"));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
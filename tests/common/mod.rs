@@ -0,0 +1,34 @@
+//! Helpers shared by the integration tests.
+
+use tag_lang::parser::Position;
+
+/// Rewrites each [Position] of a debug output, compact or pretty, with
+/// `render`: every node of the AST has one, making its debug output long.
+pub fn replace_positions<F: Fn(&Position) -> String>(debug: &str, render: F) -> String {
+    const START: &str = "Position {";
+    let mut replaced = String::new();
+    let mut rest = debug;
+
+    while let Some(start) = rest.find(START) {
+        replaced += &rest[..start];
+        rest = &rest[start + START.len()..];
+        let end = rest.find('}').expect("a position is closed");
+
+        let mut position = Position::default();
+        for field in rest[..end].split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let (name, value) = field.split_once(": ").expect("a field has a value");
+            let value = value.parse().expect("the fields of a position are numbers");
+            match name {
+                "file" => position.file = value,
+                "offset" => position.offset = value,
+                "length" => position.length = value,
+                "line" => position.line = value,
+                "column" => position.column = value,
+                name => panic!("unknown field `{}` of a position", name)
+            }
+        }
+        replaced += &render(&position);
+        rest = &rest[end + 1..];
+    }
+    replaced + rest
+}
@@ -0,0 +1,377 @@
+//! Differential tests of the generator: the chat of a program run by the
+//! reference interpreter must be the chat of its datapack run by the
+//! simulator, for the examples and for randomly generated programs.
+
+use proptest::prelude::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::sample::Index;
+use std::path::PathBuf;
+use tag_lang::{check_sources, compile_sources, CompileOptions, Source, Target, FILE_EXTENSION};
+use tag_lang::interpreter::interpret;
+use tag_lang::simulator::Simulator;
+
+/// Dynamic interpolation relies on function macros.
+const TARGET: &str = "1.20.4";
+
+const RANDOM_PROGRAMS: u32 = 300;
+
+fn options(namespace: &str) -> CompileOptions {
+    let target: Target = TARGET.parse().unwrap();
+    CompileOptions { namespace: namespace.into(), target, ..Default::default() }
+}
+
+/// The chat of the interpreter, and the one of the simulator.
+fn run(namespace: &str, text: &str) -> (Vec<String>, Vec<String>) {
+    let sources = [Source { name: format!("{}{}", namespace, FILE_EXTENSION), text: text.into() }];
+    let options = options(namespace);
+
+    let ast = check_sources(&sources, &options).unwrap_or_else(|errors| panic!("{:?}\n{}", errors, text));
+    let expected = interpret(&ast, &options).unwrap_or_else(|err| panic!("{}\n{}", err, text));
+
+    let datapack = compile_sources(&sources, options).unwrap_or_else(|errors| panic!("{:?}\n{}", errors, text));
+    let mut simulator = Simulator::new(&datapack);
+    simulator.load().unwrap_or_else(|err| panic!("{}\n{}", err, text));
+
+    (expected, simulator.chat)
+}
+
+#[test]
+fn test_examples() {
+    let examples = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut paths: Vec<_> = std::fs::read_dir(examples).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(FILE_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let namespace = path.file_stem().unwrap().to_string_lossy().to_string();
+        let (expected, actual) = run(&namespace, &std::fs::read_to_string(&path).unwrap());
+        assert!(!expected.is_empty(), "{} prints nothing", path.display());
+        assert_eq!(expected, actual, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_shadowed_names() {
+    // Functions and macros see the variables around their declaration, not
+    // the ones of their caller shadowing them, and a variable shadowing
+    // another is assigned a value computed from the other.
+    let text = "x := 1;\n$a := 1;\nif true {\n\
+                \tdef m() {\n\t\t/say #{x}\n\t}\n\
+                \tdef $n(k) {\n\t\t/say #{$a + k}\n\t}\n\
+                \tdef $f() {\n\t\t/say #{$a}\n\t}\n\
+                \tif true {\n\t\tx := 2;\n\t\t$a := 2;\n\t\tm();\n\t\t$n(10);\n\t\t$f();\n\
+                \t\tif true {\n\t\t\t$a := $a * 10;\n\t\t\t/say #{$a}\n\t\t}\n\t}\n}\n";
+    let (expected, actual) = run("shadowed", text);
+    assert_eq!(expected, ["1", "11", "1", "20"]);
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_returned_values() {
+    // Functions return values to the expressions calling them, which
+    // evaluate their arguments from left to right.
+    let text = "def $show($n) {\n\t/say #{$n}\n\treturn $n;\n}\n\
+                def $add($a, $b) {\n\treturn $a + $b;\n}\n\
+                /say #{$add($show(1), $show(2))}\n\
+                $x := $add($add(1, 2), 3) * 2;\n/say #{$x}\n";
+    let (expected, actual) = run("returned", text);
+    assert_eq!(expected, ["1", "2", "3", "12"]);
+    assert_eq!(expected, actual);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(RANDOM_PROGRAMS))]
+
+    #[test]
+    fn test_random_programs(program in program()) {
+        let text = program.render();
+        let (expected, actual) = run("random", &text);
+        prop_assert_eq!(expected, actual, "{}", text);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Integer,
+    String,
+    Boolean
+}
+
+/// An expression, whose type is only chosen when it is rendered: its
+/// variables are picked among the visible ones of that type, so that
+/// shrinking keeps the programs valid.
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(u8),
+    Variable(Index),
+    /// `+` for integers and strings, `==` of integers for booleans.
+    Sum(Box<Expr>, Box<Expr>),
+    /// `*` for integers, like [Expr::Sum] otherwise.
+    Product(Box<Expr>, Box<Expr>),
+    /// `==` of values of the given type for booleans, like [Expr::Sum]
+    /// otherwise.
+    Equality(Type, Box<Expr>, Box<Expr>),
+    Parentheses(Box<Expr>),
+    /// Calls one of the functions declared before returning a value of
+    /// the type, with the first of the arguments, like [Stmt::Call].
+    Call(Index, Vec<Expr>)
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Assignment { dynamic: bool, typing: Type, value: Expr },
+    /// Assigns one of the visible variables again, shadowing it if it is
+    /// declared in an outer block.
+    Shadow(Index, Expr),
+    Say(Type, Expr),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    /// Calls one of the functions declared before, with the first of the
+    /// arguments.
+    Call(Index, Vec<Expr>)
+}
+
+/// A function seeing its arguments and the variables declared before the
+/// block declaring it, which the blocks calling it may shadow.
+#[derive(Debug, Clone)]
+struct Func {
+    dynamic: bool,
+    /// Whether each argument is dynamic, its type, and whether the type is
+    /// written. Static functions (macros) only take static arguments.
+    args: Vec<(bool, Type, bool)>,
+    block: Vec<Stmt>,
+    /// The type and the value the function returns, if it is dynamic.
+    result: Option<(Type, Expr)>
+}
+
+/// A random program: variables, arithmetic, comparisons and conditions,
+/// over integers, strings and booleans, and functions with dynamic and
+/// static arguments, which may return a value.
+///
+/// The functions are declared in a block of their own, after the `outer`
+/// statements, and called from the `statements` of a block in it: dynamic
+/// functions are generated before the other statements of their block, so
+/// they can't read the variables it assigns.
+#[derive(Debug, Clone)]
+struct Program {
+    outer: Vec<Stmt>,
+    functions: Vec<Func>,
+    statements: Vec<Stmt>
+}
+
+fn typing() -> impl Strategy<Value = Type> {
+    prop_oneof![2 => Just(Type::Integer), 1 => Just(Type::String), 1 => Just(Type::Boolean)]
+}
+
+fn expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![any::<u8>().prop_map(Expr::Literal), any::<Index>().prop_map(Expr::Variable)];
+    leaf.prop_recursive(3, 12, 2, |inner| prop_oneof![
+        (inner.clone(), inner.clone()).prop_map(|(a, b)| Expr::Sum(Box::new(a), Box::new(b))),
+        (inner.clone(), inner.clone()).prop_map(|(a, b)| Expr::Product(Box::new(a), Box::new(b))),
+        (typing(), inner.clone(), inner.clone()).prop_map(|(typing, a, b)| Expr::Equality(typing, Box::new(a), Box::new(b))),
+        inner.clone().prop_map(|a| Expr::Parentheses(Box::new(a))),
+        (any::<Index>(), vec(inner, 2)).prop_map(|(function, args)| Expr::Call(function, args))
+    ])
+}
+
+fn statement() -> impl Strategy<Value = Stmt> {
+    let leaf = prop_oneof![
+        3 => (any::<bool>(), typing(), expr()).prop_map(|(dynamic, typing, value)| Stmt::Assignment { dynamic, typing, value }),
+        1 => (any::<Index>(), expr()).prop_map(|(variable, value)| Stmt::Shadow(variable, value)),
+        3 => (typing(), expr()).prop_map(|(typing, value)| Stmt::Say(typing, value)),
+        1 => (any::<Index>(), vec(expr(), 2)).prop_map(|(function, args)| Stmt::Call(function, args))
+    ];
+    leaf.prop_recursive(2, 16, 3, |inner| {
+        (expr(), vec(inner.clone(), 1..3), option::of(vec(inner, 1..3)))
+            .prop_map(|(condition, block, else_block)| Stmt::If(condition, block, else_block))
+    })
+}
+
+fn function() -> impl Strategy<Value = Func> {
+    let args = vec((any::<bool>(), typing(), any::<bool>()), 0..3);
+    (any::<bool>(), args, vec(statement(), 1..3), option::of((typing(), expr())))
+        .prop_map(|(dynamic, args, block, result)| Func { dynamic, args, block, result: result.filter(|_| dynamic) })
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    (vec(statement(), 0..4), vec(function(), 0..3), vec(statement(), 2..8))
+        .prop_map(|(outer, functions, statements)| Program { outer, functions, statements })
+}
+
+impl Program {
+    fn render(&self) -> String {
+        let mut renderer = Renderer { text: String::new(), variables: vec![], functions: vec![], dynamic: true, counter: 0 };
+        for statement in &self.outer {
+            renderer.statement(0, statement);
+        }
+        renderer.line(0, "if true {");
+        for function in &self.functions {
+            renderer.function(function);
+        }
+        renderer.line(1, "if true {");
+        renderer.statements(2, &self.statements);
+        renderer.line(1, "}");
+        renderer.line(0, "}");
+        renderer.text
+    }
+}
+
+/// A function declared by a rendered program: its name, its arguments
+/// and the type it returns.
+type Declared = (String, Vec<(String, Type)>, Option<Type>);
+
+struct Renderer {
+    text: String,
+    /// The variables visible from the statement being rendered, with their type.
+    variables: Vec<(String, Type)>,
+    /// The functions declared so far.
+    functions: Vec<Declared>,
+    /// Whether dynamic statements can be rendered: the body of a static
+    /// function (a macro) must be static.
+    dynamic: bool,
+    counter: usize
+}
+
+impl Renderer {
+    fn name(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{}{}", prefix, self.counter)
+    }
+
+    fn line(&mut self, depth: usize, line: &str) {
+        self.text += &"\t".repeat(depth);
+        self.text += line;
+        self.text += "\n";
+    }
+
+    fn function(&mut self, function: &Func) {
+        let name = if function.dynamic { self.name("$f") } else { self.name("g") };
+        let mut args = vec![];
+        let mut signature = vec![];
+        for &(dynamic, typing, annotated) in &function.args {
+            let dynamic = dynamic && function.dynamic;
+            let arg = if dynamic { self.name("$a") } else { self.name("s") };
+            signature.push(match typing {
+                _ if !annotated => arg.clone(),
+                Type::Integer => format!("{}: int", arg),
+                Type::String => format!("{}: string", arg),
+                Type::Boolean => format!("{}: bool", arg)
+            });
+            args.push((arg, typing));
+        }
+
+        let visible = self.variables.len();
+        self.variables.extend(args.iter().cloned());
+        self.dynamic = function.dynamic;
+        self.line(1, &format!("def {}({}) {{", name, signature.join(", ")));
+        self.statements(2, &function.block);
+        if let Some((typing, value)) = &function.result {
+            let value = self.expression(value, *typing, true);
+            self.line(2, &format!("return {};", value));
+        }
+        self.line(1, "}");
+        self.variables.truncate(visible);
+        self.dynamic = true;
+
+        let result = function.result.as_ref().map(|(typing, _)| *typing);
+        self.functions.push((name, args, result));
+    }
+
+    fn statements(&mut self, depth: usize, statements: &[Stmt]) {
+        let visible = self.variables.len();
+        for statement in statements {
+            self.statement(depth, statement);
+        }
+        self.variables.truncate(visible);
+    }
+
+    fn statement(&mut self, depth: usize, statement: &Stmt) {
+        match statement {
+            Stmt::Assignment { dynamic, typing, value } => {
+                // Static variables can't hold dynamic values.
+                let dynamic = *dynamic && self.dynamic;
+                let name = if dynamic { self.name("$v") } else { self.name("c") };
+                let value = self.expression(value, *typing, dynamic);
+                self.line(depth, &format!("{} := {};", name, value));
+                self.variables.push((name, *typing));
+            },
+            Stmt::Shadow(variable, value) => {
+                let variables: Vec<_> = self.variables.iter()
+                    .filter(|(name, _)| self.dynamic || !name.starts_with('$'))
+                    .cloned()
+                    .collect();
+                if variables.is_empty() {
+                    return;
+                }
+                let (name, typing) = variable.get(&variables).clone();
+                let value = self.expression(value, typing, name.starts_with('$'));
+                self.line(depth, &format!("{} := {};", name, value));
+                self.variables.push((name, typing));
+            },
+            Stmt::Say(typing, value) => {
+                let value = self.expression(value, *typing, self.dynamic);
+                self.line(depth, &format!("/say #{{{}}}", value));
+            },
+            Stmt::If(condition, block, else_block) => {
+                let condition = self.expression(condition, Type::Boolean, self.dynamic);
+                self.line(depth, &format!("if {} {{", condition));
+                self.statements(depth + 1, block);
+                if let Some(block) = else_block {
+                    self.line(depth, "} else {");
+                    self.statements(depth + 1, block);
+                }
+                self.line(depth, "}");
+            },
+            Stmt::Call(function, args) if self.dynamic && !self.functions.is_empty() => {
+                let call = self.call(function.get(&self.functions), args);
+                self.line(depth, &format!("{};", call));
+            },
+            Stmt::Call(..) => {}
+        }
+    }
+
+    fn call(&self, (name, signature, _): &Declared, args: &[Expr]) -> String {
+        let args: Vec<_> = signature.iter().zip(args)
+            .map(|((arg, typing), value)| self.expression(value, *typing, arg.starts_with('$')))
+            .collect();
+        format!("{}({})", name, args.join(", "))
+    }
+
+    fn expression(&self, expr: &Expr, typing: Type, dynamic: bool) -> String {
+        match (expr, typing) {
+            (Expr::Call(index, args), _) => {
+                let functions: Vec<_> = self.functions.iter()
+                    .filter(|(_, _, result)| dynamic && *result == Some(typing))
+                    .collect();
+                match functions.as_slice() {
+                    [] => self.expression(&Expr::Literal(index.index(u8::MAX as usize) as u8), typing, dynamic),
+                    functions => {
+                        let function: &&Declared = index.get(functions);
+                        self.call(function, args)
+                    }
+                }
+            },
+            (Expr::Literal(n), Type::Integer) => (n % 10).to_string(),
+            (Expr::Literal(n), Type::String) => format!("\"{}\"", ["a", "b c", "", "a"][*n as usize % 4]),
+            (Expr::Literal(n), Type::Boolean) => (n % 2 == 0).to_string(),
+            (Expr::Variable(index), _) => {
+                let variables: Vec<_> = self.variables.iter()
+                    .filter(|(name, other)| *other == typing && (dynamic || !name.starts_with('$')))
+                    .collect();
+                match variables.as_slice() {
+                    [] => self.expression(&Expr::Literal(index.index(u8::MAX as usize) as u8), typing, dynamic),
+                    variables => index.get(variables).0.clone()
+                }
+            },
+            (Expr::Sum(a, b) | Expr::Product(a, b), Type::Boolean) => self.expression(&Expr::Equality(Type::Integer, a.clone(), b.clone()), typing, dynamic),
+            (Expr::Product(a, b), Type::Integer) => format!("{} * {}", self.expression(a, typing, dynamic), self.expression(b, typing, dynamic)),
+            // Only static strings can be concatenated.
+            (Expr::Sum(a, b) | Expr::Product(a, b) | Expr::Equality(_, a, b), Type::String) => format!("{} + {}", self.expression(a, typing, false), self.expression(b, typing, false)),
+            (Expr::Sum(a, b) | Expr::Equality(_, a, b), Type::Integer) => format!("{} + {}", self.expression(a, typing, dynamic), self.expression(b, typing, dynamic)),
+            (Expr::Equality(other, a, b), Type::Boolean) => format!("({} == {})", self.expression(a, *other, dynamic), self.expression(b, *other, dynamic)),
+            (Expr::Parentheses(a), _) => format!("({})", self.expression(a, typing, dynamic))
+        }
+    }
+}
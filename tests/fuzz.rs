@@ -0,0 +1,256 @@
+//! Property tests of the front end: the parser and the generator must
+//! report malformed sources instead of panicking, and printing an AST with
+//! [tag_lang::parser::pretty] must give back a source parsing to it, which
+//! is already formatted.
+
+mod common;
+
+use proptest::prelude::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::strategy::Union;
+use tag_lang::{compile_sources, parse_str, CompileOptions, Source, FILE_EXTENSION};
+use tag_lang::parser::{Position, AST, MAX_NESTING};
+use tag_lang::parser::pretty::{print, format};
+use tag_lang::parser::statement::{Statement, IfStatement, Command, VariableAssignment, VariableSignature, Test, Assert};
+use tag_lang::parser::function::{Function, FunctionSignature, FunctionCall, Return};
+use tag_lang::parser::expression::{Expression, Summand, Term, VariableName};
+use tag_lang::parser::typing::Typing;
+use tag_lang::generator::staticness::IsStatic;
+
+/// Words that can't be used as identifiers.
+const KEYWORDS: &[&str] = &["true", "false", "def", "if", "else", "test", "assert", "return"];
+
+/// The pieces random token streams are made of.
+const TOKENS: &[&str] = &[
+    "def", "if", "else", "test", "assert", "return ", "true", "false", "trueish", "int", "string", "bool",
+    "$x", "$f", "x", "f", "g1", "$", "_", "'",
+    "0", "42", "99999999999", "\"a b\"", "\"", "\"\"",
+    ":=", ":", ";", ",", "+", "*", "==", "=", "(", ")", "{", "}", "#{", "#",
+    "/say ", "/", "/tellraw @a #{", "\n", " ", "\t"
+];
+
+/// Compiles `text`, for its errors and panics only.
+fn compile(text: &str) {
+    let sources = [Source { name: format!("fuzz{}", FILE_EXTENSION), text: text.into() }];
+    let options = CompileOptions { namespace: "fuzz".into(), tests: true, ..Default::default() };
+    let _ = compile_sources(&sources, options);
+}
+
+/// The debug output of `statements`, without the positions, which can't
+/// survive printing.
+fn shape(statements: &[Statement]) -> String {
+    common::replace_positions(&format!("{:?}", statements), |_| "Position".into())
+}
+
+fn pos() -> Position {
+    Position::default()
+}
+
+/// Mostly single letters, so that names often refer to something.
+fn identifier() -> impl Strategy<Value = String> {
+    let name = "[a-z][a-z0-9_]{0,4}".prop_filter("keywords aren't identifiers", |name| !KEYWORDS.contains(&name.as_str()));
+    prop_oneof![3 => "[abc]", 1 => name]
+}
+
+fn variable(dynamic: bool) -> BoxedStrategy<VariableName> {
+    if dynamic {
+        prop_oneof![
+            identifier().prop_map(VariableName::Dynamic),
+            identifier().prop_map(VariableName::Static)
+        ].boxed()
+    } else {
+        identifier().prop_map(VariableName::Static).boxed()
+    }
+}
+
+fn typing() -> impl Strategy<Value = Typing> {
+    prop_oneof![
+        Just(Typing::Unknown),
+        Just(Typing::Integer),
+        Just(Typing::String),
+        Just(Typing::Boolean)
+    ]
+}
+
+fn text() -> impl Strategy<Value = String> {
+    "[a-z @~]{0,6}"
+}
+
+fn function_call(dynamic: bool, args: BoxedStrategy<Expression>) -> impl Strategy<Value = FunctionCall> {
+    (variable(dynamic), vec(args, 0..3)).prop_map(|(name, args)| {
+        // Static functions can't be called with dynamic arguments.
+        let name = match name {
+            VariableName::Static(name) if args.iter().any(|arg| arg.is_dynamic()) =>
+                VariableName::Dynamic(name),
+            name => name
+        };
+        FunctionCall { name, args, position: pos() }
+    })
+}
+
+/// Expressions shaped the way the parser builds them: sums and products
+/// have at least two operands, a boolean can only end a sum, and
+/// comparisons don't chain.
+fn expression(dynamic: bool) -> BoxedStrategy<Expression> {
+    let leaf = prop_oneof![
+        (0..=i32::MAX).prop_map(|n| Expression::Summand(Summand::Term(Term::Number(n)), pos())),
+        text().prop_map(|str| Expression::Summand(Summand::Term(Term::String(str)), pos())),
+        variable(dynamic).prop_map(|var| Expression::Summand(Summand::Term(Term::Variable(var, pos())), pos())),
+        any::<bool>().prop_map(|value| Expression::Boolean(value, pos()))
+    ];
+
+    leaf.prop_recursive(4, 32, 3, move |inner| {
+        let term = prop_oneof![
+            (0..=i32::MAX).prop_map(Term::Number),
+            variable(dynamic).prop_map(|var| Term::Variable(var, pos())),
+            inner.clone().prop_map(|expr| Term::Expression(Box::new(expr))),
+            function_call(dynamic, inner.clone()).prop_map(Term::FunctionCall)
+        ];
+        let summand = vec(term, 1..4).prop_map(|mut terms| match terms.len() {
+            1 => Summand::Term(terms.pop().unwrap()),
+            _ => Summand::Multiplication(terms)
+        });
+        let sum = (vec(summand, 1..4), option::of(any::<bool>())).prop_map(|(summands, boolean)| {
+            let mut operands: Vec<_> = summands.into_iter().map(|summand| Expression::Summand(summand, pos())).collect();
+            if let Some(value) = boolean {
+                *operands.last_mut().unwrap() = Expression::Boolean(value, pos());
+            }
+            match operands.len() {
+                1 => operands.pop().unwrap(),
+                _ => Expression::Sum(operands, pos())
+            }
+        });
+
+        prop_oneof![
+            sum.clone(),
+            (sum.clone(), sum).prop_map(|(left, right)| Expression::Equality(Box::new(left), Box::new(right), pos()))
+        ]
+    }).boxed()
+}
+
+/// Statements, which are all static outside of dynamic blocks, as static
+/// functions (macros) can't hold anything else.
+fn statement(dynamic: bool, depth: u32) -> BoxedStrategy<Statement> {
+    let mut kinds = vec![
+        (vec((text(), expression(dynamic)), 0..3), text())
+            .prop_map(|(start, end)| Statement::Command(Command { start, end, position: pos() }))
+            .boxed(),
+        (variable(dynamic), typing(), expression(dynamic))
+            .prop_map(|(name, typing, value)| Statement::VariableAssignment(VariableAssignment {
+                signature: VariableSignature { name, typing }, value, position: pos()
+            }))
+            .boxed()
+    ];
+
+    if dynamic {
+        kinds.push(function_call(true, expression(true)).prop_map(Statement::FunctionCall).boxed());
+        kinds.push(expression(true).prop_map(|expr| Statement::Assert(Assert { expr, position: pos() })).boxed());
+    }
+
+    if depth > 0 {
+        kinds.push(if_statement(dynamic, depth - 1).prop_map(Statement::IfStatement).boxed());
+        kinds.push(function(dynamic, depth - 1).prop_map(Statement::FunctionDeclaration).boxed());
+        if dynamic {
+            kinds.push((text(), block(true, depth - 1))
+                .prop_map(|(name, block)| Statement::Test(Test { name, block, position: pos() }))
+                .boxed());
+        }
+    }
+
+    Union::new(kinds).boxed()
+}
+
+fn block(dynamic: bool, depth: u32) -> BoxedStrategy<Vec<Statement>> {
+    vec(statement(dynamic, depth), 0..4).boxed()
+}
+
+fn if_statement(dynamic: bool, depth: u32) -> BoxedStrategy<IfStatement> {
+    let else_if = if depth > 0 {
+        option::of(if_statement(dynamic, depth - 1)).boxed()
+    } else {
+        Just(None).boxed()
+    };
+
+    (expression(dynamic), block(dynamic, depth), else_if, option::of(block(dynamic, depth)))
+        .prop_map(|(expr, block, else_if, else_block)| IfStatement {
+            expr,
+            block,
+            // An `else if` can't be followed by another `else`.
+            else_block: if else_if.is_some() { None } else { else_block },
+            else_if: Box::new(else_if)
+        })
+        .boxed()
+}
+
+fn function(dynamic: bool, depth: u32) -> BoxedStrategy<Function> {
+    let signature = |dynamic| vec((variable(dynamic), typing()), 0..3)
+        .prop_map(|args| args.into_iter().map(|(name, typing)| VariableSignature { name, typing }).collect::<Vec<_>>());
+
+    let static_function = (identifier(), signature(false), block(false, depth))
+        .prop_map(|(name, args, block)| Function {
+            signature: FunctionSignature { name: VariableName::Static(name), args }, block, result: None, position: pos()
+        });
+
+    if dynamic {
+        let result = expression(true).prop_map(|expr| Return { expr, typing: Typing::Unknown, position: pos() });
+        let dynamic_function = (identifier(), signature(true), block(true, depth), option::of(result))
+            .prop_map(|(name, args, block, result)| Function {
+                signature: FunctionSignature { name: VariableName::Dynamic(name), args }, block, result, position: pos()
+            });
+        prop_oneof![static_function, dynamic_function].boxed()
+    } else {
+        static_function.boxed()
+    }
+}
+
+/// Compiles `text`, expecting `error` if it isn't `None`.
+fn expect(text: &str, error: Option<&str>) {
+    let sources = [Source { name: format!("limits{}", FILE_EXTENSION), text: text.into() }];
+    let result = compile_sources(&sources, CompileOptions { namespace: "limits".into(), ..Default::default() });
+    let errors: Vec<_> = result.err().unwrap_or_default().into_iter().map(|err| err.error).collect();
+    assert_eq!(errors.first().map(String::as_str), error, "{}", text);
+}
+
+#[test]
+fn test_limits() {
+    let nested = |depth: usize, open: &str, inner: &str, close: &str| open.repeat(depth) + inner + &close.repeat(depth);
+
+    expect(&nested(MAX_NESTING, "if true {\n", "/say hi\n", "}\n"), None);
+    expect(&nested(MAX_NESTING + 1, "if true {\n", "/say hi\n", "}\n"), Some("too deeply nested"));
+    expect(&format!("/say #{{{}}}\n", nested(MAX_NESTING, "(", "1", ")")), None);
+    expect(&format!("/say #{{{}}}\n", nested(10_000, "(", "1", ")")), Some("too deeply nested"));
+    // Siblings are as deep as their parent.
+    let deepest = nested(MAX_NESTING, "(", "1", ")");
+    expect(&format!("/say #{{{} + {}}}\n", deepest, deepest), None);
+    expect(&format!("/say #{{{}}}\n", vec!["1"; 10_000].join(" + ")), None);
+    expect(&format!("$x := 1;\n$y := {};\n", vec!["$x"; 10_000].join(" * ")), None);
+    expect("/say #{99999999999}\n", Some("integer literal is too large"));
+
+    expect("a := 1;\na := a + 1;\n/say #{a}\n", None);
+    let body = nested(MAX_NESTING - 2, "if n == 1 {\n", "$f(n);\n", "}\n");
+    expect(&format!("def $f(n) {{\n{}}}\n$f(1);\n", body), Some("too many nested macro expansions"));
+}
+
+proptest! {
+    #[test]
+    fn test_token_streams(tokens in vec(prop::sample::select(TOKENS), 0..40)) {
+        let text = tokens.concat();
+        if parse_str(&text).is_ok() {
+            compile(&text);
+        }
+    }
+
+    #[test]
+    fn test_round_trip(statements in block(true, 3)) {
+        let ast = AST { statements };
+        let text = print(&ast);
+        let reparsed = parse_str(&text).map_err(|err| TestCaseError::fail(format!(
+            "{}:{}: {}\n{}", err.position.line, err.position.column, err.error, text)))?;
+
+        prop_assert_eq!(shape(&reparsed.statements), shape(&ast.statements));
+        prop_assert_eq!(print(&reparsed), text.clone());
+        prop_assert_eq!(format(&reparsed, &text), text.clone());
+        compile(&text);
+    }
+}
@@ -0,0 +1,136 @@
+//! Drives `tag lsp` the way an editor would, over the stdio of the process:
+//! opening and editing a document, then asking for the definitions, hovers
+//! and completions of its names.
+
+use lsp_server::{Message, Notification, Request, RequestId, Response};
+use lsp_types::Url;
+use serde_json::{json, Value};
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+struct Client {
+    server: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i32
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_tag-lang"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = server.stdin.take().unwrap();
+        let stdout = BufReader::new(server.stdout.take().unwrap());
+        Client { server, stdin, stdout, next_id: 0 }
+    }
+
+    fn send(&mut self, message: Message) {
+        message.write(&mut self.stdin).unwrap();
+    }
+
+    fn receive(&mut self) -> Message {
+        Message::read(&mut self.stdout).unwrap().expect("the server closed its output")
+    }
+
+    /// Sends a request, giving the result of its response.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        self.send(Message::Request(Request::new(id.clone(), method.into(), params)));
+
+        loop {
+            if let Message::Response(Response { id: response_id, result, error }) = self.receive() {
+                assert_eq!(response_id, id);
+                assert!(error.is_none(), "{:?}", error);
+                return result.unwrap_or(Value::Null);
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::Notification(Notification::new(method.into(), params)));
+    }
+
+    /// Waits for the diagnostics of `uri`, giving their messages.
+    fn diagnostics(&mut self, uri: &Url) -> Vec<String> {
+        loop {
+            if let Message::Notification(notification) = self.receive() {
+                if notification.method == "textDocument/publishDiagnostics" && notification.params["uri"] == uri.as_str() {
+                    return notification.params["diagnostics"].as_array().unwrap().iter()
+                        .map(|diagnostic| diagnostic["message"].as_str().unwrap().to_string())
+                        .collect();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+    }
+}
+
+/// The protocol position of the `n`th character of `line` in `text`.
+fn at(text: &str, line: usize, n: usize) -> Value {
+    let line_text = text.lines().nth(line).unwrap();
+    json!({ "line": line, "character": line_text[..n].encode_utf16().count() })
+}
+
+#[test]
+fn test_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let uri = Url::from_file_path(dir.path().join("main.tag")).unwrap();
+    let position = |text: &str, line, n| json!({ "textDocument": { "uri": uri }, "position": at(text, line, n) });
+
+    let mut client = Client::start();
+    let capabilities = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(capabilities["capabilities"]["completionProvider"]["triggerCharacters"], json!(["$"]));
+    client.notify("initialized", json!({}));
+
+    let broken = "$count := 1;\n/say #{$cont}\n";
+    client.notify("textDocument/didOpen", json!({
+        "textDocument": { "uri": uri, "languageId": "tag", "version": 1, "text": broken }
+    }));
+    assert_eq!(client.diagnostics(&uri), ["unknown variable `$cont`\nhelp: did you mean `$count`?"]);
+
+    let text = "def $greet(name: string) {\n\t/say #{name}\n}\n\n$count := 1;\n$greet(\"a\");\n$total := $count + 1;\n";
+    client.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": text }]
+    }));
+    assert!(client.diagnostics(&uri).is_empty());
+
+    let definition = client.request("textDocument/definition", position(text, 6, 12));
+    assert_eq!(definition, json!([{ "uri": uri, "range": {
+        "start": { "line": 4, "character": 0 }, "end": { "line": 4, "character": 6 }
+    } }]));
+    let definition = client.request("textDocument/definition", position(text, 5, 2));
+    assert_eq!(definition[0]["range"]["start"], json!({ "line": 0, "character": 0 }));
+
+    let hover = client.request("textDocument/hover", position(text, 6, 12));
+    assert_eq!(hover["contents"]["value"], "```tag\n$count: int\n```");
+    let hover = client.request("textDocument/hover", position(text, 1, 9));
+    assert_eq!(hover["contents"]["value"], "```tag\nname: string\n```");
+    let hover = client.request("textDocument/hover", position(text, 5, 2));
+    assert_eq!(hover["contents"]["value"], "```tag\ndef $greet(name: string)\n```");
+
+    let completions = client.request("textDocument/completion", position(text, 6, 13));
+    let labels: Vec<&str> = completions.as_array().unwrap().iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"$count") && labels.contains(&"$greet"), "{:?}", labels);
+    assert!(!labels.contains(&"name") && !labels.contains(&"$total"), "{:?}", labels);
+    let edit = &completions[0]["textEdit"]["range"];
+    assert_eq!((&edit["start"]["character"], &edit["end"]["character"]), (&json!(10), &json!(13)));
+
+    client.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri } }));
+    assert!(client.diagnostics(&uri).is_empty());
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.server.wait().unwrap().success());
+}
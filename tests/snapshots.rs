@@ -0,0 +1,149 @@
+//! Golden files for the compiler: every `.tag` file of `examples/` and
+//! `tests/fixtures/` has a snapshot in `tests/snapshots/`, holding its AST,
+//! its diagnostics and the files of the datapack it compiles to.
+//!
+//! A snapshot differing from the output of the compiler fails the test.
+//! After checking that the new output is right, update the snapshots with:
+//!
+//! ```sh
+//! BLESS=1 cargo test --test snapshots
+//! ```
+
+mod common;
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tag_lang::{compile_sources, parse_str, source_name, CompileOptions, Source, FILE_EXTENSION};
+
+/// The directories holding the fixtures, relative to the root of the crate.
+const FIXTURE_DIRS: &[&str] = &["examples", "tests/fixtures"];
+
+const SNAPSHOT_DIR: &str = "tests/snapshots";
+const SNAPSHOT_EXTENSION: &str = "snap";
+
+/// Set to update the snapshots instead of checking them.
+const BLESS_VAR: &str = "BLESS";
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// The fixtures, with the path of their snapshot.
+fn fixtures() -> Vec<(PathBuf, PathBuf)> {
+    let mut fixtures = vec![];
+
+    for dir in FIXTURE_DIRS {
+        let snapshots = root().join(SNAPSHOT_DIR).join(Path::new(dir).file_name().unwrap());
+        for entry in std::fs::read_dir(root().join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.to_string_lossy().ends_with(FILE_EXTENSION) {
+                let snapshot = snapshots.join(path.file_name().unwrap()).with_extension(SNAPSHOT_EXTENSION);
+                fixtures.push((path, snapshot));
+            }
+        }
+    }
+
+    fixtures.sort();
+    fixtures
+}
+
+/// Compiles the fixture at `path` the way `tag compile` does, and renders
+/// each stage of it.
+fn snapshot(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    let namespace = path.file_stem().unwrap().to_string_lossy().to_string();
+    let text = std::fs::read_to_string(path).unwrap();
+    let mut snapshot = String::new();
+
+    writeln!(snapshot, "=== ast").unwrap();
+    match parse_str(&text) {
+        Ok(ast) => {
+            // Positions are kept on a single line, for the AST to stay readable.
+            let debug = format!("{:#?}", ast.statements);
+            let debug = common::replace_positions(&debug, |pos| format!("Position({}:{}:{})", pos.file, pos.line, pos.column));
+            writeln!(snapshot, "{}", debug).unwrap()
+        },
+        Err(err) => writeln!(snapshot, "{}:{}: {}", err.position.line, err.position.column, err.error).unwrap()
+    }
+
+    let sources = [Source { name, text }];
+    let options = CompileOptions { namespace, ..Default::default() };
+    writeln!(snapshot, "\n=== diagnostics").unwrap();
+    match compile_sources(&sources, options) {
+        Ok(datapack) => {
+            for (path, content) in datapack.files() {
+                writeln!(snapshot, "\n=== {}", path.to_string_lossy().replace('\\', "/")).unwrap();
+                writeln!(snapshot, "{}", String::from_utf8_lossy(&content)).unwrap();
+            }
+        },
+        Err(errors) => {
+            for err in errors {
+                let file = source_name(&sources, err.position.file);
+                writeln!(snapshot, "{}:{}:{}: {}", file, err.position.line, err.position.column, err.error).unwrap();
+                for note in &err.notes {
+                    writeln!(snapshot, "  = {}", note).unwrap();
+                }
+            }
+        }
+    }
+
+    snapshot
+}
+
+/// The first line differing between `expected` and `actual`.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(a), Some(b)) if a == b => continue,
+            (None, None) => break,
+            (a, b) => return format!("line {}:\n    expected: {}\n    actual:   {}",
+                                     line, a.unwrap_or("<end>"), b.unwrap_or("<end>"))
+        }
+    }
+    "line endings differ".to_string()
+}
+
+#[test]
+fn test_snapshots() {
+    let bless = std::env::var_os(BLESS_VAR).is_some();
+    let fixtures = fixtures();
+    let mut failures = vec![];
+
+    for (fixture, path) in &fixtures {
+        let actual = snapshot(fixture);
+        let relative = path.strip_prefix(root()).unwrap().display();
+
+        if bless {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, &actual).unwrap();
+            continue;
+        }
+
+        match std::fs::read_to_string(path) {
+            Ok(expected) if expected == actual => {},
+            Ok(expected) => failures.push(format!("{} differs at {}", relative, first_difference(&expected, &actual))),
+            Err(_) => failures.push(format!("{} is missing", relative))
+        }
+    }
+
+    // Snapshots left behind by a fixture that was renamed or removed.
+    for dir in FIXTURE_DIRS {
+        let snapshots = root().join(SNAPSHOT_DIR).join(Path::new(dir).file_name().unwrap());
+        for entry in std::fs::read_dir(&snapshots).into_iter().flatten() {
+            let path = entry.unwrap().path();
+            if fixtures.iter().all(|(_, snapshot)| *snapshot != path) {
+                if bless {
+                    std::fs::remove_file(&path).unwrap();
+                } else {
+                    failures.push(format!("{} has no fixture", path.strip_prefix(root()).unwrap().display()));
+                }
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}\n\nrun `{}=1 cargo test --test snapshots` to update the snapshots",
+            failures.join("\n"), BLESS_VAR);
+}